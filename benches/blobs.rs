@@ -0,0 +1,77 @@
+//! Benchmarks of writing a file as a blob: streamed by `write_blob_from_file`, as add does,
+//! against reading it whole into a `GitObject` first.
+//!
+//! Run with `cargo bench --bench blobs`.
+
+#![feature(test)]
+
+extern crate test;
+
+use gitlet::objects::{Fmt, GitObject};
+use gitlet::repository::Repository;
+use std::fs;
+use std::path::PathBuf;
+use test::Bencher;
+
+/// the size of the file written, large enough for the buffering to matter
+const SIZE: usize = 1 << 20;
+
+/// create an empty directory for a benchmark, removing leftovers from previous runs
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("gitlet-bench-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir.canonicalize().unwrap()
+}
+
+/// a repository and a file of pseudo-random bytes in its work tree, which compress
+/// about as badly as real binaries
+fn blob_repo(name: &str) -> (Repository, PathBuf) {
+    let repo = Repository::init(temp_dir(name)).unwrap();
+
+    let mut state: u64 = 1;
+    let data: Vec<u8> = (0..SIZE)
+        .map(|_| {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1);
+            (state >> 56) as u8
+        })
+        .collect();
+
+    let path = repo.work_tree.join("data.bin");
+    fs::write(&path, data).unwrap();
+
+    (repo, path)
+}
+
+/// remove a written object, so the next iteration writes it again
+fn remove_object(repo: &Repository, sha: &str) {
+    fs::remove_file(repo.git_dir.join("objects").join(&sha[..2]).join(&sha[2..])).unwrap();
+}
+
+#[bench]
+fn bench_write_blob_streamed(b: &mut Bencher) {
+    let (repo, path) = blob_repo("blob-streamed");
+
+    // both ways write the same object
+    let object = GitObject::from_file(&path, Fmt::Blob).unwrap();
+    assert_eq!(
+        repo.write_blob_from_file(&path).unwrap(),
+        object.sha().unwrap()
+    );
+
+    b.iter(|| {
+        let sha = repo.write_blob_from_file(&path).unwrap();
+        remove_object(&repo, &sha);
+    });
+}
+
+#[bench]
+fn bench_write_blob_buffered(b: &mut Bencher) {
+    let (repo, path) = blob_repo("blob-buffered");
+
+    b.iter(|| {
+        let object = GitObject::from_file(&path, Fmt::Blob).unwrap();
+        let sha = repo.write_object(&object).unwrap();
+        remove_object(&repo, &sha);
+    });
+}
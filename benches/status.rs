@@ -0,0 +1,57 @@
+//! Benchmarks of `status` over a work tree with thousands of untracked files.
+//!
+//! Run with `cargo bench --bench status`.
+
+#![feature(test)]
+
+extern crate test;
+
+use gitlet::repository::Repository;
+use std::fs;
+use std::path::PathBuf;
+use test::Bencher;
+
+/// create an empty directory for a benchmark, removing leftovers from previous runs
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("gitlet-bench-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir.canonicalize().unwrap()
+}
+
+/// a work tree of 5000 untracked files in 50 directories, with a 50 rules `.gitignore`
+/// matching none of them, so every rule is checked against every path
+fn untracked_repo(name: &str) -> Repository {
+    let repo = Repository::init(temp_dir(name)).unwrap();
+
+    let rules: String = (0..25)
+        .map(|i| format!("*.ignored{}\nbuild{}/\n", i, i))
+        .collect();
+    fs::write(repo.work_tree.join(".gitignore"), rules).unwrap();
+
+    for dir in 0..50 {
+        let dir = repo.work_tree.join(format!("dir{}", dir));
+        fs::create_dir(&dir).unwrap();
+
+        for file in 0..100 {
+            fs::write(dir.join(format!("file{}.txt", file)), "untracked\n").unwrap();
+        }
+    }
+
+    repo
+}
+
+#[bench]
+fn bench_status_untracked(b: &mut Bencher) {
+    let repo = untracked_repo("status-untracked");
+    assert_eq!(repo.status().unwrap().untracked.len(), 5001);
+
+    b.iter(|| repo.status().unwrap());
+}
+
+#[bench]
+fn bench_untracked_files(b: &mut Bencher) {
+    let repo = untracked_repo("untracked-files");
+
+    b.iter(|| repo.untracked_files(false).unwrap());
+}
@@ -0,0 +1,103 @@
+//! Path attributes, read from `.gitattributes` files.
+//!
+//! Each line is a pattern followed by attributes: `attr` sets it, `-attr` unsets it,
+//! `attr=value` gives it a value and `!attr` makes it unspecified again. When several
+//! lines match a path, the last one mentioning an attribute wins.
+
+/// the state of an attribute for a path
+#[derive(Debug, Clone, PartialEq)]
+pub enum Attr {
+    Set,
+    Unset,
+    Value(String),
+}
+
+/// a pattern with its attributes, None for the unspecified ones
+type Rule = (String, Vec<(String, Option<Attr>)>);
+
+#[derive(Debug, Default, PartialEq)]
+pub struct GitAttributes {
+    rules: Vec<Rule>,
+}
+
+impl GitAttributes {
+    /// parse the content of an attributes file, its rules take precedence over the existing ones
+    pub fn extend(&mut self, data: &str) {
+        for line in data.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut fields = line.split_whitespace();
+            // unwrap is safe because the line is not empty
+            let pattern = fields.next().unwrap().to_string();
+
+            let attrs = fields
+                .map(|field| {
+                    if let Some(name) = field.strip_prefix('-') {
+                        (name.to_string(), Some(Attr::Unset))
+                    } else if let Some(name) = field.strip_prefix('!') {
+                        (name.to_string(), None)
+                    } else if let Some((name, value)) = field.split_once('=') {
+                        (name.to_string(), Some(Attr::Value(value.to_string())))
+                    } else {
+                        (field.to_string(), Some(Attr::Set))
+                    }
+                })
+                .collect();
+
+            self.rules.push((pattern, attrs));
+        }
+    }
+
+    /// the state of an attribute for a repo-relative path, None when unspecified
+    pub fn get(&self, path: &str, name: &str) -> Option<Attr> {
+        for (pattern, attrs) in self.rules.iter().rev() {
+            if !Self::matches(pattern, path) {
+                continue;
+            }
+
+            if let Some((_, attr)) = attrs.iter().rev().find(|(n, _)| n == name) {
+                return attr.clone();
+            }
+        }
+
+        None
+    }
+
+    /// a pattern without a slash matches the file name at any depth, as in `.gitignore`
+    fn matches(pattern: &str, path: &str) -> bool {
+        let Ok(glob) = glob::Pattern::new(pattern.trim_start_matches('/')) else {
+            return false;
+        };
+
+        if pattern.contains('/') {
+            glob.matches(path)
+        } else {
+            glob.matches(path.rsplit('/').next().unwrap_or(path))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_attributes() {
+        let mut attributes = GitAttributes::default();
+        attributes.extend("# comment\n*.bin filter=lfs -text\ndocs/*.bin !filter\n");
+        attributes.extend("*.txt text\n");
+
+        assert_eq!(
+            attributes.get("a/b.bin", "filter"),
+            Some(Attr::Value("lfs".to_string()))
+        );
+        assert_eq!(attributes.get("a/b.bin", "text"), Some(Attr::Unset));
+        assert_eq!(attributes.get("docs/b.bin", "filter"), None);
+        assert_eq!(attributes.get("docs/b.bin", "text"), Some(Attr::Unset));
+        assert_eq!(attributes.get("a.txt", "text"), Some(Attr::Set));
+        assert_eq!(attributes.get("a.txt", "filter"), None);
+    }
+}
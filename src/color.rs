@@ -0,0 +1,127 @@
+//! ANSI colors for the output of commands.
+//!
+//! Color is decided by `color.ui`: `always`, `never`, or `auto`, the default, which
+//! colors only a terminal. `color.diff` overrides it for patches. A non-empty `NO_COLOR` environment variable turns `auto` off.
+
+use std::io::IsTerminal;
+
+pub const GREEN: &str = "\x1b[32m";
+pub const RED: &str = "\x1b[31m";
+pub const CYAN: &str = "\x1b[36m";
+const RESET: &str = "\x1b[m";
+
+/// whether to color the output, given `color.ui`, whether the output is a terminal and
+/// whether `NO_COLOR` is set
+pub fn enabled(ui: Option<&str>, is_terminal: bool, no_color: bool) -> anyhow::Result<bool> {
+    Ok(match ui.unwrap_or("auto") {
+        "always" => true,
+        "never" | "false" => false,
+        "auto" | "true" => is_terminal && !no_color,
+        ui => anyhow::bail!("invalid color.ui value: {}", ui),
+    })
+}
+
+/// whether to color what is printed to stdout, given `color.ui`
+pub fn stdout_enabled(ui: Option<&str>) -> anyhow::Result<bool> {
+    let no_color = std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+
+    enabled(ui, std::io::stdout().is_terminal(), no_color)
+}
+
+/// wrap text in a color, or leave it as is when color is off
+pub fn paint(text: &str, color: &str, enabled: bool) -> String {
+    if enabled {
+        format!("{}{}{}", color, text, RESET)
+    } else {
+        text.to_string()
+    }
+}
+
+/// color a patch as git does: the hunk headers cyan, the added lines green and the
+/// deleted lines red, the file headers are left alone
+pub fn paint_diff(patch: &str, enabled: bool) -> String {
+    if !enabled {
+        return patch.to_string();
+    }
+
+    let mut out = String::new();
+    // `--- a/path` and `+++ b/path` are headers, a line starting with - or + is only a
+    // change inside a hunk
+    let mut in_hunk = false;
+
+    for line in patch.split_inclusive('\n') {
+        let (text, newline) = match line.strip_suffix('\n') {
+            Some(text) => (text, "\n"),
+            None => (line, ""),
+        };
+
+        let color = if text.starts_with("diff --git ") {
+            in_hunk = false;
+            None
+        } else if text.starts_with("@@ ") {
+            in_hunk = true;
+            Some(CYAN)
+        } else if in_hunk && text.starts_with('+') {
+            Some(GREEN)
+        } else if in_hunk && text.starts_with('-') {
+            Some(RED)
+        } else {
+            None
+        };
+
+        match color {
+            Some(color) => out.push_str(&paint(text, color, true)),
+            None => out.push_str(text),
+        }
+        out.push_str(newline);
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_color() {
+        assert!(enabled(None, true, false).unwrap());
+        assert!(!enabled(None, false, false).unwrap());
+        assert!(!enabled(Some("auto"), true, true).unwrap());
+        assert!(enabled(Some("always"), false, true).unwrap());
+        assert!(!enabled(Some("never"), true, false).unwrap());
+        assert!(enabled(Some("bogus"), true, false).is_err());
+
+        assert_eq!(paint("  added: a.txt", GREEN, false), "  added: a.txt");
+        assert!(!paint("  added: a.txt", GREEN, false).contains('\x1b'));
+        assert_eq!(
+            paint("  added: a.txt", GREEN, true),
+            "\x1b[32m  added: a.txt\x1b[m"
+        );
+    }
+
+    #[test]
+    fn test_paint_diff() {
+        let patch = "diff --git a/a.txt b/a.txt\n\
+                     index 0000001..0000002 100644\n\
+                     --- a/a.txt\n\
+                     +++ b/a.txt\n\
+                     @@ -1,2 +1,2 @@\n\
+                     \x20kept\n\
+                     --- removed\n\
+                     +added\n";
+
+        assert_eq!(paint_diff(patch, false), patch);
+        assert_eq!(
+            paint_diff(patch, true),
+            "diff --git a/a.txt b/a.txt\n\
+             index 0000001..0000002 100644\n\
+             --- a/a.txt\n\
+             +++ b/a.txt\n\
+             \x1b[36m@@ -1,2 +1,2 @@\x1b[m\n\
+             \x20kept\n\
+             \x1b[31m--- removed\x1b[m\n\
+             \x1b[32m+added\x1b[m\n"
+        );
+    }
+}
@@ -0,0 +1,398 @@
+//! Line diffs, printed as unified diffs.
+//!
+//! Lines are matched with Myers' algorithm, which finds a shortest edit script: the fewest
+//! deleted and inserted lines turning one sequence into the other.
+
+use std::ops::Range;
+
+/// one step of an edit script, with the indices of the lines it concerns
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Edit {
+    /// line a[.0] is kept as b[.1]
+    Equal(usize, usize),
+    /// line a[.0] is deleted
+    Delete(usize),
+    /// line b[.0] is inserted
+    Insert(usize),
+}
+
+/// a shortest edit script turning a into b, in order
+///
+/// this is the linear space variant: the middle of a shortest path is found by
+/// searching from both ends at once, then the parts before and after it are diffed in
+/// turn, so memory stays proportional to the lengths of a and b
+pub fn diff_lines<T: PartialEq>(a: &[T], b: &[T]) -> Vec<Edit> {
+    let max = (a.len() + b.len()).div_ceil(2) + 1;
+    let mut forward = Diagonals::new(max);
+    let mut backward = Diagonals::new(max);
+
+    let mut edits = vec![];
+    diff_range(a, b, (0, 0), &mut forward, &mut backward, &mut edits);
+
+    // the halves may interleave the changes between two kept lines, git prints the
+    // deleted lines first
+    for changes in edits.split_mut(|edit| matches!(edit, Edit::Equal(..))) {
+        changes.sort_by_key(|edit| matches!(edit, Edit::Insert(_)));
+    }
+
+    edits
+}
+
+/// the furthest x reached on each diagonal k = x - y, for k from -max to max
+struct Diagonals {
+    v: Vec<usize>,
+    max: isize,
+}
+
+impl Diagonals {
+    fn new(max: usize) -> Self {
+        Self {
+            v: vec![0; 2 * max + 1],
+            max: max as isize,
+        }
+    }
+}
+
+impl std::ops::Index<isize> for Diagonals {
+    type Output = usize;
+
+    fn index(&self, k: isize) -> &usize {
+        &self.v[(k + self.max) as usize]
+    }
+}
+
+impl std::ops::IndexMut<isize> for Diagonals {
+    fn index_mut(&mut self, k: isize) -> &mut usize {
+        &mut self.v[(k + self.max) as usize]
+    }
+}
+
+/// push the edits turning a into b, whose first lines are at `start` in the whole
+/// sequences
+fn diff_range<T: PartialEq>(
+    a: &[T],
+    b: &[T],
+    start: (usize, usize),
+    forward: &mut Diagonals,
+    backward: &mut Diagonals,
+    edits: &mut Vec<Edit>,
+) {
+    let (x, y) = start;
+
+    let prefix = a.iter().zip(b).take_while(|(a, b)| a == b).count();
+    let (a, b) = (&a[prefix..], &b[prefix..]);
+    let suffix = a
+        .iter()
+        .rev()
+        .zip(b.iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let (a, b) = (&a[..a.len() - suffix], &b[..b.len() - suffix]);
+
+    edits.extend((0..prefix).map(|i| Edit::Equal(x + i, y + i)));
+    let (x, y) = (x + prefix, y + prefix);
+
+    if a.is_empty() || b.is_empty() {
+        edits.extend((0..a.len()).map(|i| Edit::Delete(x + i)));
+        edits.extend((0..b.len()).map(|j| Edit::Insert(y + j)));
+    } else {
+        let (mid_x, mid_y) = middle_snake(a, b, forward, backward);
+
+        diff_range(&a[..mid_x], &b[..mid_y], (x, y), forward, backward, edits);
+        diff_range(
+            &a[mid_x..],
+            &b[mid_y..],
+            (x + mid_x, y + mid_y),
+            forward,
+            backward,
+            edits,
+        );
+    }
+
+    let (x, y) = (x + a.len(), y + b.len());
+    edits.extend((0..suffix).map(|i| Edit::Equal(x + i, y + i)));
+}
+
+/// a point (x, y) on a shortest path from the start of a and b to their end, which is
+/// neither, for a and b which differ in their first and last lines
+///
+/// paths are extended by one edit at a time from the start and from the end, until
+/// the two meet
+fn middle_snake<T: PartialEq>(
+    a: &[T],
+    b: &[T],
+    forward: &mut Diagonals,
+    backward: &mut Diagonals,
+) -> (usize, usize) {
+    let (n, m) = (a.len(), b.len());
+    // the forward diagonal k is the backward diagonal delta - k
+    let delta = n as isize - m as isize;
+    let odd = delta % 2 != 0;
+
+    forward[1] = 0;
+    backward[1] = 0;
+
+    for d in 0..=(n + m).div_ceil(2) as isize {
+        for k in (-d..=d).step_by(2) {
+            let mut x = if k == -d || (k != d && forward[k - 1] < forward[k + 1]) {
+                forward[k + 1]
+            } else {
+                forward[k - 1] + 1
+            };
+            let (start_x, start_y) = (x, (x as isize - k) as usize);
+
+            let mut y = start_y;
+            while x < n && y < m && a[x] == b[y] {
+                x += 1;
+                y += 1;
+            }
+            forward[k] = x;
+
+            if odd && (k - delta).abs() < d && x + backward[delta - k] >= n {
+                return (start_x, start_y);
+            }
+        }
+
+        // x and y count lines from the ends of a and b
+        for k in (-d..=d).step_by(2) {
+            let mut x = if k == -d || (k != d && backward[k - 1] < backward[k + 1]) {
+                backward[k + 1]
+            } else {
+                backward[k - 1] + 1
+            };
+
+            let mut y = (x as isize - k) as usize;
+            while x < n && y < m && a[n - x - 1] == b[m - y - 1] {
+                x += 1;
+                y += 1;
+            }
+            backward[k] = x;
+
+            if !odd && (k - delta).abs() <= d && x + forward[delta - k] >= n {
+                return (n - x, m - y);
+            }
+        }
+    }
+
+    unreachable!("the paths from both ends always meet")
+}
+
+/// a hunk of a unified diff, see [diff_hunks]
+#[derive(Debug, PartialEq)]
+pub struct Hunk {
+    /// the lines of a the hunk covers, context included
+    pub old: Range<usize>,
+    /// the lines of b replacing them
+    pub new: Range<usize>,
+    /// the hunk as printed, its `@@` header and its lines
+    pub text: String,
+}
+
+/// the hunks of a unified diff from a to b, with `context` unchanged lines around changes
+///
+/// returns an empty string when a and b have the same lines
+pub fn unified_diff(a: &[u8], b: &[u8], context: usize) -> String {
+    diff_hunks(a, b, context)
+        .into_iter()
+        .map(|hunk| hunk.text)
+        .collect()
+}
+
+/// the hunks of the diff from a to b, as [unified_diff] prints them
+pub fn diff_hunks(a: &[u8], b: &[u8], context: usize) -> Vec<Hunk> {
+    let a = split_lines(a);
+    let b = split_lines(b);
+    let edits = diff_lines(&a, &b);
+
+    // the ranges of edits to print, changes with the context around them, merged when
+    // they touch
+    let mut ranges: Vec<(usize, usize)> = vec![];
+    for (i, edit) in edits.iter().enumerate() {
+        if matches!(edit, Edit::Equal(..)) {
+            continue;
+        }
+
+        let (start, end) = (
+            i.saturating_sub(context),
+            (i + context + 1).min(edits.len()),
+        );
+        match ranges.last_mut() {
+            Some(last) if last.1 >= start => last.1 = end,
+            _ => ranges.push((start, end)),
+        }
+    }
+
+    // the lines of a and b before each edit
+    let mut positions = vec![];
+    let (mut x, mut y) = (0, 0);
+    for edit in &edits {
+        positions.push((x, y));
+        match edit {
+            Edit::Equal(..) => (x, y) = (x + 1, y + 1),
+            Edit::Delete(_) => x += 1,
+            Edit::Insert(_) => y += 1,
+        }
+    }
+
+    let mut hunks = vec![];
+
+    for (start, end) in ranges {
+        let edits = &edits[start..end];
+        let (old_start, new_start) = positions[start];
+        let old_len = edits
+            .iter()
+            .filter(|e| !matches!(e, Edit::Insert(_)))
+            .count();
+        let new_len = edits
+            .iter()
+            .filter(|e| !matches!(e, Edit::Delete(_)))
+            .count();
+
+        let mut text = format!(
+            "@@ -{} +{} @@\n",
+            hunk_range(old_start, old_len),
+            hunk_range(new_start, new_len)
+        );
+
+        for edit in edits {
+            let (prefix, line) = match *edit {
+                Edit::Equal(i, _) => (' ', a[i]),
+                Edit::Delete(i) => ('-', a[i]),
+                Edit::Insert(j) => ('+', b[j]),
+            };
+
+            text.push(prefix);
+            text.push_str(&String::from_utf8_lossy(line));
+            if !line.ends_with(b"\n") {
+                text.push_str("\n\\ No newline at end of file\n");
+            }
+        }
+
+        hunks.push(Hunk {
+            old: old_start..old_start + old_len,
+            new: new_start..new_start + new_len,
+            text,
+        });
+    }
+
+    hunks
+}
+
+/// a with only some hunks of its diff to b applied, the hunks in the order of [diff_hunks]
+pub fn apply_hunks<'a>(a: &[u8], b: &[u8], hunks: impl IntoIterator<Item = &'a Hunk>) -> Vec<u8> {
+    let a = split_lines(a);
+    let b = split_lines(b);
+
+    let mut out = vec![];
+    let mut next = 0;
+
+    // the lines of a between hunks are kept, those a hunk covers are replaced by its own
+    for hunk in hunks {
+        out.extend(a[next..hunk.old.start].concat());
+        out.extend(b[hunk.new.clone()].concat());
+        next = hunk.old.end;
+    }
+    out.extend(a[next..].concat());
+
+    out
+}
+
+/// the lines of data, each with its newline
+fn split_lines(data: &[u8]) -> Vec<&[u8]> {
+    data.split_inclusive(|c| *c == b'\n').collect()
+}
+
+/// `start,len` of a hunk header, 1-based, a single line has no length and an empty range
+/// starts at the line before it
+fn hunk_range(start: usize, len: usize) -> String {
+    match len {
+        0 => format!("{},0", start),
+        1 => format!("{}", start + 1),
+        _ => format!("{},{}", start + 1, len),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff_lines() {
+        let a: Vec<char> = "abcabba".chars().collect();
+        let b: Vec<char> = "cbabac".chars().collect();
+        let edits = diff_lines(&a, &b);
+
+        // the shortest script has 5 edits, the rest is kept
+        let changes = edits
+            .iter()
+            .filter(|e| !matches!(e, Edit::Equal(..)))
+            .count();
+        assert_eq!(changes, 5);
+
+        // replaying the script rebuilds b
+        let rebuilt: Vec<char> = edits
+            .iter()
+            .filter_map(|e| match *e {
+                Edit::Equal(i, _) => Some(a[i]),
+                Edit::Insert(j) => Some(b[j]),
+                Edit::Delete(_) => None,
+            })
+            .collect();
+        assert_eq!(rebuilt, b);
+
+        assert!(diff_lines::<char>(&[], &[]).is_empty());
+        assert_eq!(diff_lines(&[], &['x']), vec![Edit::Insert(0)]);
+    }
+
+    #[test]
+    fn test_diff_lines_rewrite() {
+        let a: Vec<usize> = (0..2000).collect();
+        let b: Vec<usize> = (2000..4000).collect();
+        let edits = diff_lines(&a, &b);
+
+        // every line is replaced, the deletions come first
+        let expected: Vec<Edit> = (0..2000)
+            .map(Edit::Delete)
+            .chain((0..2000).map(Edit::Insert))
+            .collect();
+        assert_eq!(edits, expected);
+    }
+
+    #[test]
+    fn test_unified_diff() {
+        let a = b"1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n11\n12\n";
+        let b = b"1\n2\n3\nfour\n5\n6\n7\n8\n9\n10\n11\n12\n13";
+
+        // as printed by git diff
+        assert_eq!(
+            unified_diff(a, b, 3),
+            "@@ -1,7 +1,7 @@\n 1\n 2\n 3\n-4\n+four\n 5\n 6\n 7\n\
+             @@ -10,3 +10,4 @@\n 10\n 11\n 12\n+13\n\\ No newline at end of file\n"
+        );
+        // with 6 unchanged lines or less between them, changes share a hunk
+        assert_eq!(
+            unified_diff(&a[..21], &b[..26], 3).matches("@@ -").count(),
+            1
+        );
+        assert_eq!(unified_diff(b"", b"new\n", 3), "@@ -0,0 +1 @@\n+new\n");
+        assert_eq!(unified_diff(a, a, 3), "");
+    }
+
+    #[test]
+    fn test_apply_hunks() {
+        let a = b"1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n";
+        let b = b"one\n2\n3\n4\n5\n6\n7\n8\n9\nten\n";
+
+        let hunks = diff_hunks(a, b, 3);
+        assert_eq!(hunks.len(), 2);
+        assert_eq!((hunks[0].old.clone(), hunks[0].new.clone()), (0..4, 0..4));
+        assert!(hunks[1].text.starts_with("@@ -7,4 +7,4 @@\n"));
+
+        assert_eq!(apply_hunks(a, b, &hunks), b);
+        assert_eq!(apply_hunks(a, b, []), a);
+        assert_eq!(
+            apply_hunks(a, b, [&hunks[1]]),
+            b"1\n2\n3\n4\n5\n6\n7\n8\n9\nten\n"
+        );
+    }
+}
@@ -1,17 +1,103 @@
-use anyhow::{ensure, Context};
+use anyhow::ensure;
 use indexmap::IndexMap;
 use std::path::{Path, PathBuf};
 
 #[derive(Debug, PartialEq)]
 pub struct GitIgnore {
-    pub global: Vec<Vec<Rule>>,
+    /// the rules outside of the work tree, by precedence, with the file they come from
+    pub global: Vec<(String, Vec<Rule>)>,
+    /// the rules of the `.gitignore` files, by directory
     pub local: IndexMap<String, Vec<Rule>>,
 }
 
+/// the rule deciding whether a path is ignored, see [GitIgnore::check]
 #[derive(Debug, PartialEq)]
-pub enum Rule {
-    Negation(String),
-    Pattern(String),
+pub struct MatchResult {
+    /// the file of the rule, such as `.gitignore` or `sub/.gitignore`
+    pub source: String,
+    /// the line of the rule in its file, from 1
+    pub line: usize,
+    /// the pattern as written
+    pub pattern: String,
+    /// whether the path is ignored, false when the rule is a negation
+    pub ignored: bool,
+}
+
+/// an ignore rule, its pattern is compiled once when the rules are parsed
+///
+/// patterns follow gitignore(5): a pattern with a slash before its end is anchored to the
+/// directory of its file, otherwise it matches a name at any depth; a trailing slash
+/// matches directories only; `**` matches any number of directories
+#[derive(Debug)]
+pub struct Rule {
+    /// the pattern as written in its file
+    pub pattern: String,
+    /// the line of the pattern in its file, from 1
+    pub line: usize,
+    /// the pattern starts with `!`, a matching path is not ignored
+    pub negation: bool,
+    /// the pattern ends with `/`, it matches directories only
+    pub dir_only: bool,
+    regex: regex::Regex,
+}
+
+impl PartialEq for Rule {
+    fn eq(&self, other: &Self) -> bool {
+        self.pattern == other.pattern && self.line == other.line
+    }
+}
+
+impl Rule {
+    /// parse a line of an ignore file, None for blank lines, comments and invalid patterns
+    fn parse(line: &str, number: usize) -> Option<Self> {
+        let line = trim_trailing_spaces(line);
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+
+        let (negation, pattern) = match line.strip_prefix('!') {
+            Some(pattern) => (true, pattern),
+            None => (false, line),
+        };
+
+        let (dir_only, pattern) = match pattern.strip_suffix('/') {
+            Some(pattern) => (true, pattern),
+            None => (false, pattern),
+        };
+
+        // a slash at the start or in the middle anchors the pattern, otherwise it matches
+        // the name of a file or directory at any depth
+        let regex = if pattern.contains('/') {
+            format!(
+                "^{}$",
+                glob_to_regex(pattern.strip_prefix('/').unwrap_or(pattern))?
+            )
+        } else {
+            format!("^(?:.*/)?{}$", glob_to_regex(pattern)?)
+        };
+
+        Some(Self {
+            pattern: line.to_string(),
+            line: number,
+            negation,
+            dir_only,
+            regex: regex::Regex::new(&regex).ok()?,
+        })
+    }
+
+    /// whether a path, relative to the directory of the rule's file, matches the rule
+    pub fn matches(&self, path: &str, is_dir: bool) -> bool {
+        (is_dir || !self.dir_only) && self.regex.is_match(path)
+    }
+
+    fn result(&self, source: String) -> MatchResult {
+        MatchResult {
+            source,
+            line: self.line,
+            pattern: self.pattern.clone(),
+            ignored: !self.negation,
+        }
+    }
 }
 
 impl Default for GitIgnore {
@@ -24,99 +110,77 @@ impl Default for GitIgnore {
 }
 
 impl GitIgnore {
-    // todo do not clone the string
+    /// parse the lines of an ignore file, invalid patterns are skipped
     pub fn parse(lines: &str) -> Vec<Rule> {
         lines
-            .trim()
-            .split('\n')
-            .filter_map(|line| {
-                let line = line.trim();
-                if line.is_empty() || line.starts_with('#') {
-                    None
-                } else {
-                    match line.chars().next() {
-                        Some('!') => {
-                            let pattern = &line[1..];
-
-                            if Path::new(pattern).is_dir() {
-                                Some(Rule::Negation(format!("{}/**", pattern)))
-                            } else {
-                                Some(Rule::Negation(pattern.to_string()))
-                            }
-                        }
-                        Some('\\') | Some('/') => {
-                            let pattern = &line[1..];
-
-                            if Path::new(pattern).is_dir() {
-                                Some(Rule::Pattern(format!("{}/**", pattern)))
-                            } else {
-                                Some(Rule::Pattern(pattern.to_string()))
-                            }
-                        }
-                        _ => {
-                            let pattern = line;
-
-                            if Path::new(pattern).is_dir() {
-                                Some(Rule::Pattern(format!("{}/**", pattern)))
-                            } else {
-                                Some(Rule::Pattern(pattern.to_string()))
-                            }
-                        }
-                    }
-                }
-            })
-            .collect::<Vec<_>>()
+            .lines()
+            .enumerate()
+            .filter_map(|(i, line)| Rule::parse(line, i + 1))
+            .collect()
     }
 
-    fn check_rules(rules: &Vec<Rule>, path: &str) -> Option<bool> {
-        for rule in rules {
-            match rule {
-                Rule::Negation(pattern) => {
-                    let glob = glob::Pattern::new(pattern)
-                        .context("invalid glob pattern")
-                        .ok()?;
-
-                    if glob.matches(path) {
-                        return Some(false);
-                    }
-                }
-                Rule::Pattern(pattern) => {
-                    let glob = glob::Pattern::new(pattern).ok()?;
-
-                    if glob.matches(path) {
-                        return Some(true);
-                    }
-                }
-            }
-        }
-
-        None
+    /// the last rule matching the path decides, as in git
+    fn check_rules<'a>(rules: &'a [Rule], path: &str, is_dir: bool) -> Option<&'a Rule> {
+        rules.iter().rev().find(|rule| rule.matches(path, is_dir))
     }
 
-    pub fn check(&self, path: &str) -> anyhow::Result<Option<bool>> {
-        let pathbuf = PathBuf::from(path);
+    /// whether a path is ignored, see [Self::check]
+    pub fn is_ignored(&self, path: &str) -> anyhow::Result<bool> {
+        Ok(self.check(path)?.is_some_and(|result| result.ignored))
+    }
 
+    /// the rule deciding whether a path is ignored, None when no rule matches it
+    ///
+    /// a path ending with `/` is a directory. A path inside an ignored directory is
+    /// ignored, whatever the rules say about the path itself, as git does not look into
+    /// ignored directories: the rule matching the directory is returned.
+    pub fn check(&self, path: &str) -> anyhow::Result<Option<MatchResult>> {
         ensure!(
-            pathbuf.is_relative(),
+            Path::new(path).is_relative(),
             "path must be relative to the repository root"
         );
 
-        if let Some(result) = self.check_scoped(path) {
-            return Ok(Some(result));
+        let (path, is_dir) = match path.strip_suffix('/') {
+            Some(path) => (path, true),
+            None => (path, false),
+        };
+
+        let components: Vec<&str> = path.split('/').collect();
+        for i in 1..components.len() {
+            let dir = components[..i].join("/");
+            if let Some(result) = self.check_path(&dir, true).filter(|result| result.ignored) {
+                return Ok(Some(result));
+            }
         }
 
-        Ok(self.check_global(path))
+        Ok(self.check_path(path, is_dir))
     }
 
-    pub fn check_scoped(&self, path: &str) -> Option<bool> {
+    fn check_path(&self, path: &str, is_dir: bool) -> Option<MatchResult> {
+        self.check_scoped(path, is_dir)
+            .or_else(|| self.check_global(path, is_dir))
+    }
+
+    /// check the rules of the `.gitignore` files in the directories above a path, the
+    /// deepest first, each matching the path relative to its directory
+    pub fn check_scoped(&self, path: &str, is_dir: bool) -> Option<MatchResult> {
         let mut parent = PathBuf::from(path);
         parent.pop();
 
         loop {
             let parent_str = parent.to_str().unwrap();
             if let Some(rules) = self.local.get(parent_str) {
-                if let Some(result) = Self::check_rules(rules, path) {
-                    return Some(result);
+                let relative = match parent_str {
+                    "" => path,
+                    _ => &path[parent_str.len() + 1..],
+                };
+
+                if let Some(rule) = Self::check_rules(rules, relative, is_dir) {
+                    let source = match parent_str {
+                        "" => ".gitignore".to_string(),
+                        dir => format!("{}/.gitignore", dir),
+                    };
+                    return Some(rule.result(source));
                 }
             }
 
@@ -127,12 +191,211 @@ impl GitIgnore {
         None
     }
 
-    pub fn check_global(&self, path: &str) -> Option<bool> {
-        for rules in &self.global {
-            if let Some(result) = Self::check_rules(rules, path) {
-                return Some(result);
+    /// check the rules which are not in the work tree, in order of precedence
+    pub fn check_global(&self, path: &str, is_dir: bool) -> Option<MatchResult> {
+        for (source, rules) in &self.global {
+            if let Some(rule) = Self::check_rules(rules, path, is_dir) {
+                return Some(rule.result(source.clone()));
             }
         }
         None
     }
 }
+
+/// remove trailing spaces, unless they are escaped with a backslash
+fn trim_trailing_spaces(line: &str) -> &str {
+    let line = line.trim_end_matches('\r');
+    let trimmed = line.trim_end_matches(' ');
+
+    if trimmed.ends_with('\\') && trimmed.len() < line.len() {
+        &line[..trimmed.len() + 1]
+    } else {
+        trimmed
+    }
+}
+
+/// translate a glob to a regex, None if it is invalid, such as an unclosed `[`
+///
+/// wildcards never match a `/`, except for `**` between slashes or at either end
+fn glob_to_regex(glob: &str) -> Option<String> {
+    let chars: Vec<char> = glob.chars().collect();
+    let mut regex = String::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        match chars[i] {
+            '*' if chars.get(i + 1) == Some(&'*') => {
+                let after = i + 2;
+                let alone = (i == 0 || chars[i - 1] == '/')
+                    && (after == chars.len() || chars[after] == '/');
+
+                if !alone {
+                    // not between slashes, this is a plain `*`
+                    regex.push_str("[^/]*");
+                    i = after;
+                } else if after == chars.len() {
+                    regex.push_str(".*");
+                    i = after;
+                } else {
+                    // `**/` matches zero or more directories
+                    regex.push_str("(?:.*/)?");
+                    i = after + 1;
+                }
+            }
+            '*' => {
+                regex.push_str("[^/]*");
+                i += 1;
+            }
+            '?' => {
+                regex.push_str("[^/]");
+                i += 1;
+            }
+            '[' => {
+                let mut j = i + 1;
+                let negated = matches!(chars.get(j), Some('!') | Some('^'));
+                if negated {
+                    j += 1;
+                }
+
+                // a `]` right after the opening bracket is part of the class
+                let start = j;
+                if chars.get(j) == Some(&']') {
+                    j += 1;
+                }
+                while chars.get(j).is_some_and(|c| *c != ']') {
+                    j += 1;
+                }
+                if j >= chars.len() {
+                    return None;
+                }
+
+                let class: String = chars[start..j]
+                    .iter()
+                    .map(|c| match c {
+                        '\\' | '[' | ']' | '&' | '~' | '^' => format!("\\{}", c),
+                        c => c.to_string(),
+                    })
+                    .collect();
+
+                // a class never matches a slash
+                let not = if negated { "^" } else { "" };
+                regex.push_str(&format!("[[{}{}]&&[^/]]", not, class));
+                i = j + 1;
+            }
+            '\\' => {
+                let c = chars.get(i + 1)?;
+                regex.push_str(&regex::escape(&c.to_string()));
+                i += 2;
+            }
+            c => {
+                regex.push_str(&regex::escape(&c.to_string()));
+                i += 1;
+            }
+        }
+    }
+
+    Some(regex)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ignore(rules: &str) -> GitIgnore {
+        GitIgnore {
+            global: vec![],
+            local: IndexMap::from([("".to_string(), GitIgnore::parse(rules))]),
+        }
+    }
+
+    #[test]
+    fn test_parse_compiles_patterns() {
+        let rules = GitIgnore::parse("# comment\n*.log\n!keep.log\n[invalid\n/build/  \n");
+
+        let patterns: Vec<_> = rules.iter().map(|r| r.pattern.as_str()).collect();
+        assert_eq!(patterns, vec!["*.log", "!keep.log", "/build/"]);
+        assert!(rules[1].negation);
+        assert!(rules[2].dir_only);
+    }
+
+    #[test]
+    fn test_git_cases() {
+        let ignore = ignore(
+            "*.log\n!keep.log\n/build\ndoc/**/*.txt\nout/\n**/cache\nlib/*.a\n\\#hash\nspace\\ \n",
+        );
+        let check = |path: &str| ignore.check(path).unwrap().map(|result| result.ignored);
+
+        // as answered by git check-ignore for the same .gitignore
+        for (path, ignored) in [
+            ("debug.log", Some(true)),
+            ("sub/debug.log", Some(true)),
+            ("keep.log", Some(false)),
+            ("sub/keep.log", Some(false)),
+            ("build", Some(true)),
+            ("build/", Some(true)),
+            ("build/main.o", Some(true)),
+            ("sub/build", None),
+            ("doc/a.txt", Some(true)),
+            ("doc/x/y/a.txt", Some(true)),
+            ("doc/a.md", None),
+            ("sub/doc/a.txt", None),
+            ("out", None),
+            ("out/", Some(true)),
+            ("out/bin", Some(true)),
+            ("sub/out/bin", Some(true)),
+            ("cache", Some(true)),
+            ("a/b/cache/x", Some(true)),
+            ("lib/x.a", Some(true)),
+            ("lib/sub/x.a", None),
+            ("#hash", Some(true)),
+            ("space ", Some(true)),
+            ("main.rs", None),
+        ] {
+            assert_eq!(check(path), ignored, "{}", path);
+        }
+
+        // a file in an ignored directory cannot be re-included
+        let ignore = self::ignore("logs/\n!logs/keep.log\n");
+        assert!(ignore.is_ignored("logs/keep.log").unwrap());
+
+        // the rules of a nested .gitignore are relative to its directory
+        let ignore = GitIgnore {
+            global: vec![],
+            local: IndexMap::from([("sub".to_string(), GitIgnore::parse("/tmp\n"))]),
+        };
+        assert!(ignore.is_ignored("sub/tmp").unwrap());
+        assert_eq!(ignore.check("tmp").unwrap(), None);
+        assert_eq!(ignore.check("sub/x/tmp").unwrap(), None);
+    }
+
+    #[test]
+    fn test_check_result() {
+        let mut ignore = ignore("# logs\n*.log\n\n!keep.log\n");
+        ignore
+            .local
+            .insert("sub".to_string(), GitIgnore::parse("tmp/\n"));
+        ignore.global.push((
+            ".gitlet/info/exclude".to_string(),
+            GitIgnore::parse("*.o\n"),
+        ));
+
+        // as git check-ignore -v prints them, source:line:pattern
+        let describe = |path: &str| {
+            ignore
+                .check(path)
+                .unwrap()
+                .map(|r| format!("{}:{}:{} {}", r.source, r.line, r.pattern, r.ignored))
+        };
+        assert_eq!(describe("a.log").unwrap(), ".gitignore:2:*.log true");
+        assert_eq!(
+            describe("keep.log").unwrap(),
+            ".gitignore:4:!keep.log false"
+        );
+        assert_eq!(describe("sub/tmp/x").unwrap(), "sub/.gitignore:1:tmp/ true");
+        assert_eq!(
+            describe("main.o").unwrap(),
+            ".gitlet/info/exclude:1:*.o true"
+        );
+        assert_eq!(describe("main.rs"), None);
+    }
+}
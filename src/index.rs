@@ -7,9 +7,17 @@
 //!
 //! When you git commit those changes, a new tree is produced from the index file, a new commit object is generated with that tree, branches are updated and we’re done.
 
+use crate::platform::{is_executable, Stat};
 use anyhow::Context;
 use bytes::{Buf, BufMut, Bytes, BytesMut};
+use chrono::{DateTime, Utc};
 use std::cmp::min;
+use std::fs::Metadata;
+
+/// extended flag of an entry not checked out in the work tree
+const SKIP_WORKTREE: u16 = 1 << 14;
+/// extended flag of an entry added with `git add -N`
+const INTENT_TO_ADD: u16 = 1 << 13;
 
 /// # The git index file format
 /// It is a **binary** file with three parts:
@@ -32,8 +40,12 @@ impl Index {
 
         let version = header.split_to(4);
         let version = u32::from_be_bytes([version[0], version[1], version[2], version[3]]);
-        // only support version 2 format
-        anyhow::ensure!(version == 2, "invalid index file version");
+        // version 3 adds extended flags, version 4 compresses the names, which we don't read
+        anyhow::ensure!(
+            version == 2 || version == 3,
+            "unsupported index file version: {}",
+            version
+        );
 
         let num_entries = header.split_to(4);
         let num_entries = u32::from_be_bytes([
@@ -101,11 +113,9 @@ impl Index {
             let fsize = bytes.split_to(4);
             let fsize = u32::from_be_bytes([fsize[0], fsize[1], fsize[2], fsize[3]]);
 
-            // Read SHA-1 of object, we store it as a hex string in our struct.
-            // In file it is stored as 20 bytes.
-
-            let sha = bytes.split_to(20);
-            let sha = hex::encode(sha);
+            // Read SHA-1 of object, stored as 20 bytes
+            let mut sha = [0; 20];
+            bytes.copy_to_slice(&mut sha);
 
             // Flags we're going to ignore
             let flags_and_name_len = bytes.split_to(2);
@@ -116,7 +126,22 @@ impl Index {
             let flag_assume_valid = (flags & 0b1000) != 0;
             let flag_extended = (flags & 0b0100) != 0;
             let flag_stage = flags & 0b0011;
-            anyhow::ensure!(!flag_extended, "do not support extended flag");
+
+            // version 3 entries may have a second flags word, of which git uses two bits
+            let extended_flags = if flag_extended {
+                anyhow::ensure!(version >= 3, "extended flag in a version 2 index");
+
+                let extended_flags = bytes.split_to(2);
+                let extended_flags = u16::from_be_bytes([extended_flags[0], extended_flags[1]]);
+                anyhow::ensure!(
+                    extended_flags & !(SKIP_WORKTREE | INTENT_TO_ADD) == 0,
+                    "unknown extended index flags: {:#x}",
+                    extended_flags
+                );
+                extended_flags
+            } else {
+                0
+            };
 
             // Read name of file, null-terminated
 
@@ -151,8 +176,8 @@ impl Index {
                 name.freeze()
             };
 
-            // We have consumed 62 + name.len() + 1 bytes
-            let consumed = 62 + name.len() + 1;
+            // We have consumed 62 + name.len() + 1 bytes, and 2 more with extended flags
+            let consumed = 62 + if flag_extended { 2 } else { 0 } + name.len() + 1;
             // We need to align to 8 bytes
             let padding = (8 - (consumed % 8)) % 8;
             bytes.advance(padding);
@@ -172,6 +197,8 @@ impl Index {
                 sha,
                 flag_assume_valid,
                 flag_stage,
+                flag_skip_worktree: extended_flags & SKIP_WORKTREE != 0,
+                flag_intent_to_add: extended_flags & INTENT_TO_ADD != 0,
                 name,
             };
 
@@ -181,12 +208,15 @@ impl Index {
         Ok(Index { version, entries })
     }
 
+    /// serialize the index, as version 3 when an entry has extended flags, and 2 otherwise,
+    /// as git does
     pub fn serialize(&self) -> anyhow::Result<Bytes> {
         let mut buf = BytesMut::new();
 
         buf.put_slice(b"DIRC");
 
-        buf.put_u32(self.version);
+        let extended = self.entries.iter().any(|e| e.extended_flags() != 0);
+        buf.put_u32(if extended { 3 } else { 2 });
 
         buf.put_u32(self.entries.len() as u32);
 
@@ -198,35 +228,77 @@ impl Index {
             buf.put_u32(entry.dev);
             buf.put_u32(entry.ino);
             buf.put_slice(&[0; 2]); // unused placeholder
-            buf.put_u16(entry.mode_type << 12 | entry.mode_perms);
+            buf.put_u16(entry.mode());
             buf.put_u32(entry.uid);
             buf.put_u32(entry.gid);
             buf.put_u32(entry.fsize);
 
-            let sha = hex::decode(&entry.sha).context("invalid sha")?;
-            anyhow::ensure!(sha.len() == 20, "invalid sha");
+            buf.put_slice(&entry.sha);
 
-            buf.put_slice(&sha);
+            let name_len = min(entry.name.len(), 0xfff);
+            buf.put_u16(entry.flags() | name_len as u16);
 
-            let mut flags = 0u16;
-            if entry.flag_assume_valid {
-                flags |= 1 << 15;
+            let mut size = 62 + entry.name.len() + 1;
+            if entry.extended_flags() != 0 {
+                buf.put_u16(entry.extended_flags());
+                size += 2;
             }
-            flags |= entry.flag_stage;
-
-            let name_len = min(entry.name.len(), 0xfff);
-            flags |= name_len as u16;
-            buf.put_u16(flags);
 
             buf.put_slice(entry.name.as_bytes());
             buf.put_u8(0);
 
-            let padding = (8 - ((62 + name_len + 1) % 8)) % 8;
+            let padding = (8 - (size % 8)) % 8;
             buf.put_slice(&vec![0; padding]);
         }
 
         Ok(buf.freeze())
     }
+
+    /// get the entry of a file at the given stage
+    pub fn get(&self, name: &str, stage: u16) -> Option<&IndexEntry> {
+        self.position(name, stage).ok().map(|i| &self.entries[i])
+    }
+
+    /// whether a file has an entry, at any stage
+    pub fn contains(&self, name: &str) -> bool {
+        self.entries
+            .get(self.first_position(name))
+            .is_some_and(|e| e.name == name)
+    }
+
+    /// remove the first entry of a file, returns the removed entry
+    pub fn remove(&mut self, name: &str) -> Option<IndexEntry> {
+        let position = self.first_position(name);
+
+        if self.entries.get(position)?.name != name {
+            return None;
+        }
+
+        Some(self.entries.remove(position))
+    }
+
+    /// binary search of the entry of a file at a stage, see [slice::binary_search]
+    ///
+    /// entries are sorted by name and stage, as git writes them and [Self::upsert] keeps them
+    fn position(&self, name: &str, stage: u16) -> Result<usize, usize> {
+        self.entries
+            .binary_search_by(|e| (e.name.as_str(), e.flag_stage).cmp(&(name, stage)))
+    }
+
+    /// the position of the first entry of a file, or where it would be inserted
+    fn first_position(&self, name: &str) -> usize {
+        self.entries.partition_point(|e| e.name.as_str() < name)
+    }
+
+    /// insert an entry, replacing the existing entry with the same name and stage
+    ///
+    /// entries are kept sorted by name and stage, as git expects
+    pub fn upsert(&mut self, entry: IndexEntry) {
+        match self.position(&entry.name, entry.flag_stage) {
+            Ok(position) => self.entries[position] = entry,
+            Err(position) => self.entries.insert(position, entry),
+        }
+    }
 }
 
 impl Default for Index {
@@ -261,12 +333,16 @@ pub struct IndexEntry {
     pub gid: u32,
     /// The size of the object, in bytes.
     pub fsize: u32,
-    /// sha1 of the object
-    pub sha: String,
+    /// sha1 of the object, see [Self::sha_hex] for its hex form
+    pub sha: [u8; 20],
     ///
     pub flag_assume_valid: bool,
     ///
     pub flag_stage: u16,
+    /// The file is not checked out, as with a sparse checkout, an extended flag.
+    pub flag_skip_worktree: bool,
+    /// The file was added with `git add -N`, its content is not staged yet, an extended flag.
+    pub flag_intent_to_add: bool,
     ///
     pub name: String,
 }
@@ -283,15 +359,121 @@ impl Default for IndexEntry {
             uid: 0,
             gid: 0,
             fsize: 0,
-            sha: "".to_string(),
+            sha: [0; 20],
             flag_assume_valid: false,
             flag_stage: 0,
+            flag_skip_worktree: false,
+            flag_intent_to_add: false,
             name: "".to_string(),
         }
     }
 }
 
 impl IndexEntry {
+    /// build the entry of a regular file or a symlink in the work tree from its metadata
+    ///
+    /// the timestamps are split into seconds and the nanoseconds within that second,
+    /// as [Self::is_unchanged] compares them
+    pub fn from_metadata(name: String, sha: [u8; 20], metadata: &Metadata) -> Self {
+        let (mode_type, mode_perms) = if metadata.file_type().is_symlink() {
+            (0b1010, 0)
+        } else if is_executable(metadata) {
+            (0b1000, 0o755)
+        } else {
+            (0b1000, 0o644)
+        };
+
+        let mut entry = Self {
+            name,
+            mode_type,
+            mode_perms,
+            sha,
+            ..Default::default()
+        };
+        entry.refresh(metadata);
+        entry
+    }
+
+    /// record the stat data of the file, once its content is known to match the entry
+    pub fn refresh(&mut self, metadata: &Metadata) {
+        let stat = Stat::from_metadata(metadata);
+
+        self.ctime = stat.ctime;
+        self.mtime = stat.mtime;
+        self.dev = stat.dev;
+        self.ino = stat.ino;
+        self.uid = stat.uid;
+        self.gid = stat.gid;
+        self.fsize = stat.size;
+    }
+
+    /// the sha of the object as hex, as objects are named
+    pub fn sha_hex(&self) -> String {
+        hex::encode(self.sha)
+    }
+
+    /// set the sha of the object from its hex form
+    pub fn set_sha_hex(&mut self, sha: &str) -> anyhow::Result<()> {
+        self.sha = sha_from_hex(sha)?;
+        Ok(())
+    }
+
+    /// set the object type and permissions from the octal mode of a tree entry,
+    /// like `100755`
+    pub fn set_mode(&mut self, mode: &str) -> anyhow::Result<()> {
+        let mode = u16::from_str_radix(mode, 8).context(format!("invalid mode: {}", mode))?;
+
+        self.mode_type = mode >> 12;
+        self.mode_perms = mode & 0o0777;
+
+        Ok(())
+    }
+
+    /// the time the file metadata last changed
+    ///
+    /// the index stores unsigned 32-bit seconds, so dates past 2038 stay in the future
+    pub fn ctime_datetime(&self) -> anyhow::Result<DateTime<Utc>> {
+        Self::datetime(self.ctime).context("invalid ctime")
+    }
+
+    /// the time the file content last changed, see [Self::ctime_datetime]
+    pub fn mtime_datetime(&self) -> anyhow::Result<DateTime<Utc>> {
+        Self::datetime(self.mtime).context("invalid mtime")
+    }
+
+    fn datetime((seconds, nanoseconds): (u32, u32)) -> Option<DateTime<Utc>> {
+        DateTime::from_timestamp(i64::from(seconds), nanoseconds)
+    }
+
+    /// the raw mode, the object type in the high 4 bits and the unix permissions
+    pub fn mode(&self) -> u16 {
+        self.mode_type << 12 | self.mode_perms
+    }
+
+    /// the raw flags as stored in the index, without the name length in the low 12 bits
+    pub fn flags(&self) -> u16 {
+        let mut flags = self.flag_stage << 12;
+        if self.flag_assume_valid {
+            flags |= 1 << 15;
+        }
+        if self.extended_flags() != 0 {
+            flags |= 1 << 14;
+        }
+        flags
+    }
+
+    /// the raw extended flags, written after the flags in a version 3 index when not 0
+    pub fn extended_flags(&self) -> u16 {
+        let mut flags = 0;
+        if self.flag_skip_worktree {
+            flags |= SKIP_WORKTREE;
+        }
+        if self.flag_intent_to_add {
+            flags |= INTENT_TO_ADD;
+        }
+        flags
+    }
+
     pub fn mode_type_str(&self) -> &str {
         match self.mode_type {
             0b1000 => "regular file",
@@ -300,4 +482,284 @@ impl IndexEntry {
             _ => unreachable!(),
         }
     }
+
+    /// whether the file behind this entry is known to be unchanged, without reading it
+    ///
+    /// a size change guarantees a content change, so the size is checked first,
+    /// then the timestamps. A false result means the file may have changed and
+    /// should be hashed, unless its size differs.
+    pub fn is_unchanged(&self, metadata: &Metadata) -> bool {
+        if !self.same_size(metadata) {
+            return false;
+        }
+
+        let stat = Stat::from_metadata(metadata);
+
+        self.ctime == stat.ctime && self.mtime == stat.mtime
+    }
+
+    /// whether the kind of the file on disk differs from the kind recorded in this entry
+    ///
+    /// metadata must come from [std::fs::symlink_metadata], so symlinks are not followed.
+    /// A directory stands for a git link.
+    pub fn is_type_changed(&self, metadata: &Metadata) -> bool {
+        let file_type = metadata.file_type();
+
+        let mode_type = if file_type.is_symlink() {
+            0b1010
+        } else if file_type.is_dir() {
+            0b1110
+        } else {
+            0b1000
+        };
+
+        mode_type != self.mode_type
+    }
+
+    /// whether the file has the size recorded in this entry, truncated to 32 bits as git does
+    pub fn same_size(&self, metadata: &Metadata) -> bool {
+        metadata.len() as u32 == self.fsize
+    }
+}
+
+/// the 20 bytes of a sha written as 40 hex digits
+pub fn sha_from_hex(sha: &str) -> anyhow::Result<[u8; 20]> {
+    let mut bytes = [0; 20];
+    hex::decode_to_slice(sha, &mut bytes).context(format!("invalid sha: {}", sha))?;
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// a sha ending with the given hex digits
+    fn sha(end: &str) -> [u8; 20] {
+        sha_from_hex(&format!("{:0>40}", end)).unwrap()
+    }
+
+    fn entry(name: &str, sha: &str) -> IndexEntry {
+        IndexEntry {
+            name: name.to_string(),
+            sha: self::sha(sha),
+            mode_type: 0b1000,
+            mode_perms: 0o644,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_index_get() {
+        let mut index = Index::default();
+        index.upsert(entry("a.txt", "aa"));
+
+        assert_eq!(index.get("a.txt", 0).unwrap().sha, sha("aa"));
+        assert!(index.get("a.txt", 1).is_none());
+        assert!(index.get("b.txt", 0).is_none());
+    }
+
+    #[test]
+    fn test_index_remove() {
+        let mut index = Index::default();
+        index.upsert(entry("a.txt", "aa"));
+        index.upsert(entry("b.txt", "bb"));
+
+        assert_eq!(index.remove("a.txt").unwrap().sha, sha("aa"));
+        assert!(index.remove("a.txt").is_none());
+        assert_eq!(index.entries.len(), 1);
+        assert_eq!(index.entries[0].name, "b.txt");
+    }
+
+    #[test]
+    fn test_index_lookup_stages() {
+        let mut index = Index::default();
+        for (name, stage) in [("b.txt", 3), ("a.txt", 0), ("b.txt", 1), ("c.txt", 0)] {
+            index.upsert(IndexEntry {
+                flag_stage: stage,
+                ..entry(name, &format!("{:040}", stage))
+            });
+        }
+
+        assert_eq!(index.get("b.txt", 3).unwrap().sha, sha("3"));
+        assert!(index.get("b.txt", 2).is_none());
+        assert!(index.contains("b.txt"));
+        assert!(!index.contains("b"));
+        assert!(!index.contains("d.txt"));
+
+        // the lowest stage goes first
+        assert_eq!(index.remove("b.txt").unwrap().flag_stage, 1);
+        assert_eq!(index.remove("b.txt").unwrap().flag_stage, 3);
+        assert!(index.remove("b.txt").is_none());
+
+        // serialization is unaffected by the lookups
+        let bytes = index.serialize().unwrap();
+        assert_eq!(
+            Index::from_bytes(bytes.clone())
+                .unwrap()
+                .serialize()
+                .unwrap(),
+            bytes
+        );
+    }
+
+    #[test]
+    fn test_index_entry_raw_fields() {
+        let entry = IndexEntry {
+            flag_stage: 2,
+            flag_assume_valid: true,
+            ..entry("a.txt", &"0".repeat(40))
+        };
+
+        assert_eq!(entry.mode(), 0o100644);
+        assert_eq!(entry.flags(), 0xa000);
+
+        let mut index = Index::default();
+        index.upsert(entry);
+        let parsed = Index::from_bytes(index.serialize().unwrap()).unwrap();
+        assert_eq!(parsed.entries[0].flag_stage, 2);
+        assert!(parsed.entries[0].flag_assume_valid);
+    }
+
+    #[test]
+    fn test_index_version_3() {
+        // written by git after `git update-index --skip-worktree a.txt` and `git add -N b.txt`,
+        // without the trailing checksum
+        let data = hex::decode(concat!(
+            "4449524300000003000000036ad10b772f569d836ad10b772f569d830000fe00",
+            "00f72031000081a400000000000000000000000278981922613b2afb6025042f",
+            "f6bd878ac1994e8540054000612e747874000000000000000000000000000000",
+            "000000000000000000000000000081a4000000000000000000000000e69de29b",
+            "b2d1d6434b8b29ae775ad8c2e48c539140052000622e7478740000006ad10b77",
+            "2f569d836ad10b772f569d830000fe0000f72051000081a40000000000000000",
+            "00000002f2ad6c76f0115a6ba5b00456a849810e7ec0af200005632e74787400",
+            "00000000",
+        ))
+        .unwrap();
+
+        let mut index = Index::from_bytes(Bytes::from(data.clone())).unwrap();
+        assert_eq!(index.version, 3);
+
+        let flags: Vec<_> = index
+            .entries
+            .iter()
+            .map(|e| (e.name.as_str(), e.flag_skip_worktree, e.flag_intent_to_add))
+            .collect();
+        assert_eq!(
+            flags,
+            vec![
+                ("a.txt", true, false),
+                ("b.txt", false, true),
+                ("c.txt", false, false)
+            ]
+        );
+        assert_eq!(
+            index.entries[2].sha_hex(),
+            "f2ad6c76f0115a6ba5b00456a849810e7ec0af20"
+        );
+
+        assert_eq!(index.serialize().unwrap(), data);
+
+        // without extended flags, the index is written as version 2
+        for entry in &mut index.entries {
+            entry.flag_skip_worktree = false;
+            entry.flag_intent_to_add = false;
+        }
+        let bytes = index.serialize().unwrap();
+        assert_eq!(bytes[4..8], [0, 0, 0, 2]);
+        assert_eq!(Index::from_bytes(bytes).unwrap().entries.len(), 3);
+    }
+
+    #[test]
+    fn test_index_entry_sha_round_trip() {
+        let hex = "f2ad6c76f0115a6ba5b00456a849810e7ec0af20";
+
+        let mut entry = entry("a.txt", "0");
+        entry.set_sha_hex(hex).unwrap();
+        assert_eq!(entry.sha_hex(), hex);
+
+        let mut index = Index::default();
+        index.upsert(entry);
+        let parsed = Index::from_bytes(index.serialize().unwrap()).unwrap();
+        assert_eq!(parsed.entries[0].sha, index.entries[0].sha);
+        assert_eq!(parsed.entries[0].sha_hex(), hex);
+
+        assert!(index.entries[0].set_sha_hex("f2ad").is_err());
+        assert!(index.entries[0].set_sha_hex(&"g".repeat(40)).is_err());
+    }
+
+    #[test]
+    fn test_index_entry_datetime_after_2038() {
+        let entry = IndexEntry {
+            ctime: (4_000_000_000, 5),
+            mtime: (u32::MAX, 0),
+            ..entry("a.txt", &"0".repeat(40))
+        };
+
+        let ctime = entry.ctime_datetime().unwrap();
+        assert_eq!(ctime.timestamp(), 4_000_000_000);
+        assert_eq!(ctime.timestamp_subsec_nanos(), 5);
+        assert_eq!(
+            entry.mtime_datetime().unwrap().to_rfc3339(),
+            "2106-02-07T06:28:15+00:00"
+        );
+
+        // the seconds survive a round trip through the index file
+        let mut index = Index::default();
+        index.upsert(entry);
+        let parsed = Index::from_bytes(index.serialize().unwrap()).unwrap();
+        assert_eq!(parsed.entries[0].mtime, (u32::MAX, 0));
+    }
+
+    #[test]
+    fn test_index_upsert() {
+        let mut index = Index::default();
+        index.upsert(entry("c.txt", "cc"));
+        index.upsert(entry("a.txt", "aa"));
+        index.upsert(entry("b/c.txt", "bc"));
+        index.upsert(entry("a.txt", "a2"));
+
+        let names: Vec<_> = index.entries.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, vec!["a.txt", "b/c.txt", "c.txt"]);
+        assert_eq!(index.get("a.txt", 0).unwrap().sha, sha("a2"));
+    }
+
+    #[test]
+    fn test_index_entry_is_unchanged() {
+        let path =
+            std::env::temp_dir().join(format!("gitlet-test-index-entry-{}", std::process::id()));
+        std::fs::write(&path, "hello").unwrap();
+
+        let metadata = path.metadata().unwrap();
+        let entry = IndexEntry::from_metadata("a.txt".to_string(), sha("aa"), &metadata);
+        assert!(entry.is_unchanged(&metadata));
+
+        // the file grows, the size tells it changed without hashing it
+        std::fs::write(&path, "hello world").unwrap();
+        let metadata = path.metadata().unwrap();
+        assert!(!entry.same_size(&metadata));
+        assert!(!entry.is_unchanged(&metadata));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_index_entry_is_type_changed() {
+        let dir =
+            std::env::temp_dir().join(format!("gitlet-test-type-change-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let path = dir.join("a.txt");
+        std::fs::write(&path, "hello").unwrap();
+
+        let entry = entry("a.txt", "aa");
+        assert!(!entry.is_type_changed(&path.symlink_metadata().unwrap()));
+
+        // the file is replaced by a symlink
+        std::fs::remove_file(&path).unwrap();
+        crate::platform::symlink("b.txt", &path).unwrap();
+        assert!(entry.is_type_changed(&path.symlink_metadata().unwrap()));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 }
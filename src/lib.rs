@@ -4,7 +4,11 @@
 #[macro_use]
 mod macros;
 
+pub mod color;
+pub mod diff;
 pub mod objects;
+pub mod platform;
+pub mod progress;
 pub mod refs;
 pub mod repository;
 pub mod utils;
@@ -12,3 +16,9 @@ pub mod utils;
 pub mod index;
 
 pub mod ignore;
+
+pub mod attributes;
+
+pub mod merge;
+
+pub mod rename;
@@ -1,11 +1,13 @@
 use anyhow::{ensure, Context};
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
+use gitlet::color;
 use gitlet::objects::tree::TreeEntry;
 use gitlet::objects::{Fmt, GitObject, GitObjectTrait};
-use gitlet::repository::Repository;
-use indexmap::{IndexMap, IndexSet};
-use std::collections::BTreeSet;
-use std::os::unix::fs::MetadataExt;
+use gitlet::progress::Stderr;
+use gitlet::repository::{Bisect, ChangeKind, Head, Merge, Push, Rebase, Repository};
+use gitlet::utils::cleanup_message;
+use indexmap::IndexMap;
+use std::collections::HashSet;
 use std::path::PathBuf;
 
 #[derive(Parser)]
@@ -31,12 +33,24 @@ enum Commands {
             value_name = "type",
             help = "Specify the expected type.",
             default_value = "blob",
-            required = true
+            required_unless_present_any = ["show_type", "show_size"]
         )]
         fmt: Fmt,
         /// file to cat
-        #[arg(help = "The objects to display.")]
-        object: String,
+        #[arg(
+            help = "The objects to display.",
+            required_unless_present_any = ["show_type", "show_size"]
+        )]
+        object: Option<String>,
+        /// Show the type of the object instead of its content
+        #[arg(short = 't', value_name = "object", conflicts_with = "show_size")]
+        show_type: Option<String>,
+        /// Show the size of the object instead of its content
+        #[arg(short = 's', value_name = "object")]
+        show_size: Option<String>,
+        /// Allow -t and -s to query objects of an unknown type
+        #[arg(long)]
+        allow_unknown_type: bool,
     },
 
     /// Compute objects ID and optionally creates a blob from a file
@@ -45,13 +59,15 @@ enum Commands {
         #[arg(short)]
         write: bool,
         #[arg(
-            value_enum,
             short = 't',
             value_name = "type",
             help = "Specify the expected type.",
             default_value = "blob"
         )]
-        fmt: Fmt,
+        fmt: String,
+        /// Allow any type and skip the validation of the type, for testing malformed objects
+        #[arg(long)]
+        literally: bool,
         /// Read objects from <file>
         path: PathBuf,
     },
@@ -61,6 +77,15 @@ enum Commands {
         /// Commit to start at
         #[arg(default_value = "HEAD")]
         commit: String,
+        /// Print the history as a graphviz digraph
+        #[arg(long, conflicts_with_all = ["max_count", "oneline"])]
+        graph: bool,
+        /// Limit the number of commits to output
+        #[arg(short = 'n', long)]
+        max_count: Option<usize>,
+        /// Print each commit as its short sha and subject
+        #[arg(long)]
+        oneline: bool,
     },
     /// List the contents of a tree objects
     LsTree {
@@ -71,41 +96,128 @@ enum Commands {
         tree: String,
     },
 
-    /// Checkout a commit inside of a directory.
-    /// todo this just clones file by tree into the directory, does not update HEAD
+    /// Create a tree object from the index, and print its sha.
+    WriteTree,
+    /// Create a commit object of a tree, and print its sha, no ref is updated.
+    CommitTree {
+        /// The tree of the commit
+        tree: String,
+        /// The parent commit, the commit is a root commit without it
+        #[arg(short)]
+        parent: Option<String>,
+        /// The commit message
+        #[arg(short)]
+        message: String,
+    },
+    /// Switch to a branch or commit, or checkout a commit inside of a directory.
     Checkout {
         /// The commit or tree or ref to checkout.
         name: String,
-        /// The EMPTY directory to checkout on.
-        path: PathBuf,
+        /// The EMPTY directory to checkout on, the work tree and HEAD are updated when omitted.
+        path: Option<PathBuf>,
+        /// Discard local changes when updating the work tree.
+        #[arg(short, long, alias = "discard-changes")]
+        force: bool,
+    },
+    /// Show changes between two commits or trees.
+    Diff {
+        /// The commit or tree to compare from
+        a: String,
+        /// The commit or tree to compare to
+        b: String,
+    },
+    /// Show a commit with its patch, a tag, a tree or a blob.
+    Show {
+        /// The object to show
+        #[arg(default_value = "HEAD")]
+        object: String,
+    },
+    /// List, create, or delete branches.
+    Branch {
+        /// The branch to create, or to delete with `-d`
+        name: Option<String>,
+        /// Delete a branch
+        #[arg(short, long, requires = "name")]
+        delete: bool,
+        /// The commit the new branch will point to
+        #[arg(conflicts_with = "delete")]
+        start_point: Option<String>,
     },
     /// List all refs in a local repository
-    ShowRef,
+    ShowRef {
+        /// Abbreviate the object names to the shortest unique prefix, at least n characters long
+        #[arg(long, value_name = "n", num_args = 0..=1, default_missing_value = "7")]
+        abbrev: Option<usize>,
+        /// Dereference tags into object IDs as well, shown with a `^{}` suffix
+        #[arg(short, long)]
+        dereference: bool,
+    },
     /// tag
     Tag {
         /// Whether to create a tag objects
         #[arg(short = 'a', requires = "name")]
         create_tag_object: bool,
+        /// The message of the tag object, an editor is opened when it is missing
+        #[arg(short, long, requires = "create_tag_object")]
+        message: Option<String>,
         /// The new tag's name.
         name: Option<String>,
         /// The objects the new tag will point to
         #[arg(default_value = "HEAD")]
         object: String,
+        /// How to sort the listed tags, `version:refname` orders version numbers naturally
+        #[arg(long, default_value = "refname", value_parser = ["refname", "version:refname", "v:refname"])]
+        sort: String,
     },
     /// List all the stage files
     LsFiles {
         /// Show everything
         #[arg(long, short)]
         verbose: bool,
+        /// Also show the raw data of every entry, including its flags
+        #[arg(long)]
+        debug: bool,
+        /// Show the files of the index, the default
+        #[arg(long, short)]
+        cached: bool,
+        /// Show the files missing from the work tree
+        #[arg(long, short, conflicts_with_all = ["verbose", "debug"])]
+        deleted: bool,
+        /// Show the files whose work tree version differs from the index, deleted ones included
+        #[arg(long, short, conflicts_with_all = ["verbose", "debug"])]
+        modified: bool,
+        /// Show the untracked files
+        #[arg(long, short, conflicts_with_all = ["verbose", "debug"])]
+        others: bool,
+        /// With --others, also show the ignored files
+        #[arg(long, requires = "others")]
+        include_ignored: bool,
     },
     /// Check path(s) against ignore rules.
     CheckIgnore {
+        /// Show the rule deciding each path, as `<source>:<line>:<pattern>\t<path>`
+        #[arg(short, long)]
+        verbose: bool,
         /// Paths to check
         #[arg(required = true)]
         path: Vec<String>,
     },
     /// Show the working tree status.
-    Status,
+    Status {
+        /// Also detect renames of files whose content changed, at least n% similar
+        #[arg(
+            short = 'M',
+            long = "find-renames",
+            value_name = "n",
+            num_args = 0..=1,
+            default_missing_value = "50",
+            value_parser = clap::value_parser!(u8).range(0..=100)
+        )]
+        find_renames: Option<u8>,
+        /// Give the output in a stable format for scripts, only v2 is supported
+        #[arg(long, value_name = "version", value_parser = ["v2"])]
+        porcelain: Option<String>,
+    },
     /// Remove files from the working tree and the index.
     Rm {
         /// Files to remove
@@ -114,16 +226,134 @@ enum Commands {
     /// Add files contents to the index.
     Add {
         /// Files to add
+        #[arg(required_unless_present = "patch")]
         path: Vec<String>,
+        /// Choose interactively which hunks of the modified files to stage
+        #[arg(short, long, conflicts_with = "path")]
+        patch: bool,
     },
     /// Record changes to the repository.
     Commit {
         /// Message to associate with this commit.
+        #[arg(short, long, required_unless_present = "file", conflicts_with = "file")]
+        message: Option<String>,
+        /// Read the message from a file, `-` reads it from the standard input.
+        #[arg(short = 'F', long)]
+        file: Option<PathBuf>,
+        /// Allow recording a commit that has the exact same tree as its parent.
+        #[arg(long)]
+        allow_empty: bool,
+        /// Bypass the pre-commit and commit-msg hooks.
+        #[arg(short = 'n', long)]
+        no_verify: bool,
+    },
+    /// Get and set repository or global options.
+    Config {
+        /// List all variables set in config files, along with their values.
         #[arg(short, long)]
-        message: String,
+        list: bool,
+        /// Show the config file each variable comes from.
+        #[arg(long, requires = "list")]
+        show_origin: bool,
+        /// The variable to get or set, as `section.key` or `section.subsection.key`.
+        #[arg(conflicts_with = "list")]
+        name: Option<String>,
+        /// The value to set, in the repository's config file.
+        value: Option<String>,
+    },
+    /// Join another branch into the current branch, only fast-forwards are supported.
+    Merge {
+        /// The branch or commit to merge.
+        branch: String,
+    },
+    /// Reapply the commits of the current branch on top of another branch.
+    Rebase {
+        /// The branch or commit to rebase onto.
+        onto: String,
+    },
+    /// Use binary search to find the commit that introduced a bug.
+    Bisect {
+        #[command(subcommand)]
+        command: BisectCommand,
+    },
+    /// Add or show object notes.
+    Notes {
+        /// Add a note with the given message to the object.
+        #[arg(long, value_name = "MESSAGE")]
+        add: Option<String>,
+        /// The object to annotate or show the note of.
+        #[arg(default_value = "HEAD")]
+        object: String,
+    },
+    /// Show or prune the reflog of HEAD.
+    Reflog {
+        /// Drop the entries older than --expire-days from every reflog.
+        #[arg(long)]
+        expire: bool,
+        /// The age in days after which entries expire.
+        #[arg(long, default_value_t = 90)]
+        expire_days: u32,
+    },
+    /// Pick out and massage parameters.
+    RevParse {
+        /// Resolve the argument to exactly one existing object, fail otherwise.
+        #[arg(long)]
+        verify: bool,
+        /// Print the short name of the ref instead of its sha.
+        #[arg(long, conflicts_with = "verify")]
+        abbrev_ref: bool,
+        /// Like --verify, but print the shortest unique prefix of the sha, at least 7 long.
+        #[arg(long, conflicts_with = "abbrev_ref")]
+        short: bool,
+        /// The revision to parse.
+        rev: String,
+    },
+    /// Update a remote ref along with its objects, the remote must be on the local filesystem.
+    Push {
+        /// A configured remote, or the path of a repository.
+        remote: String,
+        /// `[+]<src>[:<dst>]`, the active branch by default.
+        refspec: Option<String>,
+        /// Allow updates which are not fast-forwards.
+        #[arg(short, long)]
+        force: bool,
+    },
+    /// Download the branches of a remote, the remote must be on the local filesystem.
+    Fetch {
+        /// A configured remote, or the path of a repository.
+        remote: String,
+        /// Only fetch the last <DEPTH> commits of each branch.
+        #[arg(long)]
+        depth: Option<usize>,
+    },
+    /// Find a common ancestor of two commits.
+    MergeBase {
+        /// Check whether the first commit is an ancestor of the second one, exit with 1 if not
+        #[arg(long)]
+        is_ancestor: bool,
+        a: String,
+        b: String,
     },
 }
 
+#[derive(Subcommand)]
+enum BisectCommand {
+    /// Start a bisection from the current HEAD.
+    Start,
+    /// Mark a commit as good, it does not contain the bug.
+    Good {
+        #[arg(default_value = "HEAD")]
+        rev: String,
+    },
+    /// Mark a commit as bad, it contains the bug.
+    Bad {
+        #[arg(default_value = "HEAD")]
+        rev: String,
+    },
+    /// Finish the bisection and go back to the original HEAD.
+    Reset,
+}
+
 fn main() -> anyhow::Result<()> {
     let cli = Cli::parse();
 
@@ -132,58 +362,97 @@ fn main() -> anyhow::Result<()> {
             let repo = Repository::init(path)?;
             println!("init at path: {}", repo.git_dir.display());
         }
-        Commands::CatFile { fmt, object } => {
+        Commands::CatFile {
+            fmt,
+            object,
+            show_type,
+            show_size,
+            allow_unknown_type,
+        } => {
             let repo = Repository::find(".")?;
+
+            if let Some(name) = show_type.as_ref().or(show_size.as_ref()) {
+                let sha = repo
+                    .find_object(name, false)?
+                    .ok_or(anyhow::anyhow!("object not found: {}", name))?;
+
+                let (fmt, length) = if allow_unknown_type {
+                    repo.read_object_header(&sha)?
+                } else {
+                    let object = repo.read_object(&sha)?;
+                    (object.header.fmt.to_str().to_string(), object.header.length)
+                };
+
+                if show_type.is_some() {
+                    println!("{}", fmt);
+                } else {
+                    println!("{}", length);
+                }
+
+                return Ok(());
+            }
+
+            // unwrap is safe because clap requires object without -t or -s
+            let object = object.unwrap();
+            // peel to the requested type, so `cat-file tag <tag>` shows the tag itself
             let object = repo
-                .find_object(&object, true)?
+                .find_object_peeled(&object, Some(fmt))?
                 .ok_or(anyhow::anyhow!("object not found: {}", object))?;
 
             let object = repo.read_object(&object)?;
 
             ensure!(object.header.fmt == fmt, "objects type mismatch");
 
-            println!("{}", object);
+            // the content ends with its own newline
+            print!("{}", object);
         }
-        Commands::HashObject { write, fmt, path } => {
+        Commands::HashObject {
+            write,
+            fmt,
+            literally,
+            path,
+        } => {
             let repo = Repository::find(".")?;
             anyhow::ensure!(path.exists(), "file does not exist: {}", path.display());
 
             let data = std::fs::read(&path)?;
 
-            let object = GitObject::new(fmt, data.into());
+            let data = if literally {
+                GitObject::serialize_literally(&fmt, &data)?
+            } else {
+                let fmt = Fmt::from_str(&fmt, true).map_err(|e| anyhow::anyhow!(e))?;
+                GitObject::new(fmt, data.into()).serialize()?
+            };
 
             let sha = if write {
-                repo.write_object(&object)?
+                repo.write_raw_object(&data)?
             } else {
-                gitlet::utils::sha(&object.serialize()?)
+                gitlet::utils::sha(&data)
             };
 
             println!("{}", sha);
         }
-        Commands::Log { commit } => {
+        Commands::Log {
+            commit,
+            graph,
+            max_count,
+            oneline,
+        } => {
             let repo = Repository::find(".")?;
-            let commit = repo
-                .find_object(&commit, true)?
-                .ok_or(anyhow::anyhow!("object not found: {}", commit))?;
-
-            // todo do not clone
-            fn log_graphviz(
-                repo: &Repository,
-                sha: &str,
-                visited: &mut BTreeSet<String>,
-            ) -> anyhow::Result<()> {
-                if visited.contains(sha) {
-                    return Ok(());
-                }
 
-                visited.insert(sha.to_string());
+            if !graph {
+                print!("{}", repo.log(&commit, max_count, oneline)?);
+                return Ok(());
+            }
 
-                let commit = repo.read_object(sha)?;
+            let commits = repo.commits_from(&commit, false)?;
 
-                anyhow::ensure!(commit.header.fmt == Fmt::Commit, "objects type mismatch");
+            print!(r"digraph log{{");
+            print!("  node[shape=rect]");
 
-                let commit = gitlet::objects::commit::Commit::from_bytes(commit.data)?;
-                let short_sha = &sha[..8];
+            for commit in commits {
+                let (sha, commit) = commit?;
+                let short_sha = repo.abbrev(&sha, 8)?;
 
                 let mut message = commit
                     .message()
@@ -197,19 +466,11 @@ fn main() -> anyhow::Result<()> {
 
                 print!("  c_{} [label=\"{}: {}\"]", sha, short_sha, message);
 
-                if let Some(parents) = commit.parents() {
-                    for parent in parents {
-                        print!("  c_{} -> c_{}", sha, parent);
-                        log_graphviz(repo, parent, visited)?;
-                    }
+                for parent in commit.parents().into_iter().flatten() {
+                    print!("  c_{} -> c_{}", sha, parent);
                 }
-
-                Ok(())
             }
 
-            print!(r"digraph log{{");
-            print!("  node[shape=rect]");
-            log_graphviz(&repo, &commit, &mut BTreeSet::new())?;
             println!("}}");
         }
         Commands::LsTree { recursive, tree } => {
@@ -248,13 +509,8 @@ fn main() -> anyhow::Result<()> {
                     if recursive && file_type == gitlet::objects::tree::FileType::Tree {
                         ls_tree(repo, recursive, &sha1, prefix.join(path))?;
                     } else {
-                        println!(
-                            "{} {} {}\t{}",
-                            mode,
-                            file_type.to_str(),
-                            sha1,
-                            prefix.join(&path).display()
-                        );
+                        let path = prefix.join(&path);
+                        println!("{}", TreeEntry { mode, path, sha1 });
                     }
                 }
 
@@ -263,130 +519,183 @@ fn main() -> anyhow::Result<()> {
 
             ls_tree(&repo, recursive, &tree, PathBuf::from(""))?;
         }
-        Commands::Checkout { name, path } => {
+        Commands::WriteTree => {
             let repo = Repository::find(".")?;
 
-            let name = repo
-                .find_object(&name, true)?
-                .ok_or(anyhow::anyhow!("object not found: {}", name))?;
-
-            let commit = repo.read_object(&name)?;
+            println!("{}", repo.create_tree_from_index(&repo.read_index()?)?);
+        }
+        Commands::CommitTree {
+            tree,
+            parent,
+            message,
+        } => {
+            let repo = Repository::find(".")?;
 
-            ensure!(
-                commit.header.fmt == Fmt::Commit,
-                "objects type mismatch, expected commit"
-            );
+            println!("{}", repo.commit_tree(&tree, parent.as_deref(), message)?);
+        }
+        Commands::Checkout { name, path, force } => {
+            let mut repo = Repository::find(".")?;
+            repo.set_progress(Stderr::default());
 
-            let commit = gitlet::objects::commit::Commit::from_bytes(commit.data)?;
+            let Some(path) = path else {
+                return repo.checkout(&name, force);
+            };
 
-            let tree = commit.tree().ok_or(anyhow::anyhow!("commit has no tree"))?;
-            if path.exists() {
-                ensure!(path.is_dir(), "path is not a directory: {}", path.display());
-                ensure!(
-                    path.read_dir()?.next().is_none(),
-                    "path is not empty: {}",
-                    path.display()
-                );
-            } else {
-                std::fs::create_dir_all(&path)?;
-            }
+            repo.checkout_to(&name, &path)?;
+        }
+        Commands::Diff { a, b } => {
+            let repo = Repository::find(".")?;
 
-            fn checkout(repo: &Repository, tree: &str, prefix: PathBuf) -> anyhow::Result<()> {
-                let tree_object = repo.read_object(tree)?;
-                ensure!(
-                    tree_object.header.fmt == Fmt::Tree,
-                    "objects type mismatch, expected tree"
-                );
-                let tree = gitlet::objects::tree::Tree::from_bytes(tree_object.data)?;
+            print!(
+                "{}",
+                color::paint_diff(&repo.diff(&a, &b)?, diff_color(&repo)?)
+            );
+        }
+        Commands::Show { object } => {
+            let repo = Repository::find(".")?;
 
-                for tree_entry in tree.0 {
-                    let file_type = tree_entry.file_type()?;
-                    let TreeEntry { path, sha1, .. } = tree_entry;
+            print!(
+                "{}",
+                color::paint_diff(&repo.show(&object)?, diff_color(&repo)?)
+            );
+        }
+        Commands::Branch {
+            name,
+            delete,
+            start_point,
+        } => {
+            let repo = Repository::find(".")?;
 
-                    let object = repo.read_object(&sha1)?;
-                    let dest = prefix.join(&path);
+            match name {
+                Some(name) if delete => {
+                    let sha = repo.delete_branch(&name)?;
+                    println!("Deleted branch {} (was {}).", name, repo.abbrev(&sha, 7)?);
+                }
+                Some(name) => {
+                    repo.create_branch(&name, start_point.as_deref().unwrap_or("HEAD"))?;
+                }
+                None => {
+                    let head = repo.head()?;
+                    if let Head::Detached(sha) = &head {
+                        println!("* (HEAD detached at {})", repo.abbrev(sha, 7)?);
+                    }
 
-                    match file_type {
-                        gitlet::objects::tree::FileType::Tree => {
-                            std::fs::create_dir_all(&dest)?;
-                            checkout(repo, &sha1, dest)?;
-                        }
-                        gitlet::objects::tree::FileType::Blob => {
-                            std::fs::write(&dest, object.data)?;
-                        }
-                        gitlet::objects::tree::FileType::SymLink => {
-                            unimplemented!()
-                        }
-                        gitlet::objects::tree::FileType::Commit => {
-                            unimplemented!()
-                        }
+                    for branch in repo.branches()? {
+                        let active = head == Head::Branch(branch.name.clone());
+                        println!("{} {}", if active { '*' } else { ' ' }, branch.name);
                     }
                 }
-
-                Ok(())
             }
-
-            checkout(&repo, tree, path)?;
         }
-        Commands::ShowRef => {
+        Commands::ShowRef {
+            abbrev,
+            dereference,
+        } => {
             let repo = Repository::find(".")?;
 
-            let refs = repo.refs()?;
+            let refs = if dereference {
+                repo.peeled_refs()?
+            } else {
+                repo.refs()?
+                    .into_iter()
+                    .map(|(path, sha)| (path, (sha, None)))
+                    .collect()
+            };
+
+            let format_sha = |sha: String| match abbrev {
+                Some(len) => repo.abbrev(&sha, len),
+                None => Ok(sha),
+            };
+
+            for (path, (sha, peeled)) in refs {
+                println!("{} {}", format_sha(sha)?, path);
 
-            for (path, sha) in refs {
-                println!("{} {}", sha, path);
+                if let Some(peeled) = peeled {
+                    println!("{} {}^{{}}", format_sha(peeled)?, path);
+                }
             }
         }
         Commands::Tag {
             name,
             create_tag_object,
+            message,
             object,
+            sort,
         } => {
             let repo = Repository::find(".")?;
 
             // create a tag
             if let Some(name) = name {
+                // tags are not peeled, a tag may point at another tag
                 let mut sha = repo
-                    .find_object(&object, true)?
+                    .find_object(&object, false)?
                     .ok_or(anyhow::anyhow!("object not found: {}", object))?;
 
                 // create tag
                 if create_tag_object {
-                    let tag_object = gitlet::objects::tag::Tag::new(
-                        name.clone(),
-                        sha.clone(),
-                        "default@default.com".to_owned(),
-                        "A tag generated by gitlet, which won't let you customize the message!"
-                            .to_owned(),
-                    );
-
-                    let bytes = tag_object.serialize()?;
-
-                    let git_object = GitObject::new(Fmt::Tag, bytes);
+                    let message = match message {
+                        Some(message) => cleanup_message(&message, "whitespace", '#')?,
+                        None => {
+                            let template = format!(
+                                "\n#\n# Write a message for tag:\n#   {}\n# Lines starting with '#' will be ignored.\n",
+                                name
+                            );
+                            let message = edit_message(&repo, "TAG_EDITMSG", &template)?;
+
+                            cleanup_message(&message, "strip", '#')?
+                        }
+                    };
+                    ensure!(!message.is_empty(), "no tag message?");
 
-                    sha = repo.write_object(&git_object)?;
+                    sha = repo.write_tag_object(&name, &sha, message)?;
                 }
 
                 let tag_ref = gitlet::refs::tag::Tag::new(name, sha);
 
                 tag_ref.write_to(&repo)?;
             } else {
-                // list tags
-                let tags_path = repo.git_dir.join("refs").join("tags");
-                for entry in walkdir::WalkDir::new(tags_path) {
-                    let entry = entry?;
-                    let path = entry.path();
-                    if path.is_file() {
-                        let sha = std::fs::read_to_string(path)?;
-                        let sha = sha.trim_end_matches('\n');
-                        println!("{} {}", sha, entry.file_name().to_string_lossy());
-                    }
+                // list tags, tags() sorts them by name already
+                let mut tags = repo.tags()?;
+                if sort != "refname" {
+                    tags.sort_by(|a, b| gitlet::utils::version_cmp(a.name(), b.name()));
+                }
+
+                for tag in tags {
+                    println!("{} {}", tag.object(), tag.name());
                 }
             }
         }
-        Commands::LsFiles { verbose } => {
+        Commands::LsFiles {
+            verbose,
+            debug,
+            cached,
+            deleted,
+            modified,
+            others,
+            include_ignored,
+        } => {
             let repo = Repository::find(".")?;
 
+            // like git, the untracked files come first, then each entry of the index is
+            // listed for every selected kind it belongs to
+            if others {
+                for path in repo.untracked_files(include_ignored)? {
+                    println!("{}", path);
+                }
+            }
+
+            let cached = cached || !(deleted || modified || others);
+            let mut deleted_files: HashSet<String> = if deleted {
+                repo.deleted_files()?.into_iter().collect()
+            } else {
+                HashSet::new()
+            };
+            let mut modified_files: HashSet<String> = if modified {
+                repo.modified_files()?.into_iter().collect()
+            } else {
+                HashSet::new()
+            };
+
             let index = repo.read_index()?;
 
             if verbose {
@@ -397,222 +706,464 @@ fn main() -> anyhow::Result<()> {
                 )
             }
 
+            // verbose and debug cannot be combined with another kind, so they show cached files
             for e in index.entries {
-                println!("{}", e.name);
+                if cached {
+                    println!("{}", e.name);
+                }
                 if verbose {
                     println!("  {} with perms: {:o}", e.mode_type_str(), e.mode_perms);
-                    println!("  on blob: {}", e.sha);
+                    println!("  on blob: {}", e.sha_hex());
 
-                    let ctime = chrono::DateTime::<chrono::Utc>::from_timestamp(
-                        e.ctime.0 as i64,
-                        e.ctime.1,
-                    )
-                    .context("invalid ctime")?;
-                    let mtime = chrono::DateTime::<chrono::Utc>::from_timestamp(
-                        e.mtime.0 as i64,
-                        e.mtime.1,
-                    )
-                    .context("invalid mtime")?;
-                    println!("  created: {}, modified: {}", ctime, mtime);
+                    println!(
+                        "  created: {}, modified: {}",
+                        e.ctime_datetime()?,
+                        e.mtime_datetime()?
+                    );
                     println!("  device: {}, inode: {}", e.dev, e.ino);
-                    let user = users::get_user_by_uid(e.uid).context("invalid uid")?;
-                    let group = users::get_group_by_gid(e.gid).context("invalid gid")?;
+                    // an owner without a name, or any owner on windows, is its number
+                    let (user, group) = gitlet::platform::owner_names(e.uid, e.gid);
                     println!(
                         "  user: {} ({})  group: {} ({})",
-                        user.name().to_string_lossy(),
+                        user.unwrap_or_else(|| e.uid.to_string()),
                         e.uid,
-                        group.name().to_string_lossy(),
+                        group.unwrap_or_else(|| e.gid.to_string()),
                         e.gid
                     );
                     println!(
-                        "  flags: stage={} assume_valid={}",
-                        e.flag_stage, e.flag_assume_valid
+                        "  flags: stage={} assume_valid={} skip_worktree={} intent_to_add={}",
+                        e.flag_stage,
+                        e.flag_assume_valid,
+                        e.flag_skip_worktree,
+                        e.flag_intent_to_add
                     )
                 }
+                if debug {
+                    println!("  ctime: {}:{}", e.ctime.0, e.ctime.1);
+                    println!("  mtime: {}:{}", e.mtime.0, e.mtime.1);
+                    println!("  dev: {}\tino: {}", e.dev, e.ino);
+                    println!("  uid: {}\tgid: {}", e.uid, e.gid);
+                    println!("  size: {}\tflags: {:x}", e.fsize, e.flags());
+                    println!("  mode: {:o}\tsha: {}", e.mode(), e.sha_hex());
+                }
+                // an unmerged path has an entry per stage, but is listed once
+                if deleted_files.remove(&e.name) {
+                    println!("{}", e.name);
+                }
+                if modified_files.remove(&e.name) {
+                    println!("{}", e.name);
+                }
             }
         }
-        Commands::CheckIgnore { path } => {
+        Commands::CheckIgnore { verbose, path } => {
             let repo = Repository::find(".")?;
 
             let ignore = repo.read_ignore()?;
 
             for p in path {
-                let result = ignore.check(&p)?;
-                if let Some(true) = result {
-                    println!("{}: ignored", p);
+                // directory-only patterns need to know the path is a directory
+                let result = if repo.work_tree.join(&p).is_dir() && !p.ends_with('/') {
+                    ignore.check(&format!("{}/", p))?
                 } else {
-                    println!("{}: not ignored", p);
+                    ignore.check(&p)?
+                };
+                match result {
+                    // like git, the rule is shown even when it is a negation
+                    Some(result) if verbose => println!(
+                        "{}:{}:{}\t{}",
+                        result.source, result.line, result.pattern, p
+                    ),
+                    Some(result) if result.ignored => println!("{}: ignored", p),
+                    _ => println!("{}: not ignored", p),
                 }
             }
         }
-        Commands::Status => {
+        Commands::Status {
+            find_renames,
+            porcelain,
+        } => {
             let repo = Repository::find(".")?;
-            let index = repo.read_index()?;
 
-            // part 1: current branch
-            if let Ok(branch) = repo.active_branch() {
-                println!("On branch {}.", branch);
-            } else {
-                println!(
-                    "HEAD detached at {}",
-                    repo.find_object("HEAD", true)?.context("HEAD not found")?
-                );
-            }
-
-            // part 2: changes staged for commit
-            // index contains the staged files
-            // head contains last commit files
-            fn tree_to_dict(
-                repo: &Repository,
-                tree: &str,
-                prefix: &PathBuf,
-                dict: &mut IndexMap<String, String>,
-            ) -> anyhow::Result<()> {
-                let tree_or_commit = repo
-                    .find_object(tree, true)?
-                    .ok_or(anyhow::anyhow!("object not found: {}", tree))?;
-
-                let object = repo.read_object(&tree_or_commit)?;
+            if porcelain.is_some() {
+                let status = repo.status()?;
 
-                if let Fmt::Commit = object.header.fmt {
-                    let commit = gitlet::objects::commit::Commit::from_bytes(object.data.clone())?;
-                    let tree = commit.tree().ok_or(anyhow::anyhow!("commit has no tree"))?;
-                    return tree_to_dict(repo, tree, prefix, dict);
+                for entry in status.entries {
+                    println!("{}", entry.porcelain_v2());
+                }
+                for path in status.untracked {
+                    println!("? {}", path);
                 }
 
-                ensure!(
-                    object.header.fmt == Fmt::Tree,
-                    "objects type mismatch, expected tree"
-                );
-
-                let tree_object = object;
+                return Ok(());
+            }
 
-                let tree = gitlet::objects::tree::Tree::from_bytes(tree_object.data)?;
+            let status = repo.status()?;
+            let use_color =
+                color::stdout_enabled(repo.read_config()?.get("color", "ui").as_deref())?;
+            let staged =
+                |line: String| println!("{}", color::paint(&line, color::GREEN, use_color));
+            let unstaged =
+                |line: String| println!("{}", color::paint(&line, color::RED, use_color));
+            let label = |kind: ChangeKind| match kind {
+                ChangeKind::Added => "added:   ",
+                ChangeKind::Modified => "modified: ",
+                ChangeKind::Deleted => "deleted: ",
+                ChangeKind::TypeChanged => "typechange: ",
+            };
 
-                for tree_entry in tree.0 {
-                    let file_type = tree_entry.file_type()?;
+            // part 1: current branch
+            match repo.head()? {
+                Head::Branch(branch) => println!("On branch {}.", branch),
+                Head::Unborn(branch) => {
+                    println!("On branch {}.", branch);
+                    println!("No commits yet");
+                }
+                Head::Detached(sha) => println!("HEAD detached at {}", sha),
+            }
 
-                    let dest = tree_entry.path;
-                    let sha1 = tree_entry.sha1;
+            // part 2: changes staged for commit, modified paths first, then renames,
+            // then the added and deleted paths which are not renames
+            println!("Changes to be committed:");
 
-                    match file_type {
-                        gitlet::objects::tree::FileType::Tree => {
-                            tree_to_dict(repo, &sha1, &prefix.join(dest), dict)?;
-                        }
-                        gitlet::objects::tree::FileType::Blob => {
-                            dict.insert(prefix.join(dest).display().to_string(), sha1);
-                        }
-                        gitlet::objects::tree::FileType::SymLink => {
-                            unimplemented!()
-                        }
-                        gitlet::objects::tree::FileType::Commit => {
-                            unimplemented!()
-                        }
+            // path -> sha of the added and deleted paths, to find the renames among them
+            let mut added = IndexMap::new();
+            let mut deleted = IndexMap::new();
+            for entry in &status.entries {
+                match (entry.staged, &entry.head, &entry.index) {
+                    ('A', _, Some((_, sha))) => {
+                        added.insert(entry.path.clone(), sha.clone());
                     }
+                    ('D', Some((_, sha)), _) => {
+                        deleted.insert(entry.path.clone(), sha.clone());
+                    }
+                    _ => {}
                 }
+            }
 
-                Ok(())
+            for change in status.staged() {
+                if matches!(change.kind, ChangeKind::Modified | ChangeKind::TypeChanged) {
+                    staged(format!("  {}{}", label(change.kind), change.path));
+                }
             }
 
-            let mut head = IndexMap::new();
+            let renames = gitlet::rename::detect_renames(&deleted, &added, find_renames, |sha| {
+                Ok(repo.read_object(sha)?.data)
+            })?;
 
-            // transform the tree into a dict<path, sha1>
-            tree_to_dict(&repo, "HEAD", &PathBuf::from(""), &mut head)?;
+            for rename in renames {
+                staged(format!("  renamed: {} -> {}", rename.from, rename.to));
+                deleted.shift_remove(&rename.from);
+                added.shift_remove(&rename.to);
+            }
 
-            println!("Changes to be committed:");
-            // then compare with the index
-            for entry in &index.entries {
-                if let Some(sha) = head.get(&entry.name) {
-                    if sha != &entry.sha {
-                        println!("  modified: {}", entry.name);
-                    }
-                    head.remove(&entry.name);
-                } else {
-                    println!("  added:   {}", entry.name);
-                }
+            for name in added.keys() {
+                staged(format!("  {}{}", label(ChangeKind::Added), name));
             }
 
-            for (name, _) in head {
-                println!("  deleted: {}", name);
+            for name in deleted.keys() {
+                staged(format!("  {}{}", label(ChangeKind::Deleted), name));
             }
 
             // part 3: changes not staged for commit
             println!("Changes not staged for commit:");
 
-            let ignore = repo.read_ignore()?;
-
-            let mut all_files = IndexSet::new();
+            for change in status.unstaged() {
+                unstaged(format!("  {}{}", label(change.kind), change.path));
+            }
 
-            for entry in walkdir::WalkDir::new(&repo.work_tree) {
-                let entry = entry.context("failed to read entry")?;
+            println!();
 
-                let path = entry.path();
+            println!("Untracked files:");
 
-                if (path.is_dir() || path.starts_with(&repo.git_dir))
-                    || (path.starts_with(repo.git_dir.with_file_name(".git")))
-                {
-                    continue;
-                }
+            for path in &status.untracked {
+                unstaged(format!("  {}", path));
+            }
 
-                all_files.insert(path.to_owned());
+            let dirty = !status.unstaged().is_empty() || !status.untracked.is_empty();
+            if !dirty && repo.staged_tree_matches_head()? {
+                println!();
+                println!("nothing to commit, working tree clean");
             }
+        }
+        Commands::Rm { path } => {
+            let repo = Repository::find(".")?;
 
-            for entry in &index.entries {
-                let abs_path = repo.work_tree.join(&entry.name);
+            repo.rm(&path, true, false)?;
+        }
+        Commands::Add { path, patch } => {
+            let repo = Repository::find(".")?;
 
-                if !abs_path.exists() {
-                    println!("  deleted: {}", entry.name);
-                } else {
-                    let meta = abs_path.metadata()?;
+            if patch {
+                // the header of a file is printed before its first hunk
+                let mut shown = String::new();
 
-                    // Compare metadata
-                    let ctime_ns = entry.ctime.0 as i64 * 1_000_000_000 + entry.ctime.1 as i64;
-                    let mtime_ns = entry.mtime.0 as i64 * 1_000_000_000 + entry.mtime.1 as i64;
+                repo.add_patch(|path, hunk| {
+                    if shown != path {
+                        println!("diff --git a/{0} b/{0}\n--- a/{0}\n+++ b/{0}", path);
+                        shown = path.to_string();
+                    }
+                    print!("{}", hunk);
 
-                    // todo we should deal with symlink here
-                    // todo git modify ctime and mtime after status command
-                    if meta.ctime_nsec() != ctime_ns || meta.mtime_nsec() != mtime_ns {
-                        let data = std::fs::read(&abs_path)?;
-                        let object = GitObject::new(Fmt::Blob, data.into());
+                    loop {
+                        print!("Stage this hunk [y,n,q]? ");
+                        std::io::Write::flush(&mut std::io::stdout())?;
 
-                        let hash = gitlet::utils::sha(&object.serialize()?);
-                        if hash != entry.sha {
-                            println!("  modified: {}", entry.name);
+                        let mut answer = String::new();
+                        if std::io::stdin().read_line(&mut answer)? == 0 {
+                            return Ok(None);
+                        }
+
+                        match answer.trim() {
+                            "y" => return Ok(Some(true)),
+                            "n" => return Ok(Some(false)),
+                            "q" => return Ok(None),
+                            _ => println!(
+                                "y - stage this hunk\nn - do not stage this hunk\nq - quit"
+                            ),
                         }
                     }
+                })?;
+            } else {
+                repo.add(&path)?;
+            }
+        }
+        Commands::Commit {
+            message,
+            file,
+            allow_empty,
+            no_verify,
+        } => {
+            let repo = Repository::find(".")?;
+
+            let message = match (message, file) {
+                (Some(message), _) => message,
+                (None, Some(file)) if file.as_os_str() == "-" => {
+                    std::io::read_to_string(std::io::stdin()).context("failed to read message")?
                 }
-                all_files.remove(&repo.work_tree.join(&entry.name));
+                (None, Some(file)) => std::fs::read_to_string(&file)
+                    .context(format!("could not read log file '{}'", file.display()))?,
+                (None, None) => unreachable!("clap requires a message or a file"),
+            };
+
+            ensure!(
+                allow_empty || !repo.staged_tree_matches_head()?,
+                "nothing to commit, working tree clean"
+            );
+
+            let sha1 = repo.commit(message, !no_verify)?;
+
+            println!("commit {}", sha1)
+        }
+        Commands::Config {
+            list,
+            show_origin,
+            name,
+            value,
+        } => {
+            let mut repo = Repository::find(".")?;
+
+            if let Some(name) = name {
+                if let Some(value) = value {
+                    return repo.set_config(&name, &value);
+                }
+
+                match repo.read_config()?.get_key(&name)? {
+                    Some(value) => println!("{}", value),
+                    None => std::process::exit(1),
+                }
+
+                return Ok(());
             }
 
-            println!();
+            ensure!(
+                list,
+                "no action given, use --list to show the configuration"
+            );
 
-            println!("Untracked files:");
+            for entry in repo.config_entries()? {
+                if show_origin {
+                    print!("file:{}\t", entry.origin.display());
+                }
 
-            for path in all_files {
-                let path = path.strip_prefix(&repo.work_tree)?;
-                if ignore.check(&path.to_string_lossy())?.unwrap_or(false) {
-                    continue;
+                match entry.value {
+                    Some(value) => println!("{}={}", entry.key, value),
+                    None => println!("{}", entry.key),
                 }
-                println!("  {}", path.display());
             }
         }
-        Commands::Rm { path } => {
+        Commands::Merge { branch } => {
             let repo = Repository::find(".")?;
 
-            repo.rm(&path, true, false)?;
+            match repo.merge(&branch)? {
+                Merge::UpToDate => println!("Already up to date."),
+                Merge::FastForward(_) => println!("Fast-forward"),
+            }
         }
-        Commands::Add { path } => {
+        Commands::Rebase { onto } => {
             let repo = Repository::find(".")?;
+            let branch = repo.active_branch()?;
 
-            repo.add(&path)?;
+            match repo.rebase(&onto)? {
+                Rebase::UpToDate => println!("Current branch {} is up to date.", branch),
+                Rebase::FastForward(sha) => println!("Fast-forwarded {} to {}.", branch, sha),
+                Rebase::Rebased(_) => {
+                    println!("Successfully rebased and updated refs/heads/{}.", branch)
+                }
+            }
         }
-        Commands::Commit { message } => {
+        Commands::Bisect { command } => {
             let repo = Repository::find(".")?;
 
-            let sha1 = repo.commit(message)?;
+            let step = match command {
+                BisectCommand::Start => return repo.bisect_start(),
+                BisectCommand::Reset => return repo.bisect_reset(),
+                BisectCommand::Good { rev } => repo.bisect_mark(&rev, true)?,
+                BisectCommand::Bad { rev } => repo.bisect_mark(&rev, false)?,
+            };
 
-            println!("commit {}", sha1)
+            match step {
+                Bisect::Waiting => println!("status: waiting for both good and bad commits"),
+                Bisect::Testing { sha, remaining } => {
+                    let steps = (remaining + 1).ilog2();
+                    println!(
+                        "Bisecting: {} revisions left to test after this (roughly {} steps)",
+                        remaining, steps
+                    );
+                    println!("[{}]", sha);
+                }
+                Bisect::Found(sha) => println!("{} is the first bad commit", sha),
+            }
+        }
+        Commands::Notes { add, object } => {
+            let repo = Repository::find(".")?;
+
+            match add {
+                Some(message) => {
+                    repo.notes_add(&object, &message)?;
+                }
+                None => match repo.note(&object)? {
+                    Some(note) => print!("{}", note),
+                    None => anyhow::bail!("no note found for object {}", object),
+                },
+            }
+        }
+        Commands::Reflog {
+            expire,
+            expire_days,
+        } => {
+            let repo = Repository::find(".")?;
+
+            if expire {
+                let cutoff = chrono::Utc::now().timestamp() - i64::from(expire_days) * 24 * 3600;
+                repo.reflog_expire(cutoff)?;
+            } else {
+                for (i, entry) in repo.reflog("HEAD")?.iter().rev().enumerate() {
+                    let sha = repo.abbrev(&entry.new, 7)?;
+                    println!("{} HEAD@{{{}}}: {}", sha, i, entry.message);
+                }
+            }
+        }
+        Commands::Push {
+            remote,
+            refspec,
+            force,
+        } => {
+            let mut repo = Repository::find(".")?;
+            repo.set_progress(Stderr::default());
+
+            println!("To {}", repo.remote_path(&remote)?.display());
+
+            match repo.push(&remote, refspec.as_deref(), force)? {
+                Push::UpToDate => println!("Everything up-to-date"),
+                Push::Updated { old, new, .. } => match old {
+                    Some(old) => println!(" {}..{}", repo.abbrev(&old, 7)?, repo.abbrev(&new, 7)?),
+                    None => println!(" * [new ref] {}", repo.abbrev(&new, 7)?),
+                },
+            }
+        }
+        Commands::Fetch { remote, depth } => {
+            let mut repo = Repository::find(".")?;
+            repo.set_progress(Stderr::default());
+
+            println!("From {}", repo.remote_path(&remote)?.display());
+
+            for (branch, sha) in repo.fetch(&remote, depth)? {
+                println!(" {} {}", repo.abbrev(&sha, 7)?, branch);
+            }
+        }
+        Commands::MergeBase { is_ancestor, a, b } => {
+            let repo = Repository::find(".")?;
+
+            if is_ancestor {
+                if !repo.is_ancestor(&a, &b)? {
+                    std::process::exit(1);
+                }
+            } else {
+                match repo.merge_base(&a, &b)? {
+                    Some(sha) => println!("{}", sha),
+                    None => std::process::exit(1),
+                }
+            }
+        }
+        Commands::RevParse {
+            verify,
+            abbrev_ref,
+            short,
+            rev,
+        } => {
+            let repo = Repository::find(".")?;
+
+            if abbrev_ref {
+                println!("{}", repo.abbrev_ref(&rev)?);
+            } else if short {
+                println!("{}", repo.abbrev(&repo.verify_object(&rev)?, 7)?);
+            } else if verify {
+                println!("{}", repo.verify_object(&rev)?);
+            } else {
+                let sha = repo
+                    .resolve_object(&rev)?
+                    .ok_or(anyhow::anyhow!("unknown revision: {}", rev))?;
+                println!("{}", sha);
+            }
         }
     }
     Ok(())
 }
+
+/// let the user write a message in `$GIT_EDITOR` or `$EDITOR`, starting from a template
+///
+/// the file is kept in the git directory, as git does
+fn edit_message(repo: &Repository, file_name: &str, template: &str) -> anyhow::Result<String> {
+    let editor = std::env::var("GIT_EDITOR")
+        .or_else(|_| std::env::var("EDITOR"))
+        .ok()
+        .filter(|editor| !editor.is_empty())
+        .context("no editor is set, use -m to give the message or set EDITOR")?;
+
+    let path = repo.git_dir.join(file_name);
+    std::fs::write(&path, template).context(format!("failed to write {}", file_name))?;
+
+    // the editor may have arguments, let the shell split them
+    let status = std::process::Command::new("sh")
+        .arg("-c")
+        .arg(format!("{} \"$1\"", editor))
+        .arg(&editor)
+        .arg(&path)
+        .status()
+        .context(format!("failed to run editor: {}", editor))?;
+    ensure!(status.success(), "editor {} failed: {}", editor, status);
+
+    std::fs::read_to_string(&path).context(format!("failed to read {}", file_name))
+}
+
+/// whether to color a patch printed to stdout, `color.diff` overrides `color.ui`
+fn diff_color(repo: &Repository) -> anyhow::Result<bool> {
+    let config = repo.read_config()?;
+
+    color::stdout_enabled(
+        config
+            .get("color", "diff")
+            .or_else(|| config.get("color", "ui"))
+            .as_deref(),
+    )
+}
@@ -0,0 +1,112 @@
+//! Three-way merge of trees.
+//!
+//! Trees are compared as flattened dicts of path -> (mode, blob sha), as
+//! [crate::repository::Repository::read_tree_recursive] returns them, a path is merged
+//! as a whole: when both sides changed the same path differently, it is a conflict.
+
+use indexmap::IndexMap;
+
+/// the result of a three-way tree merge
+#[derive(Debug, PartialEq)]
+pub struct TreeMerge<V> {
+    /// the merged dict of path -> entry, conflicting paths keep our version
+    pub merged: IndexMap<String, V>,
+    /// paths changed differently on both sides
+    pub conflicts: Vec<String>,
+}
+
+/// merge the changes from base to theirs into ours
+///
+/// entries are compared whole, so a changed mode is a change like a changed sha
+pub fn merge_trees<V: Clone + PartialEq>(
+    base: &IndexMap<String, V>,
+    ours: &IndexMap<String, V>,
+    theirs: &IndexMap<String, V>,
+) -> TreeMerge<V> {
+    let mut result = TreeMerge {
+        merged: IndexMap::new(),
+        conflicts: vec![],
+    };
+
+    let paths = ours
+        .keys()
+        .chain(theirs.keys())
+        .chain(base.keys())
+        .collect::<indexmap::IndexSet<_>>();
+
+    for path in paths {
+        let base = base.get(path);
+        let ours = ours.get(path);
+        let theirs = theirs.get(path);
+
+        let merged = if ours == theirs || base == theirs {
+            ours
+        } else if base == ours {
+            theirs
+        } else {
+            result.conflicts.push(path.clone());
+            ours
+        };
+
+        if let Some(entry) = merged {
+            result.merged.insert(path.clone(), entry.clone());
+        }
+    }
+
+    result.merged.sort_keys();
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dict(entries: &[(&str, &str)]) -> IndexMap<String, String> {
+        entries
+            .iter()
+            .map(|(path, sha)| (path.to_string(), sha.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_merge_trees() {
+        let base = dict(&[("a", "1"), ("b", "1"), ("c", "1"), ("d", "1")]);
+        // ours modifies b and deletes c
+        let ours = dict(&[("a", "1"), ("b", "2"), ("d", "1")]);
+        // theirs modifies a, deletes d and adds e
+        let theirs = dict(&[("a", "2"), ("b", "1"), ("c", "1"), ("e", "1")]);
+
+        let result = merge_trees(&base, &ours, &theirs);
+
+        assert_eq!(result.merged, dict(&[("a", "2"), ("b", "2"), ("e", "1")]));
+        assert!(result.conflicts.is_empty());
+    }
+
+    #[test]
+    fn test_merge_trees_conflict() {
+        let base = dict(&[("a", "1"), ("b", "1")]);
+        let ours = dict(&[("a", "2"), ("c", "1")]);
+        let theirs = dict(&[("a", "3"), ("c", "2")]);
+
+        let result = merge_trees(&base, &ours, &theirs);
+
+        assert_eq!(result.conflicts, vec!["a".to_string(), "c".to_string()]);
+        assert_eq!(result.merged, dict(&[("a", "2"), ("c", "1")]));
+    }
+
+    #[test]
+    fn test_merge_trees_modes() {
+        let entry = |mode: &str, sha: &str| (mode.to_string(), sha.to_string());
+
+        let base = IndexMap::from([("run.sh".to_string(), entry("100644", "1"))]);
+        let ours = base.clone();
+        // theirs only makes the file executable
+        let theirs = IndexMap::from([("run.sh".to_string(), entry("100755", "1"))]);
+
+        let result = merge_trees(&base, &ours, &theirs);
+
+        assert_eq!(result.merged, theirs);
+        assert!(result.conflicts.is_empty());
+    }
+}
@@ -10,7 +10,11 @@ use clap::ValueEnum;
 use std::io::Read;
 use std::path::PathBuf;
 
-/// Read and write git objects, do the serialization and deserialization with compression
+/// An in-memory git object, with its header and payload.
+///
+/// This only does the (de)serialization of the `<fmt> <length>\0<data>` format,
+/// reading and writing the compressed objects in the object store is done by
+/// [crate::repository::Repository].
 #[derive(Debug)]
 pub struct GitObject {
     pub header: Header,
@@ -52,25 +56,12 @@ impl GitObject {
     }
 
     pub fn from_bytes(mut bytes: Bytes) -> anyhow::Result<Self> {
-        let (fmt, rest) = bytes
-            .split_once(|&x| x == b' ')
-            .context("failed to split objects fmt")?;
-        let (length, rest) = rest
-            .split_once(|&x| x == b'\0')
-            .context("failed to split objects length")?;
-
-        let fmt = std::str::from_utf8(fmt).context("failed to parse objects fmt")?;
+        let (fmt, length, rest) = Self::parse_header(&bytes)?;
 
         let fmt = Fmt::from_str(fmt, true)
             .map_err(|e| anyhow::anyhow!(e))
             .context(format!("failed to parse objects fmt {}", fmt))?;
 
-        let length = std::str::from_utf8(length).context("failed to parse objects length")?;
-
-        let length = length
-            .parse::<usize>()
-            .context("failed to parse objects length")?;
-
         anyhow::ensure!(rest.len() == length, "objects length mismatch");
 
         bytes.advance(bytes.len() - rest.len());
@@ -83,7 +74,40 @@ impl GitObject {
         })
     }
 
+    /// split a serialized object into its raw type name, its length and its data
+    ///
+    /// the type is not required to be one of [Fmt], so objects of unknown types
+    /// can still be inspected
+    pub fn parse_header(bytes: &[u8]) -> anyhow::Result<(&str, usize, &[u8])> {
+        let (fmt, rest) = bytes
+            .split_once(|&x| x == b' ')
+            .context("failed to split objects fmt")?;
+        let (length, rest) = rest
+            .split_once(|&x| x == b'\0')
+            .context("failed to split objects length")?;
+
+        let fmt = std::str::from_utf8(fmt).context("failed to parse objects fmt")?;
+
+        let length = std::str::from_utf8(length).context("failed to parse objects length")?;
+
+        let length = length
+            .parse::<usize>()
+            .context("failed to parse objects length")?;
+
+        Ok((fmt, length, rest))
+    }
+
+    /// read a file as a blob
+    ///
+    /// only [Fmt::Blob] is accepted: the content of a file is the payload of a blob as is,
+    /// other objects are built from their parsed form and serialized.
     pub fn from_file(path: impl Into<PathBuf>, fmt: Fmt) -> anyhow::Result<Self> {
+        anyhow::ensure!(
+            fmt == Fmt::Blob,
+            "objects can only be read from a file as a blob, not a {}",
+            fmt.to_str()
+        );
+
         let mut file = std::fs::File::open(path.into())?;
         let mut data = Vec::new();
         file.read_to_end(&mut data)?;
@@ -104,10 +128,43 @@ impl GitObject {
 
         Ok(data.into())
     }
+
+    /// serialize an object of any type, without validating the type or the data
+    ///
+    /// this is what `hash-object --literally` writes, e.g. to test fsck against malformed objects
+    pub fn serialize_literally(fmt: &str, data: &[u8]) -> anyhow::Result<Bytes> {
+        anyhow::ensure!(
+            !fmt.is_empty() && !fmt.contains([' ', '\0']),
+            "invalid objects type: {:?}",
+            fmt
+        );
+
+        let mut bytes = BytesMut::new();
+
+        bytes.extend_from_slice(fmt.as_bytes());
+        bytes.put_u8(b' ');
+        bytes.extend_from_slice(data.len().to_string().as_bytes());
+        bytes.put_u8(b'\0');
+        bytes.extend_from_slice(data);
+
+        Ok(bytes.into())
+    }
+
+    /// compute the sha of the object, without writing it
+    pub fn sha(&self) -> anyhow::Result<String> {
+        Ok(crate::utils::sha(&self.serialize()?))
+    }
 }
 
+/// the content of the object, a tree is printed as `ls-tree` does
 impl std::fmt::Display for GitObject {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.header.fmt == Fmt::Tree {
+            if let Ok(tree) = tree::Tree::from_bytes(self.data.clone()) {
+                return write!(f, "{}", tree);
+            }
+        }
+
         write!(f, "{}", String::from_utf8_lossy(&self.data))
     }
 }
@@ -118,3 +175,22 @@ pub trait GitObjectTrait {
         Self: Sized;
     fn serialize(&self) -> anyhow::Result<Bytes>;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_file_blob_only() {
+        let path =
+            std::env::temp_dir().join(format!("gitlet-test-from-file-{}", std::process::id()));
+        std::fs::write(&path, "hello").unwrap();
+
+        let blob = GitObject::from_file(&path, Fmt::Blob).unwrap();
+        assert_eq!(blob.header.length, 5);
+
+        assert!(GitObject::from_file(&path, Fmt::Tree).is_err());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,7 +1,9 @@
 use crate::objects::kvlm::Kvlm;
 use crate::objects::GitObjectTrait;
+use crate::utils::parse_date;
+use anyhow::Context;
 use bytes::Bytes;
-use chrono::{DateTime, Offset};
+use chrono::{DateTime, FixedOffset};
 
 /// A tree object, the contents of a worktree, files and directories;
 /// contains following fields:
@@ -35,7 +37,8 @@ impl Commit {
         tree: String,
         parent: Option<String>,
         author: String,
-        time: DateTime<chrono::Local>,
+        author_time: DateTime<FixedOffset>,
+        committer_time: DateTime<FixedOffset>,
         message: String,
     ) -> Self {
         let mut kvlm = Kvlm::default();
@@ -47,24 +50,73 @@ impl Commit {
             Some(())
         });
 
-        let offset = time.offset().fix().local_minus_utc();
-
-        let hours = offset / 3600;
-        let minutes = (offset % 3600) / 60;
-
-        let tz = format!("{:>+03}{:02}", hours, minutes);
-
-        let time = format!("{} {}", time.timestamp(), tz);
-
-        kvlm.insert("author".to_string(), vec![format!("{} {}", author, time)]);
+        kvlm.insert(
+            "author".to_string(),
+            vec![format!("{} {}", author, format_time(author_time))],
+        );
         kvlm.insert(
             "committer".to_string(),
-            vec![format!("{} {}", author, time)],
+            vec![format!("{} {}", author, format_time(committer_time))],
         );
         kvlm.insert("message".to_string(), vec![message]);
 
         Self { kvlm }
     }
+
+    /// the name of the author, the author line without its email and date
+    pub fn author_name(&self) -> anyhow::Result<&str> {
+        Ok(parse_signature(self.author().context("commit has no author")?)?.0)
+    }
+
+    /// the email of the author, without the angle brackets
+    pub fn author_email(&self) -> anyhow::Result<&str> {
+        Ok(parse_signature(self.author().context("commit has no author")?)?.1)
+    }
+
+    /// when the commit was authored, in the author's timezone
+    pub fn author_time(&self) -> anyhow::Result<DateTime<FixedOffset>> {
+        Ok(parse_signature(self.author().context("commit has no author")?)?.2)
+    }
+
+    /// when the commit was made, in the committer's timezone
+    pub fn committer_time(&self) -> anyhow::Result<DateTime<FixedOffset>> {
+        Ok(parse_signature(self.committer().context("commit has no committer")?)?.2)
+    }
+
+    /// replace the author line, `name <email> timestamp timezone`
+    ///
+    /// this is used to keep the original author when a commit is replayed
+    pub fn with_author(mut self, author: String) -> Self {
+        self.kvlm.insert("author".to_string(), vec![author]);
+        self
+    }
+}
+
+/// format a time as `<unix timestamp> <+hhmm>`, as stored in commit and tag objects
+pub(crate) fn format_time(time: DateTime<FixedOffset>) -> String {
+    let offset = time.offset().local_minus_utc();
+
+    let sign = if offset < 0 { '-' } else { '+' };
+    let offset = offset.abs();
+
+    let tz = format!("{}{:02}{:02}", sign, offset / 3600, (offset % 3600) / 60);
+
+    format!("{} {}", time.timestamp(), tz)
+}
+
+/// split an author, committer or tagger line, `name <email> timestamp timezone`, into
+/// its name, email and time
+///
+/// a line without a timezone is taken as UTC, like git does
+pub(crate) fn parse_signature(line: &str) -> anyhow::Result<(&str, &str, DateTime<FixedOffset>)> {
+    let (name, rest) = line
+        .split_once('<')
+        .context(format!("invalid signature: {}", line))?;
+    let (email, date) = rest
+        .split_once('>')
+        .context(format!("invalid signature: {}", line))?;
+
+    Ok((name.trim_end(), email, parse_date(date)?))
 }
 
 impl GitObjectTrait for Commit {
@@ -78,3 +130,34 @@ impl GitObjectTrait for Commit {
         Ok(self.kvlm.serialize())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_signature() {
+        let commit = Commit::from_bytes(Bytes::from_static(
+            b"tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904\n\
+              author A U Thor <author@example.com> 1700000000 +0800\n\
+              committer C O Mitter <committer@example.com> 1700000060\n\
+              \n\
+              message\n",
+        ))
+        .unwrap();
+
+        assert_eq!(commit.author_name().unwrap(), "A U Thor");
+        assert_eq!(commit.author_email().unwrap(), "author@example.com");
+
+        let time = commit.author_time().unwrap();
+        assert_eq!(time.timestamp(), 1700000000);
+        assert_eq!(time.offset().local_minus_utc(), 8 * 3600);
+
+        // no timezone, UTC
+        let time = commit.committer_time().unwrap();
+        assert_eq!(time.timestamp(), 1700000060);
+        assert_eq!(time.offset().local_minus_utc(), 0);
+
+        assert!(parse_signature("no email 1700000000 +0000").is_err());
+    }
+}
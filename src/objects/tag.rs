@@ -1,6 +1,6 @@
 use crate::objects::kvlm::Kvlm;
+use crate::objects::{Fmt, GitObjectTrait};
 use bytes::Bytes;
-use crate::objects::GitObjectTrait;
 
 /// A Tag object contains following fields:
 ///
@@ -9,7 +9,7 @@ use crate::objects::GitObjectTrait;
 /// 3. tagger: the identity of the person who created the tag;
 /// 4. message: the message associated with the tag.
 ///
-/// object can be a commit, a tree, a blob.
+/// object can be a commit, a tree, a blob or another tag, its kind is recorded as `type`.
 pub struct Tag {
     kvlm: Kvlm,
 }
@@ -22,10 +22,10 @@ impl Tag {
         message
     }
 
-    pub fn new(tag: String, object: String, tagger: String, message: String) -> Self {
+    pub fn new(tag: String, object: String, fmt: Fmt, tagger: String, message: String) -> Self {
         let mut kvlm = Kvlm::default();
-        kvlm.insert("objects".to_string(), vec![object]);
-        kvlm.insert("type".to_string(), vec!["commit".to_string()]);
+        kvlm.insert("object".to_string(), vec![object]);
+        kvlm.insert("type".to_string(), vec![fmt.to_str().to_string()]);
         kvlm.insert("tag".to_string(), vec![tag]);
         kvlm.insert("tagger".to_string(), vec![tagger]);
         kvlm.insert("message".to_string(), vec![message]);
@@ -38,7 +38,7 @@ impl GitObjectTrait for Tag {
     fn from_bytes(data: Bytes) -> anyhow::Result<Self> {
         let kvlm = Kvlm::parse(data)?;
 
-        anyhow::ensure!(kvlm.contains_key("objects"), "missing field objects");
+        anyhow::ensure!(kvlm.contains_key("object"), "missing field object");
         anyhow::ensure!(kvlm.contains_key("type"), "missing field type");
         anyhow::ensure!(kvlm.contains_key("tag"), "missing field tag");
         anyhow::ensure!(kvlm.contains_key("tagger"), "missing field tagger");
@@ -51,3 +51,58 @@ impl GitObjectTrait for Tag {
         Ok(self.kvlm.serialize())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tag_type_of_target() {
+        let tag = Tag::new(
+            "v1".to_string(),
+            "aa".to_string(),
+            Fmt::Blob,
+            "t <t@t.com> 0 +0000".to_string(),
+            "a blob\n".to_string(),
+        );
+
+        let tag = Tag::from_bytes(tag.serialize().unwrap()).unwrap();
+        assert_eq!(tag.kvlm.get_single("type").unwrap(), "blob");
+    }
+
+    #[test]
+    fn test_tag_round_trip_with_git() {
+        // written by `git tag -a v1.0 -m "Release 1.0"`
+        let data = Bytes::from_static(
+            b"object 739ce881ffdd46d6d069707244c04cfd3c8c68a0\n\
+            type commit\n\
+            tag v1.0\n\
+            tagger Tester <tester@example.com> 1700000000 +0000\n\
+            \n\
+            Release 1.0\n",
+        );
+
+        let tag = Tag::from_bytes(data.clone()).unwrap();
+        assert_eq!(
+            tag.object().unwrap(),
+            "739ce881ffdd46d6d069707244c04cfd3c8c68a0"
+        );
+        assert_eq!(tag.tag().unwrap(), "v1.0");
+        assert_eq!(tag.serialize().unwrap(), data);
+
+        // gitlet writes the same bytes, so the tag has the sha git gave it
+        let written = Tag::new(
+            "v1.0".to_string(),
+            "739ce881ffdd46d6d069707244c04cfd3c8c68a0".to_string(),
+            Fmt::Commit,
+            "Tester <tester@example.com> 1700000000 +0000".to_string(),
+            "Release 1.0\n".to_string(),
+        );
+        let object = crate::objects::GitObject::new(Fmt::Tag, written.serialize().unwrap());
+        assert_eq!(object.data, data);
+        assert_eq!(
+            object.sha().unwrap(),
+            "f45859d647f9669db6ff7f73e812f9bb2d536ea6"
+        );
+    }
+}
@@ -12,12 +12,87 @@ use std::path::PathBuf;
 pub struct Tree(pub Vec<TreeEntry>);
 
 impl Tree {
-    pub fn insert(&mut self, entry: TreeEntry) {
+    /// insert an entry into the tree
+    ///
+    /// returns an error if an entry with the same path already exists,
+    /// git trees must not contain duplicate names
+    pub fn insert(&mut self, entry: TreeEntry) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            !self.0.iter().any(|e| e.path == entry.path),
+            "duplicate tree entry: {}",
+            entry.path.display()
+        );
+
         self.0.push(entry);
+
+        Ok(())
+    }
+
+    /// compare the entries of two trees at a single level, sub-trees are not recursed into
+    ///
+    /// entries are matched by name, and the changes are in the tree sort order
+    pub fn diff(&self, other: &Tree) -> Vec<TreeChange> {
+        let mut changes = vec![];
+
+        for old in &self.0 {
+            match other.0.iter().find(|new| new.path == old.path) {
+                None => changes.push(TreeChange::Removed(old.clone())),
+                Some(new) if new.mode[0..2] != old.mode[0..2] => {
+                    changes.push(TreeChange::TypeChanged {
+                        old: old.clone(),
+                        new: new.clone(),
+                    })
+                }
+                Some(new) if new.sha1 != old.sha1 || new.mode != old.mode => {
+                    changes.push(TreeChange::Modified {
+                        old: old.clone(),
+                        new: new.clone(),
+                    })
+                }
+                Some(_) => {}
+            }
+        }
+
+        for new in &other.0 {
+            if !self.0.iter().any(|old| old.path == new.path) {
+                changes.push(TreeChange::Added(new.clone()));
+            }
+        }
+
+        changes.sort_by_key(|change| change.entry().sort_key());
+
+        changes
     }
 }
 
-#[derive(Debug)]
+/// a change between two trees, see [Tree::diff]
+#[derive(Debug, PartialEq)]
+pub enum TreeChange {
+    Added(TreeEntry),
+    Removed(TreeEntry),
+    /// the content or the permissions changed
+    Modified {
+        old: TreeEntry,
+        new: TreeEntry,
+    },
+    /// e.g. a file became a directory or a symlink
+    TypeChanged {
+        old: TreeEntry,
+        new: TreeEntry,
+    },
+}
+
+impl TreeChange {
+    /// the entry the change is about, the new one when there are two
+    pub fn entry(&self) -> &TreeEntry {
+        match self {
+            TreeChange::Added(entry) | TreeChange::Removed(entry) => entry,
+            TreeChange::Modified { new, .. } | TreeChange::TypeChanged { new, .. } => new,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub struct TreeEntry {
     pub mode: String,
     pub path: PathBuf,
@@ -32,12 +107,32 @@ impl TreeEntry {
 
         anyhow::ensure!(FileType::from_octal(&mode[0..2]).is_ok(), "invalid mode");
 
+        // a tree entry holds exactly one path component, nested paths are stored in sub-trees
+        let name = path.to_str().context("invalid path")?;
+        anyhow::ensure!(
+            !name.is_empty() && name != "." && name != ".." && !name.contains('/'),
+            "invalid tree entry path: {:?}",
+            name
+        );
+
         Ok(Self { mode, path, sha1 })
     }
 
     pub fn file_type(&self) -> anyhow::Result<FileType> {
         FileType::from_octal(&self.mode[0..2])
     }
+
+    /// the key git sorts tree entries by: the name, with a trailing `/` for sub-trees
+    pub fn sort_key(&self) -> String {
+        // unwrap is safe because the path is checked when creating the tree entry
+        let mut key = self.path.to_str().unwrap().to_string();
+
+        if self.mode.starts_with("04") {
+            key.push('/');
+        }
+
+        key
+    }
 }
 #[derive(PartialEq)]
 pub enum FileType {
@@ -68,17 +163,42 @@ impl FileType {
         .to_string()
     }
 
+    /// the type of the object the entry points at, a symlink is stored as a blob
     pub fn to_str(&self) -> &str {
         match self {
             FileType::Tree => "tree",
-            FileType::Blob => "blob",
-            FileType::SymLink => "symLink",
+            FileType::Blob | FileType::SymLink => "blob",
             FileType::Commit => "commit",
         }
     }
 }
 
-impl Tree {}
+/// one `mode type sha\tpath` line per entry, as `ls-tree` prints them
+impl std::fmt::Display for Tree {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for entry in &self.0 {
+            writeln!(f, "{}", entry)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// `mode type sha\tpath`, the line of `ls-tree`
+impl std::fmt::Display for TreeEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let file_type = self.file_type().map_err(|_| std::fmt::Error)?;
+
+        write!(
+            f,
+            "{} {} {}\t{}",
+            self.mode,
+            file_type.to_str(),
+            self.sha1,
+            self.path.display()
+        )
+    }
+}
 
 impl GitObjectTrait for Tree {
     /// `[mode] space [path] 0x00 [sha-1]`
@@ -156,25 +276,7 @@ impl GitObjectTrait for Tree {
 
         let mut data: Vec<&TreeEntry> = self.0.iter().collect();
 
-        data.sort_by(|a, b| {
-            let path_a = a.path.clone();
-            let path_b = b.path.clone();
-
-            // unwrap is safe because we have checked the file type when creating the tree entry
-            let mut path_a_str = path_a.to_str().unwrap().to_string();
-
-            if let FileType::Tree = a.file_type().expect("invalid file type") {
-                path_a_str.push('/');
-            }
-
-            let mut path_b_str = path_b.to_str().unwrap().to_string();
-
-            if let FileType::Tree = b.file_type().expect("invalid file type") {
-                path_b_str.push('/');
-            }
-
-            path_a_str.cmp(&path_b_str)
-        });
+        data.sort_by_key(|entry| entry.sort_key());
 
         for TreeEntry { mode, path, sha1 } in data {
             bytes.put_slice(mode.trim_start_matches('0').as_bytes());
@@ -210,5 +312,88 @@ mod test {
         assert_eq!(&tree.0[0].sha1, "be0c80f03e9bfa51999c6c8746b9e358124d53ef");
 
         assert_eq!(tree.serialize().unwrap(), raw);
+        assert_eq!(
+            tree.to_string(),
+            "100644 blob be0c80f03e9bfa51999c6c8746b9e358124d53ef\t.gitignore\n"
+        );
+
+        assert_eq!(Tree::from_bytes(Bytes::new()).unwrap().to_string(), "");
+    }
+
+    #[test]
+    fn test_tree_insert_duplicate() {
+        let sha1 = "be0c80f03e9bfa51999c6c8746b9e358124d53ef".to_string();
+        let mut tree = Tree::default();
+
+        tree.insert(
+            TreeEntry::try_new("100644".to_string(), PathBuf::from("foo"), sha1.clone()).unwrap(),
+        )
+        .unwrap();
+
+        let result = tree
+            .insert(TreeEntry::try_new("100644".to_string(), PathBuf::from("foo"), sha1).unwrap());
+
+        assert!(result.is_err());
+        assert_eq!(tree.0.len(), 1);
+    }
+
+    #[test]
+    fn test_tree_entry_invalid_path() {
+        let sha1 = "be0c80f03e9bfa51999c6c8746b9e358124d53ef".to_string();
+
+        for path in ["a/b", "..", "."] {
+            let result =
+                TreeEntry::try_new("100644".to_string(), PathBuf::from(path), sha1.clone());
+            let err = result.unwrap_err().to_string();
+            assert!(err.contains(path), "error should name the path: {}", err);
+        }
+
+        assert!(TreeEntry::try_new("100644".to_string(), PathBuf::from(""), sha1).is_err());
+    }
+
+    #[test]
+    fn test_tree_diff() {
+        let entry = |mode: &str, path: &str, sha: char| {
+            TreeEntry::try_new(
+                mode.to_string(),
+                PathBuf::from(path),
+                sha.to_string().repeat(40),
+            )
+            .unwrap()
+        };
+
+        let old = Tree(vec![
+            entry("100644", "a", '1'),
+            entry("100644", "b", '1'),
+            entry("100644", "c", '1'),
+            entry("100644", "d", '1'),
+        ]);
+        let new = Tree(vec![
+            entry("100644", "e", '1'),
+            entry("40000", "d", '2'),
+            entry("100755", "c", '1'),
+            entry("100644", "a", '2'),
+        ]);
+
+        assert_eq!(
+            old.diff(&new),
+            vec![
+                TreeChange::Modified {
+                    old: entry("100644", "a", '1'),
+                    new: entry("100644", "a", '2'),
+                },
+                TreeChange::Removed(entry("100644", "b", '1')),
+                TreeChange::Modified {
+                    old: entry("100644", "c", '1'),
+                    new: entry("100755", "c", '1'),
+                },
+                TreeChange::TypeChanged {
+                    old: entry("100644", "d", '1'),
+                    new: entry("40000", "d", '2'),
+                },
+                TreeChange::Added(entry("100644", "e", '1')),
+            ]
+        );
+        assert!(old.diff(&old).is_empty());
     }
 }
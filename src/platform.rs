@@ -0,0 +1,121 @@
+//! The parts of the file system which differ between unix and windows: the stat data
+//! recorded in the index, file owners, the executable bit and symlinks.
+
+use std::fs::Metadata;
+use std::io;
+use std::path::Path;
+
+/// the stat data of a file as an index entry records it, truncated to 32 bits like git does
+///
+/// times are split into seconds and the nanoseconds within that second
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Stat {
+    pub ctime: (u32, u32),
+    pub mtime: (u32, u32),
+    pub dev: u32,
+    pub ino: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub size: u32,
+}
+
+#[cfg(unix)]
+impl Stat {
+    pub fn from_metadata(metadata: &Metadata) -> Self {
+        use std::os::unix::fs::MetadataExt;
+
+        Self {
+            ctime: (metadata.ctime() as u32, metadata.ctime_nsec() as u32),
+            mtime: (metadata.mtime() as u32, metadata.mtime_nsec() as u32),
+            dev: metadata.dev() as u32,
+            ino: metadata.ino() as u32,
+            uid: metadata.uid(),
+            gid: metadata.gid(),
+            size: metadata.size() as u32,
+        }
+    }
+}
+
+#[cfg(windows)]
+impl Stat {
+    /// windows has no device, inode or owner, git for windows leaves them 0 and takes
+    /// the creation time of the file as its ctime
+    pub fn from_metadata(metadata: &Metadata) -> Self {
+        let time = |time: io::Result<std::time::SystemTime>| {
+            time.ok()
+                .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
+                .map(|since| (since.as_secs() as u32, since.subsec_nanos()))
+                .unwrap_or_default()
+        };
+
+        Self {
+            ctime: time(metadata.created()),
+            mtime: time(metadata.modified()),
+            size: metadata.len() as u32,
+            ..Default::default()
+        }
+    }
+}
+
+/// whether a file has an executable bit set, never on windows
+#[cfg(unix)]
+pub fn is_executable(metadata: &Metadata) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    metadata.permissions().mode() & 0o111 != 0
+}
+
+/// whether a file has an executable bit set, never on windows
+#[cfg(windows)]
+pub fn is_executable(_metadata: &Metadata) -> bool {
+    false
+}
+
+/// set or clear the executable bits of a file, for those who may read it as git does
+#[cfg(unix)]
+pub fn set_executable(path: impl AsRef<Path>, executable: bool) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mut permissions = std::fs::metadata(&path)?.permissions();
+    let mode = permissions.mode();
+    permissions.set_mode(if executable {
+        mode | (mode & 0o444) >> 2
+    } else {
+        mode & !0o111
+    });
+
+    std::fs::set_permissions(path, permissions)
+}
+
+/// windows files have no executable bit, this does nothing
+#[cfg(windows)]
+pub fn set_executable(_path: impl AsRef<Path>, _executable: bool) -> io::Result<()> {
+    Ok(())
+}
+
+/// create a symlink at path pointing to target
+#[cfg(unix)]
+pub fn symlink(target: impl AsRef<Path>, path: impl AsRef<Path>) -> io::Result<()> {
+    std::os::unix::fs::symlink(target, path)
+}
+
+/// create a symlink at path pointing to target, git links files only on windows
+#[cfg(windows)]
+pub fn symlink(target: impl AsRef<Path>, path: impl AsRef<Path>) -> io::Result<()> {
+    std::os::windows::fs::symlink_file(target, path)
+}
+
+/// the name of a user and of a group, None on windows where files have no owner
+#[cfg(unix)]
+pub fn owner_names(uid: u32, gid: u32) -> (Option<String>, Option<String>) {
+    (
+        users::get_user_by_uid(uid).map(|user| user.name().to_string_lossy().into_owned()),
+        users::get_group_by_gid(gid).map(|group| group.name().to_string_lossy().into_owned()),
+    )
+}
+
+/// the name of a user and of a group, None on windows where files have no owner
+#[cfg(windows)]
+pub fn owner_names(_uid: u32, _gid: u32) -> (Option<String>, Option<String>) {
+    (None, None)
+}
@@ -0,0 +1,64 @@
+//! Reporting the progress of long operations, such as checkout, fetch and push.
+//!
+//! A repository reports to [Silent] by default, see [Repository::set_progress] to
+//! report elsewhere.
+//!
+//! [Repository::set_progress]: crate::repository::Repository::set_progress
+
+use std::io::{IsTerminal, Write};
+
+/// receives the progress of the operations of a repository
+pub trait Progress {
+    /// `done` of the `total` steps of the operation `title` are complete
+    ///
+    /// the last update of an operation has `done == total`
+    fn update(&mut self, title: &str, done: usize, total: usize);
+}
+
+/// drops every update
+#[derive(Debug, Default)]
+pub struct Silent;
+
+impl Progress for Silent {
+    fn update(&mut self, _title: &str, _done: usize, _total: usize) {}
+}
+
+/// prints a counter to stderr, as `Checking out files: 50% (2/4)`, when it is a terminal
+#[derive(Debug)]
+pub struct Stderr {
+    enabled: bool,
+    /// the last percentage printed, to print only when it changes
+    percent: Option<usize>,
+}
+
+impl Default for Stderr {
+    fn default() -> Self {
+        Self {
+            enabled: std::io::stderr().is_terminal(),
+            percent: None,
+        }
+    }
+}
+
+impl Progress for Stderr {
+    fn update(&mut self, title: &str, done: usize, total: usize) {
+        if !self.enabled || total == 0 {
+            return;
+        }
+
+        let percent = done * 100 / total;
+        if self.percent == Some(percent) && done != total {
+            return;
+        }
+        self.percent = Some(percent);
+
+        let mut stderr = std::io::stderr().lock();
+        // progress is best effort, a failed write must not fail the operation
+        let _ = write!(stderr, "\r{}: {}% ({}/{})", title, percent, done, total);
+
+        if done == total {
+            let _ = writeln!(stderr, ", done.");
+            self.percent = None;
+        }
+    }
+}
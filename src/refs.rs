@@ -2,5 +2,101 @@
 //!
 //! Refs can also refer to another reference, and thus only indirectly to an objects.
 
-mod branch;
+pub mod branch;
 pub mod tag;
+
+/// Check a ref name against git's `check-ref-format` rules, one-level names are allowed.
+///
+/// The error names the offending part of the name.
+pub fn check_ref_name(name: &str) -> anyhow::Result<()> {
+    anyhow::ensure!(!name.is_empty(), "invalid ref name: empty");
+    anyhow::ensure!(name != "@", "invalid ref name '@'");
+
+    if let Some(c) = name
+        .chars()
+        .find(|c| c.is_ascii_control() || " ~^:?*[\\".contains(*c))
+    {
+        anyhow::bail!(
+            "invalid ref name '{}': contains {:?}",
+            name.escape_debug(),
+            c
+        );
+    }
+
+    for forbidden in ["..", "@{", "//"] {
+        anyhow::ensure!(
+            !name.contains(forbidden),
+            "invalid ref name '{}': contains '{}'",
+            name,
+            forbidden
+        );
+    }
+
+    anyhow::ensure!(
+        !name.starts_with('/') && !name.ends_with('/'),
+        "invalid ref name '{}': starts or ends with '/'",
+        name
+    );
+    anyhow::ensure!(
+        !name.ends_with('.'),
+        "invalid ref name '{}': ends with '.'",
+        name
+    );
+
+    for component in name.split('/') {
+        anyhow::ensure!(
+            !component.starts_with('.'),
+            "invalid ref name '{}': component '{}' starts with '.'",
+            name,
+            component
+        );
+        anyhow::ensure!(
+            !component.ends_with(".lock"),
+            "invalid ref name '{}': component '{}' ends with '.lock'",
+            name,
+            component
+        );
+    }
+
+    Ok(())
+}
+
+/// whether a ref name follows git's rules, see [check_ref_name]
+pub fn is_valid_ref_name(name: &str) -> bool {
+    check_ref_name(name).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ref_names() {
+        for name in ["master", "feature/x", "refs/heads/v1.0", "a-b_c", "HEAD"] {
+            assert!(is_valid_ref_name(name), "{}", name);
+        }
+
+        for name in [
+            "",
+            "@",
+            "with space",
+            "a..b",
+            "/master",
+            "master/",
+            "a//b",
+            "master.",
+            "a/.hidden",
+            "master.lock",
+            "x@{1}",
+            "a:b",
+            "what?",
+            "tab\there",
+            "back\\slash",
+        ] {
+            assert!(!is_valid_ref_name(name), "{:?}", name);
+        }
+
+        let err = check_ref_name("with space").unwrap_err().to_string();
+        assert!(err.contains("' '"), "{}", err);
+    }
+}
@@ -9,7 +9,8 @@
 ///
 /// # Detached Head
 /// Detached head is when HEAD points directly to a commit, instead of a branch.
-pub struct _Branch {
+#[derive(Debug, PartialEq)]
+pub struct Branch {
     pub name: String,
     pub sha: String,
 }
@@ -1,13 +1,36 @@
 use crate::repository::Repository;
 
+#[derive(Debug, PartialEq)]
 pub struct Tag {
     tag: String,
     object: String,
+    /// the object an annotated tag points at, None for lightweight tags
+    peeled: Option<String>,
 }
 
 impl Tag {
     pub fn new(tag: String, object: String) -> Self {
-        Self { tag, object }
+        Self {
+            tag,
+            object,
+            peeled: None,
+        }
+    }
+
+    pub fn with_peeled(self, peeled: Option<String>) -> Self {
+        Self { peeled, ..self }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.tag
+    }
+
+    pub fn object(&self) -> &str {
+        &self.object
+    }
+
+    pub fn peeled(&self) -> Option<&str> {
+        self.peeled.as_deref()
     }
 
     pub fn read_from(repo: &Repository, tag: String) -> anyhow::Result<Self> {
@@ -20,7 +43,13 @@ impl Tag {
     }
 
     pub fn write_to(&self, repo: &Repository) -> anyhow::Result<()> {
+        crate::refs::check_ref_name(&self.tag)?;
+
         let tag_path = repo.git_dir.join("refs").join("tags").join(&self.tag);
+        // tag names may contain slashes
+        if let Some(parent) = tag_path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
         std::fs::write(tag_path, self.object.as_bytes())?;
         Ok(())
     }
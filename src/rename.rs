@@ -0,0 +1,168 @@
+//! Rename detection between deleted and added paths.
+//!
+//! A deleted and an added path holding the same blob are always paired as a rename.
+//! With a threshold, paths with similar content are paired too: the similarity is the
+//! share of lines the two blobs have in common, as a percentage.
+
+use bytes::Bytes;
+use indexmap::IndexMap;
+use std::collections::HashMap;
+
+/// a deleted path paired with an added one
+#[derive(Debug, PartialEq)]
+pub struct Rename {
+    pub from: String,
+    pub to: String,
+    /// the similarity of the contents, 100 for an exact rename
+    pub score: u8,
+}
+
+/// pair deleted and added paths, given as dicts of path -> blob sha
+///
+/// inexact renames are only detected when a threshold is given, `read` loads the
+/// content of a blob for them. Each path is part of at most one rename.
+pub fn detect_renames(
+    deleted: &IndexMap<String, String>,
+    added: &IndexMap<String, String>,
+    threshold: Option<u8>,
+    read: impl Fn(&str) -> anyhow::Result<Bytes>,
+) -> anyhow::Result<Vec<Rename>> {
+    let mut renames = vec![];
+    let mut deleted: IndexMap<_, _> = deleted.clone();
+    let mut added: IndexMap<_, _> = added.clone();
+
+    // exact renames, the blob did not change
+    for (to, sha) in added.clone() {
+        if let Some(from) = deleted
+            .iter()
+            .find(|(_, s)| **s == sha)
+            .map(|(p, _)| p.clone())
+        {
+            deleted.shift_remove(&from);
+            added.shift_remove(&to);
+            renames.push(Rename {
+                from,
+                to,
+                score: 100,
+            });
+        }
+    }
+
+    let Some(threshold) = threshold else {
+        return Ok(renames);
+    };
+
+    // inexact renames, best scores first
+    let mut contents = HashMap::new();
+    for sha in deleted.values().chain(added.values()) {
+        if !contents.contains_key(sha) {
+            contents.insert(sha.clone(), read(sha)?);
+        }
+    }
+
+    let mut candidates = vec![];
+    for (from, from_sha) in &deleted {
+        for (to, to_sha) in &added {
+            let score = similarity(&contents[from_sha], &contents[to_sha]);
+            if score >= threshold {
+                candidates.push((score, from.clone(), to.clone()));
+            }
+        }
+    }
+    candidates.sort_by(|a, b| {
+        b.0.cmp(&a.0)
+            .then_with(|| a.1.cmp(&b.1))
+            .then_with(|| a.2.cmp(&b.2))
+    });
+
+    for (score, from, to) in candidates {
+        if deleted.contains_key(&from) && added.contains_key(&to) {
+            deleted.shift_remove(&from);
+            added.shift_remove(&to);
+            renames.push(Rename { from, to, score });
+        }
+    }
+
+    Ok(renames)
+}
+
+/// the percentage of lines shared by two contents
+pub fn similarity(a: &[u8], b: &[u8]) -> u8 {
+    let lines = |data: &[u8]| {
+        let mut counts: HashMap<Vec<u8>, usize> = HashMap::new();
+        for line in data.split_inclusive(|&c| c == b'\n') {
+            *counts.entry(line.to_vec()).or_default() += 1;
+        }
+        counts
+    };
+
+    let (a, b) = (lines(a), lines(b));
+    let total: usize = a.values().sum::<usize>() + b.values().sum::<usize>();
+
+    if total == 0 {
+        return 100;
+    }
+
+    let common: usize = a
+        .iter()
+        .map(|(line, count)| (*count).min(b.get(line).copied().unwrap_or(0)))
+        .sum();
+
+    (common * 2 * 100 / total) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dict(entries: &[(&str, &str)]) -> IndexMap<String, String> {
+        entries
+            .iter()
+            .map(|(path, sha)| (path.to_string(), sha.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_detect_renames_exact() {
+        let deleted = dict(&[("a", "1"), ("b", "2")]);
+        let added = dict(&[("c", "2"), ("d", "3")]);
+
+        let renames = detect_renames(&deleted, &added, None, |_| unreachable!()).unwrap();
+
+        assert_eq!(
+            renames,
+            vec![Rename {
+                from: "b".to_string(),
+                to: "c".to_string(),
+                score: 100
+            }]
+        );
+    }
+
+    #[test]
+    fn test_detect_renames_inexact() {
+        let deleted = dict(&[("a", "1"), ("b", "2")]);
+        let added = dict(&[("c", "3")]);
+        let read = |sha: &str| {
+            Ok(Bytes::from(match sha {
+                "1" => "one\ntwo\nthree\nfour\n",
+                "2" => "something\nelse\n",
+                _ => "one\ntwo\nthree\nfive\n",
+            }))
+        };
+
+        let renames = detect_renames(&deleted, &added, Some(50), read).unwrap();
+        assert_eq!(
+            renames,
+            vec![Rename {
+                from: "a".to_string(),
+                to: "c".to_string(),
+                score: 75
+            }]
+        );
+
+        assert!(detect_renames(&deleted, &added, Some(80), read)
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -1,37 +1,297 @@
 use crate::ignore::GitIgnore;
-use crate::index::Index;
-use crate::objects::tree::{Tree, TreeEntry};
+use crate::index::{sha_from_hex, Index, IndexEntry};
+use crate::objects::tree::{FileType, Tree, TreeEntry};
 use crate::objects::{Fmt, GitObject, GitObjectTrait};
-use crate::utils::sha;
+use crate::progress::{Progress, Silent};
+use crate::refs::branch::Branch;
+use crate::refs::tag::Tag;
+use crate::utils::{cleanup_message, parse_date, repo_path, sha, LockFile, NULL_SHA};
 use anyhow::Context;
 use bytes::Bytes;
-use indexmap::{IndexMap, IndexSet};
+use indexmap::IndexMap;
+use sha1::Digest;
+use std::cell::RefCell;
 use std::collections::HashMap;
+use std::ffi::OsString;
 use std::fs;
 use std::io::{Read, Write};
 use std::ops::Deref;
-use std::os::macos::fs::MetadataExt;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+mod bisect;
+mod diff;
+mod filters;
+mod hooks;
+mod notes;
+mod pack;
+mod reflog;
+mod remote;
+mod show;
+mod status;
+mod walk;
+
+pub use bisect::Bisect;
+pub use reflog::ReflogEntry;
+pub use remote::Push;
+pub use status::{Change, ChangeKind, Status, StatusEntry};
+pub use walk::Commits;
+
+/// special refs stored at the top of the git directory, written by operations
+/// such as reset, merge, rebase and fetch
+const PSEUDO_REFS: [&str; 4] = ["ORIG_HEAD", "MERGE_HEAD", "FETCH_HEAD", "REBASE_HEAD"];
+
+/// the time to record in a commit, the environment variable `var` overrides the current time
+fn signature_time(var: &str) -> anyhow::Result<chrono::DateTime<chrono::FixedOffset>> {
+    parse_signature_time(var, std::env::var(var).ok())
+}
+
+/// the time to record in a commit from the value of the environment variable `var`
+fn parse_signature_time(
+    var: &str,
+    value: Option<String>,
+) -> anyhow::Result<chrono::DateTime<chrono::FixedOffset>> {
+    match value {
+        Some(date) => parse_date(&date).context(format!("invalid {}", var)),
+        None => Ok(chrono::Local::now().fixed_offset()),
+    }
+}
 
 /// a gitlet repository
 pub struct Repository {
     pub work_tree: PathBuf,
     pub git_dir: PathBuf,
     pub config: RepoConfig,
+    /// the index file, `index` in the git directory unless `GIT_INDEX_FILE` is set
+    index_file: PathBuf,
+    /// where long operations report their progress
+    progress: RefCell<Box<dyn Progress>>,
+    /// the merged config, read on first use, see [Repository::reload_config]
+    merged_config: RefCell<Option<Rc<RepoConfig>>>,
+    /// the parsed pack indexes, read on first use
+    packs: RefCell<Option<Rc<pack::Packs>>>,
 }
 
 #[derive(Debug)]
 pub struct RepoConfig(configparser::ini::Ini);
 
 impl RepoConfig {
-    pub fn user(&self) -> Option<String> {
-        let name = self.get("user", "name")?;
-        let email = self.get("user", "email")?;
+    pub fn user(&self) -> User {
+        match (self.get("user", "name"), self.get("user", "email")) {
+            (Some(name), Some(email)) => User::Identity(format!("{} <{}>", name, email)),
+            (None, Some(_)) => User::MissingName,
+            (Some(_), None) => User::MissingEmail,
+            (None, None) => User::MissingBoth,
+        }
+    }
+
+    /// the value of `section.key`, or `section.subsection.key`
+    pub fn get_key(&self, name: &str) -> anyhow::Result<Option<String>> {
+        let (section, key) = split_config_key(name)?;
+
+        Ok(self.get(&section, &key))
+    }
+}
+
+/// split a `section.key` or `section.subsection.key` name into the ini section and key
+///
+/// the subsection is quoted, as in `[remote "origin"]`
+fn split_config_key(name: &str) -> anyhow::Result<(String, String)> {
+    let (section, subsection, key) = config_key_parts(name)?;
+
+    let section = match subsection {
+        Some(subsection) => format!("{} \"{}\"", section, subsection),
+        None => section.to_string(),
+    };
+
+    Ok((section, key.to_string()))
+}
+
+/// split a `section.key` or `section.subsection.key` name into its parts
+fn config_key_parts(name: &str) -> anyhow::Result<(&str, Option<&str>, &str)> {
+    let (section, rest) = name
+        .split_once('.')
+        .context(format!("key does not contain a section: {}", name))?;
+
+    let (subsection, key) = match rest.rsplit_once('.') {
+        Some((subsection, key)) => (Some(subsection), key),
+        None => (None, rest),
+    };
+    anyhow::ensure!(
+        !section.is_empty() && !key.is_empty(),
+        "invalid key: {}",
+        name
+    );
+
+    Ok((section, subsection, key))
+}
+
+/// set `name` to value in the text of a config file, as `git config <name> <value>` does
+///
+/// only the line of the key changes, the rest of the file is kept as is. A key missing
+/// from its section is added after the last line of the section, a missing section is
+/// added at the end. Section and key names are case-insensitive, subsections are not.
+fn set_config_line(text: &str, name: &str, value: &str) -> anyhow::Result<String> {
+    let (section, subsection, key) = config_key_parts(name)?;
+
+    let mut lines: Vec<String> = text.lines().map(str::to_string).collect();
+    let mut in_section = false;
+    // the line after which a missing key goes, and the lines holding the key
+    let mut section_end = None;
+    let mut matches = vec![];
+
+    for (i, line) in lines.iter().enumerate() {
+        let trimmed = line.trim_start();
+
+        if let Some(header) = trimmed.strip_prefix('[') {
+            in_section = is_config_section(header, section, subsection);
+        } else if !in_section || trimmed.is_empty() || trimmed.starts_with(['#', ';']) {
+            continue;
+        } else {
+            let name_end = trimmed
+                .find(|c: char| c == '=' || c.is_whitespace())
+                .unwrap_or(trimmed.len());
+            if trimmed[..name_end].eq_ignore_ascii_case(key) {
+                matches.push(i);
+            }
+        }
+
+        if in_section {
+            section_end = Some(i);
+        }
+    }
+
+    match (matches.as_slice(), section_end) {
+        ([i], _) => {
+            let line = &lines[*i];
+            let indent = line.len() - line.trim_start().len();
+            let name_len = line[indent..]
+                .find(|c: char| c == '=' || c.is_whitespace())
+                .unwrap_or(line.len() - indent);
+
+            lines[*i] = format!("{} = {}", &line[..indent + name_len], config_value(value));
+        }
+        ([], Some(end)) => lines.insert(end + 1, format!("\t{} = {}", key, config_value(value))),
+        ([], None) => {
+            lines.push(match subsection {
+                Some(subsection) => format!(
+                    "[{} \"{}\"]",
+                    section,
+                    subsection.replace('\\', "\\\\").replace('"', "\\\"")
+                ),
+                None => format!("[{}]", section),
+            });
+            lines.push(format!("\t{} = {}", key, config_value(value)));
+        }
+        _ => anyhow::bail!(
+            "cannot overwrite multiple values of {} with a single value",
+            name
+        ),
+    }
+
+    Ok(lines.join("\n") + "\n")
+}
+
+/// whether the section header, after its `[`, names section and subsection
+///
+/// `[section "subsection"]` compares the subsection case-sensitively, the older
+/// `[section.subsection]` does not
+fn is_config_section(header: &str, section: &str, subsection: Option<&str>) -> bool {
+    let Some((header, _)) = header.split_once(']') else {
+        return false;
+    };
+
+    match header.split_once(|c: char| c.is_whitespace()) {
+        Some((name, quoted)) => {
+            let quoted = quoted.trim();
+            let name_matches = name.eq_ignore_ascii_case(section);
+            let unquoted = quoted
+                .strip_prefix('"')
+                .and_then(|quoted| quoted.strip_suffix('"'))
+                .map(|quoted| quoted.replace("\\\"", "\"").replace("\\\\", "\\"));
+
+            name_matches && unquoted.as_deref() == subsection
+        }
+        None => match (header.split_once('.'), subsection) {
+            (Some((name, sub)), Some(subsection)) => {
+                name.eq_ignore_ascii_case(section) && sub.eq_ignore_ascii_case(subsection)
+            }
+            (None, None) => header.eq_ignore_ascii_case(section),
+            _ => false,
+        },
+    }
+}
+
+/// a config value as written in a config file, quoted when spaces around it or a
+/// comment character would be lost
+fn config_value(value: &str) -> String {
+    let escaped = value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+        .replace('\t', "\\t");
+
+    if value.starts_with(char::is_whitespace)
+        || value.ends_with(char::is_whitespace)
+        || value.contains(['#', ';'])
+    {
+        format!("\"{}\"", escaped)
+    } else {
+        escaped
+    }
+}
+
+/// path made absolute with its parent directory resolved, but not its last component,
+/// so a symlink stays the symlink and not what it points to
+fn absolute_path(path: &Path) -> anyhow::Result<PathBuf> {
+    let (Some(parent), Some(name)) = (path.parent(), path.file_name()) else {
+        // `.`, `..` or a root
+        return Ok(path.canonicalize()?);
+    };
+
+    let parent = if parent.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        parent
+    };
+
+    Ok(parent.canonicalize()?.join(name))
+}
+
+/// the user identity configured by `user.name` and `user.email`
+#[derive(Debug, PartialEq)]
+pub enum User {
+    /// both are set, formatted as `name <email>`
+    Identity(String),
+    MissingName,
+    MissingEmail,
+    MissingBoth,
+}
 
-        Some(format!("{} <{}>", name, email))
+impl User {
+    /// get the identity, or an error telling which part is missing
+    pub fn identity(self) -> anyhow::Result<String> {
+        match self {
+            User::Identity(identity) => Ok(identity),
+            User::MissingName => anyhow::bail!("author identity unknown: user.name is not set"),
+            User::MissingEmail => anyhow::bail!("author identity unknown: user.email is not set"),
+            User::MissingBoth => {
+                anyhow::bail!("author identity unknown: user.name and user.email are not set")
+            }
+        }
     }
 }
 
+/// a single entry of a config file
+#[derive(Debug)]
+pub struct ConfigEntry {
+    /// the config file the entry is read from
+    pub origin: PathBuf,
+    /// `section.key`, or `section.subsection.key`
+    pub key: String,
+    pub value: Option<String>,
+}
+
 impl Deref for RepoConfig {
     type Target = configparser::ini::Ini;
 
@@ -53,15 +313,21 @@ impl Default for RepoConfig {
 }
 
 impl Repository {
-    /// Load a repository at path.
+    /// Load the repository at path, without looking at the environment or at the parent
+    /// directories as [Self::find] does.
     pub fn load(working_dir: impl Into<PathBuf>) -> anyhow::Result<Self> {
         let working_dir = working_dir.into();
         let git_dir = working_dir.join(".gitlet");
 
+        Self::open(working_dir, git_dir)
+    }
+
+    /// Load a repository from an explicit git directory and work tree.
+    fn open(work_tree: PathBuf, git_dir: PathBuf) -> anyhow::Result<Self> {
         anyhow::ensure!(
             git_dir.exists(),
             "not a gitlet repository (or any of the parent directories): {}",
-            working_dir.display()
+            work_tree.display()
         );
 
         // Read configuration file in .git/config
@@ -72,9 +338,13 @@ impl Repository {
             .map_err(|e| anyhow::anyhow!(e))?;
 
         Ok(Self {
-            work_tree: working_dir,
+            work_tree,
+            index_file: git_dir.join("index"),
             git_dir,
             config: RepoConfig(config),
+            progress: RefCell::new(Box::new(Silent)),
+            merged_config: RefCell::new(None),
+            packs: RefCell::new(None),
         })
     }
 
@@ -109,6 +379,20 @@ impl Repository {
         fs::create_dir_all(git_dir.join("refs/tags")).context("failed to create tags directory")?;
         fs::create_dir_all(git_dir.join("refs/heads"))
             .context("failed to create heads directory")?;
+        fs::create_dir_all(git_dir.join("hooks")).context("failed to create hooks directory")?;
+        fs::create_dir_all(git_dir.join("info")).context("failed to create info directory")?;
+
+        fs::File::create(git_dir.join("info").join("exclude"))
+            .context("failed to create exclude file")?
+            .write_all(
+                b"# gitlet ls-files --others --exclude-from=.gitlet/info/exclude\n\
+                # Lines that start with '#' are comments.\n\
+                # For a project mostly in C, the following would be a good set of\n\
+                # exclude patterns (uncomment them if you want to use them):\n\
+                # *.[oa]\n\
+                # *~\n",
+            )
+            .context("failed to write exclude file")?;
 
         fs::File::create(git_dir.join("description"))
             .context("failed to create description file")?
@@ -129,50 +413,272 @@ impl Repository {
 
         Ok(Self {
             work_tree,
+            index_file: git_dir.join("index"),
             git_dir,
             config,
+            progress: RefCell::new(Box::new(Silent)),
+            merged_config: RefCell::new(None),
+            packs: RefCell::new(None),
         })
     }
 
+    /// Find the repository containing work_dir, by walking up its parents.
+    ///
+    /// As git does, `GIT_DIR` overrides the discovery and names the git directory
+    /// to use, with work_dir as the work tree. `GIT_WORK_TREE` overrides the work tree,
+    /// and `GIT_INDEX_FILE` the index file.
     pub fn find(work_dir: impl Into<PathBuf>) -> anyhow::Result<Repository> {
+        Self::find_with(work_dir, |name| std::env::var_os(name))
+    }
+
+    /// [Self::find], reading the environment variables from `env`
+    fn find_with(
+        work_dir: impl Into<PathBuf>,
+        env: impl Fn(&str) -> Option<OsString>,
+    ) -> anyhow::Result<Repository> {
+        let mut repo = Self::discover(work_dir, &env)?;
+
+        if let Some(index_file) = env("GIT_INDEX_FILE") {
+            repo.index_file = std::path::absolute(index_file).context("invalid GIT_INDEX_FILE")?;
+        }
+
+        Ok(repo)
+    }
+
+    /// find the git directory and the work tree, see [Self::find]
+    fn discover(
+        work_dir: impl Into<PathBuf>,
+        env: impl Fn(&str) -> Option<OsString>,
+    ) -> anyhow::Result<Repository> {
         let mut path = work_dir.into().canonicalize()?;
 
+        let work_tree = match env("GIT_WORK_TREE") {
+            Some(work_tree) => Some(
+                PathBuf::from(work_tree)
+                    .canonicalize()
+                    .context("invalid GIT_WORK_TREE")?,
+            ),
+            None => None,
+        };
+
+        if let Some(git_dir) = env("GIT_DIR") {
+            let git_dir = PathBuf::from(git_dir)
+                .canonicalize()
+                .context("invalid GIT_DIR")?;
+
+            return Repository::open(work_tree.unwrap_or(path), git_dir);
+        }
+
         while !path.join(".gitlet").exists() {
             if !path.pop() {
                 anyhow::bail!("No gitlet repository found");
             }
         }
 
-        Repository::load(path)
+        let git_dir = path.join(".gitlet");
+
+        Repository::open(work_tree.unwrap_or(path), git_dir)
     }
 
+    /// all the refs of the repository, loose and packed, by full name such as
+    /// `refs/heads/master`
+    ///
+    /// sorted by name, a loose ref overrides a packed one with the same name
     pub fn refs(&self) -> anyhow::Result<IndexMap<String, String>> {
-        let refs_path = self.git_dir.join("refs");
-        let prefix = PathBuf::from(&self.git_dir);
+        Ok(self
+            .refs_under("refs/")?
+            .into_iter()
+            .map(|(name, sha)| (format!("refs/{}", name), sha))
+            .collect())
+    }
 
+    /// like [Self::refs], along with the object annotated tags point at
+    ///
+    /// the peeled object is None for refs which do not point at a tag object
+    pub fn peeled_refs(&self) -> anyhow::Result<IndexMap<String, (String, Option<String>)>> {
         let mut dict = IndexMap::new();
 
-        for entry in walkdir::WalkDir::new(&refs_path) {
-            let entry = entry.context(format!("failed to read entry: {}", refs_path.display()))?;
-            if entry.file_type().is_dir() {
-                continue;
+        for (path, sha) in self.refs()? {
+            let peeled = self
+                .find_object(&sha, true)?
+                .filter(|peeled| *peeled != sha);
+
+            dict.insert(path, (sha, peeled));
+        }
+
+        Ok(dict)
+    }
+
+    /// the refs stored in `packed-refs`, by full name
+    ///
+    /// loose refs take precedence over these, see [Self::refs_under]
+    fn packed_refs(&self) -> anyhow::Result<IndexMap<String, String>> {
+        let path = self.git_dir.join("packed-refs");
+
+        if !path.is_file() {
+            return Ok(IndexMap::new());
+        }
+
+        let data = fs::read_to_string(&path).context("failed to read packed-refs file")?;
+
+        // comments start with `#`, and `^<sha>` lines hold the peeled object of the previous tag
+        Ok(data
+            .lines()
+            .filter(|line| !line.starts_with('#') && !line.starts_with('^'))
+            .filter_map(|line| line.split_once(' '))
+            .map(|(sha, name)| (name.to_string(), sha.to_string()))
+            .collect())
+    }
+
+    /// the loose and packed refs under a prefix such as `refs/heads/`, by short name
+    ///
+    /// sorted by name, a loose ref overrides a packed one with the same name
+    fn refs_under(&self, prefix: &str) -> anyhow::Result<Vec<(String, String)>> {
+        let mut dict: IndexMap<String, String> = self
+            .packed_refs()?
+            .into_iter()
+            .filter_map(|(name, sha)| Some((name.strip_prefix(prefix)?.to_string(), sha)))
+            .collect();
+
+        let dir = self.git_dir.join(prefix);
+        if dir.is_dir() {
+            for entry in walkdir::WalkDir::new(&dir) {
+                let entry = entry.context(format!("failed to read entry: {}", dir.display()))?;
+                if entry.file_type().is_dir() {
+                    continue;
+                }
+
+                let path = entry.path();
+                let sha = self
+                    .resolve_ref(path)?
+                    .ok_or_else(|| anyhow::anyhow!("failed to resolve ref: {}", path.display()))?;
+                let name = path
+                    .strip_prefix(&dir)
+                    .unwrap() // this is safe because the walk starts at dir
+                    .display()
+                    .to_string();
+
+                dict.insert(name, sha);
+            }
+        }
+
+        dict.sort_keys();
+
+        Ok(dict.into_iter().collect())
+    }
+
+    /// the branches of the repository, sorted by name
+    pub fn branches(&self) -> anyhow::Result<Vec<Branch>> {
+        Ok(self
+            .refs_under("refs/heads/")?
+            .into_iter()
+            .map(|(name, sha)| Branch { name, sha })
+            .collect())
+    }
+
+    /// create the branch `refs/heads/<name>` at the commit named by start_point
+    ///
+    /// returns the sha of the commit, the branch must not exist yet
+    pub fn create_branch(&self, name: &str, start_point: &str) -> anyhow::Result<String> {
+        let reference = format!("refs/heads/{}", name);
+        crate::refs::check_ref_name(&reference)?;
+
+        anyhow::ensure!(
+            self.resolve_ref(&reference)?.is_none(),
+            "a branch named '{}' already exists",
+            name
+        );
+
+        let sha = self
+            .find_object_peeled(start_point, Some(Fmt::Commit))?
+            .ok_or(anyhow::anyhow!(
+                "not a valid object name: '{}'",
+                start_point
+            ))?;
+
+        self.update_ref(&reference, &sha, Some(NULL_SHA))?;
+
+        Ok(sha)
+    }
+
+    /// delete the branch `refs/heads/<name>`, loose or packed, returns the sha it was at
+    ///
+    /// the branch HEAD is on cannot be deleted
+    pub fn delete_branch(&self, name: &str) -> anyhow::Result<String> {
+        let reference = format!("refs/heads/{}", name);
+        crate::refs::check_ref_name(&reference)?;
+
+        anyhow::ensure!(
+            self.active_branch().ok().as_deref() != Some(name),
+            "cannot delete branch '{}' checked out at '{}'",
+            name,
+            self.work_tree.display()
+        );
+
+        let sha = self
+            .resolve_ref(&reference)?
+            .ok_or(anyhow::anyhow!("branch '{}' not found", name))?;
+
+        let path = self.git_dir.join(&reference);
+        if path.is_file() {
+            fs::remove_file(&path).context(format!("failed to delete ref {}", reference))?;
+
+            // drop the directories left empty, as refs/heads/feature for feature/x
+            let heads = self.git_dir.join("refs/heads");
+            for dir in path.ancestors().skip(1).take_while(|dir| *dir != heads) {
+                if fs::remove_dir(dir).is_err() {
+                    break;
+                }
             }
+        }
+
+        if self.packed_refs()?.contains_key(&reference) {
+            self.remove_packed_ref(&reference)?;
+        }
+
+        Ok(sha)
+    }
 
-            let path = entry.path();
-            let sha = self
-                .resolve_ref(path)?
-                .ok_or_else(|| anyhow::anyhow!("failed to resolve ref: {}", path.display()))?;
+    /// rewrite `packed-refs` without a ref, and the peeled line following it
+    fn remove_packed_ref(&self, reference: &str) -> anyhow::Result<()> {
+        let path = self.git_dir.join("packed-refs");
+        // read under the lock, so a concurrent rewrite is neither lost nor half read
+        let lock = LockFile::acquire(&path).context("cannot lock packed-refs")?;
+        let data = fs::read_to_string(&path).context("failed to read packed-refs file")?;
 
-            let path = path
-                .strip_prefix(&prefix)
-                .unwrap() // this is safe because we know prefix is a parent of path
-                .display()
-                .to_string();
+        let mut kept = String::new();
+        let mut removed = false;
+
+        for line in data.lines() {
+            if line.starts_with('^') && removed {
+                continue;
+            }
 
-            dict.insert(path, sha);
+            removed = line
+                .split_once(' ')
+                .is_some_and(|(_, name)| name == reference);
+            if !removed {
+                kept.push_str(line);
+                kept.push('\n');
+            }
         }
 
-        Ok(dict)
+        lock.commit(kept.as_bytes())
+            .context("failed to write packed-refs file")
+    }
+
+    /// the tags of the repository, sorted by name, annotated tags are peeled
+    pub fn tags(&self) -> anyhow::Result<Vec<Tag>> {
+        self.refs_under("refs/tags/")?
+            .into_iter()
+            .map(|(name, sha)| {
+                let peeled = self
+                    .find_object(&sha, true)?
+                    .filter(|peeled| *peeled != sha);
+
+                Ok(Tag::new(name, sha).with_peeled(peeled))
+            })
+            .collect()
     }
 
     /// Resolve a reference to an git object.
@@ -180,32 +686,73 @@ impl Repository {
     /// Name can be a ref or a git object's sha
     ///
     /// If name is a ref, it will be resolved to a git object's sha using [Self::resolve_ref] first.
-    /// Then if follow is true and the object is a tag object, it will be until a non-tag object is found.
+    /// Then if follow is true and the object is a tag object, it will be until a non-tag object is found,
+    /// see [Self::find_object_peeled] to peel to a given kind of object instead.
     pub fn find_object(&self, name: &str, follow: bool) -> anyhow::Result<Option<String>> {
-        let name = self.resolve_object(name)?;
-        if !follow || name.is_none() {
-            return Ok(name);
+        if follow {
+            self.find_object_peeled(name, None)
+        } else {
+            self.resolve_object(name)
         }
+    }
+
+    /// Resolve a name like [Self::find_object], then peel it.
+    ///
+    /// With `peel_to` None, tags are followed until a non-tag object is found.
+    /// Otherwise tags are followed until an object of that kind is found, so `Fmt::Tag`
+    /// stops at the first tag, and a commit is peeled to its tree for `Fmt::Tree`.
+    /// Reaching an object which cannot be peeled further to that kind is an error.
+    pub fn find_object_peeled(
+        &self,
+        name: &str,
+        peel_to: Option<Fmt>,
+    ) -> anyhow::Result<Option<String>> {
+        let Some(mut name) = self.resolve_object(name)? else {
+            return Ok(None);
+        };
 
         let mut depth = 0;
 
-        // unwrap is safe because we have ensured that name is not None
-        let mut name = name.unwrap();
+        // the tag object we followed to get to name
+        let mut tag: Option<String> = None;
 
         loop {
             anyhow::ensure!(depth < 10, "too many levels of symbolic references");
 
             // todo We read the whole object to get the header, which is not efficient.
-            let object = self.read_object(&name)?;
+            let object = match &tag {
+                Some(tag) => self
+                    .read_object(&name)
+                    .with_context(|| format!("tag {} points at missing object {}", tag, name))?,
+                None => self.read_object(&name)?,
+            };
+            let fmt = object.header.fmt;
 
-            if follow && object.header.fmt == Fmt::Tag {
-                let tag_object = crate::objects::tag::Tag::from_bytes(object.data)?;
-                name = tag_object
+            let target = match (fmt, peel_to) {
+                (fmt, Some(peel_to)) if fmt == peel_to => return Ok(Some(name)),
+                (Fmt::Tag, _) => crate::objects::tag::Tag::from_bytes(object.data)?
                     .object()
                     .context("tag object missing object field")?
-                    .clone();
+                    .clone(),
+                (Fmt::Commit, Some(Fmt::Tree)) => {
+                    crate::objects::commit::Commit::from_bytes(object.data)?
+                        .tree()
+                        .context("commit has no tree")?
+                        .clone()
+                }
+                (_, None) => return Ok(Some(name)),
+                (fmt, Some(peel_to)) => anyhow::bail!(
+                    "object {} is a {}, it cannot be peeled to a {}",
+                    name,
+                    fmt.to_str(),
+                    peel_to.to_str()
+                ),
+            };
+
+            if fmt == Fmt::Tag {
+                tag = Some(std::mem::replace(&mut name, target));
             } else {
-                return Ok(Some(name));
+                name = target;
             }
             depth += 1;
         }
@@ -213,7 +760,8 @@ impl Repository {
 
     /// resolve a reference to sha path
     ///
-    /// The argument is a path to ref file, e.g. "refs/heads/master"
+    /// The argument is a path to ref file, e.g. "refs/heads/master", a ref without a
+    /// loose file is looked up in `packed-refs`
     ///
     /// returns None if the reference cannot be resolved
     // todo deal with recursive refs
@@ -226,31 +774,96 @@ impl Repository {
         // refs/heads/main", but .git/refs/heads/main doesn't exist yet
         // (since there's no commit for it to refer to).
         if !path.is_file() {
-            return Ok(None);
+            let Ok(name) = path.strip_prefix(&self.git_dir) else {
+                return Ok(None);
+            };
+
+            return Ok(self.packed_refs()?.shift_remove(&repo_path(name)?));
         }
 
         let data = fs::read_to_string(&path)
             .context(format!("failed to read ref file: {}", path.display()))?;
 
-        let data = data.trim_end_matches('\n');
+        // only the first line matters, FETCH_HEAD holds one line per fetched ref
+        let data = data.lines().next().unwrap_or("");
 
-        if data.starts_with("ref: ") {
-            self.resolve_ref(&data[5..])
+        if let Some(target) = data.strip_prefix("ref: ") {
+            self.resolve_ref(target)
         } else {
-            Ok(Some(data.to_string()))
+            // FETCH_HEAD lines are `<sha>\t<flags>\t<description>`
+            Ok(data.split_whitespace().next().map(|sha| sha.to_string()))
+        }
+    }
+
+    /// record the current HEAD commit in ORIG_HEAD
+    ///
+    /// this should be called before operations moving HEAD in a destructive way
+    /// (reset, merge, rebase), so that the previous position can be recovered
+    pub fn save_orig_head(&self) -> anyhow::Result<()> {
+        if let Some(head) = self.resolve_ref("HEAD")? {
+            fs::write(self.git_dir.join("ORIG_HEAD"), format!("{}\n", head))
+                .context("failed to write ORIG_HEAD file")?;
+        }
+
+        Ok(())
+    }
+
+    /// Point a ref such as `refs/heads/master` at `new`, if it still points at `old`.
+    ///
+    /// The name must follow git's rules, see [crate::refs::check_ref_name].
+    /// The ref is locked while it is compared and written, so a concurrent update is
+    /// either seen or fails on the lock. An `old` of [NULL_SHA] means the ref must not
+    /// exist, and None skips the comparison. Symbolic refs are not followed.
+    pub fn update_ref(&self, name: &str, new: &str, old: Option<&str>) -> anyhow::Result<()> {
+        self.lock_ref(name, old)?
+            .commit(format!("{}\n", new).as_bytes())
+            .context(format!("failed to update ref {}", name))
+    }
+
+    /// Lock a ref, and check it still points at `old`, as [Self::update_ref] does.
+    ///
+    /// Committing the lock writes the ref, dropping it leaves the ref untouched, so an
+    /// operation can check the ref before changing anything else.
+    fn lock_ref(&self, name: &str, old: Option<&str>) -> anyhow::Result<LockFile> {
+        crate::refs::check_ref_name(name)?;
+
+        let path = self.git_dir.join(name);
+        fs::create_dir_all(path.parent().context("invalid ref")?)?;
+        let lock = LockFile::acquire(&path).context(format!("cannot lock ref {}", name))?;
+
+        if let Some(old) = old {
+            let current = self.resolve_ref(name)?;
+            let current = current.as_deref().unwrap_or(NULL_SHA);
+
+            anyhow::ensure!(
+                current == old,
+                "cannot update ref {}: is at {} but expected {}",
+                name,
+                current,
+                old
+            );
         }
+
+        Ok(lock)
     }
 
     /// resolve a name to a git object's sha
     ///
-    /// the name can be a "HEAD" literal, branch, tag, full sha, or short sha
+    /// the name can be a "HEAD" literal (or "@"), a pseudo ref such as "ORIG_HEAD",
+    /// branch, tag, full sha, or short sha, followed by any number of `~<n>` (the nth
+    /// first-parent ancestor) and `^<n>` (the nth parent, `^0` being the commit itself)
     ///
     /// return None if the name cannot be resolved
     pub fn resolve_object(&self, name: &str) -> anyhow::Result<Option<String>> {
+        // ref names cannot contain ~ or ^, so the first one starts the suffixes
+        if let Some(i) = name.find(['~', '^']) {
+            return self.resolve_ancestry(&name[..i], &name[i..]);
+        }
+
         let mut candidates = vec![];
 
-        // case 1: name is HEAD literal
-        if name == "HEAD" {
+        // case 1: name is HEAD literal, or its alias @
+        if name == "HEAD" || name == "@" {
             // Head is a reference so we can use resolve_ref
             let head = self.resolve_ref("HEAD")?;
             if let Some(head) = head {
@@ -258,34 +871,26 @@ impl Repository {
             }
         }
 
+        // pseudo refs living at the top of the git directory
+        if PSEUDO_REFS.contains(&name) {
+            if let Some(sha) = self.resolve_ref(name)? {
+                candidates.push(sha);
+            }
+        }
+
         // case 2: name is a short or full sha
         let hash_regex = regex::Regex::new(r"^[0-9a-f]{4,40}$").context("invalid regex")?;
 
         if hash_regex.is_match(name) {
             // name is a full or short sha
             let name = name.to_lowercase();
-            let prefix = &name[..2];
-            let path = &name[2..];
-
-            let dir = self.git_dir.join("objects").join(prefix);
-
-            anyhow::ensure!(dir.exists(), "object not found: {}", name);
 
-            // filter out non-files and non-matching files
-            let entries = walkdir::WalkDir::new(dir).into_iter().filter(|e| {
-                e.as_ref().is_ok_and(|e| {
-                    e.file_type().is_file()
-                        && e.file_name()
-                            .to_str()
-                            .map(|s| s.starts_with(path))
-                            .unwrap_or(false)
-                })
-            });
-
-            for entry in entries {
-                let entry = entry.context("failed to read entry")?;
-                let file_name = entry.file_name().to_str().context("invalid file name")?;
-                candidates.push(prefix.to_string() + file_name);
+            if name.len() == 40 {
+                // a full sha names the object directly, it does not have to be stored
+                // as a loose object, e.g. it may live in a pack
+                candidates.push(name);
+            } else {
+                candidates.extend(self.objects_with_prefix(&name)?);
             }
         }
 
@@ -301,7 +906,9 @@ impl Repository {
             candidates.push(branch);
         }
 
-        anyhow::ensure!(candidates.len() <= 1, "ambiguous object name: {}", name,);
+        if candidates.len() > 1 {
+            return Err(self.ambiguous(name, &candidates));
+        }
 
         Ok(if candidates.is_empty() {
             None
@@ -311,10 +918,215 @@ impl Repository {
         })
     }
 
+    /// resolve base, then walk the parents named by suffix, a run of `~<n>` and `^<n>`
+    fn resolve_ancestry(&self, base: &str, suffix: &str) -> anyhow::Result<Option<String>> {
+        let Some(mut sha) = self.resolve_object(base)? else {
+            return Ok(None);
+        };
+
+        // the revision named so far, for error messages
+        let mut rev = base.to_string();
+        let mut rest = suffix;
+
+        while let Some(op) = rest.chars().next() {
+            anyhow::ensure!(
+                op == '~' || op == '^',
+                "invalid revision: {}{}",
+                base,
+                suffix
+            );
+
+            let digits = rest[1..].bytes().take_while(|c| c.is_ascii_digit()).count();
+            let count = match &rest[1..1 + digits] {
+                "" => 1,
+                n => n
+                    .parse::<usize>()
+                    .context(format!("invalid revision: {}{}", base, suffix))?,
+            };
+
+            let commit = self.ancestry_commit(&sha, &rev)?;
+            sha = match op {
+                '~' => {
+                    let mut commit = commit;
+                    for i in 0..count {
+                        sha = commit
+                            .parents()
+                            .and_then(|parents| parents.first())
+                            .context(format!("no such parent: {}~{}", rev, i + 1))?
+                            .clone();
+                        commit = self.ancestry_commit(&sha, &rev)?;
+                    }
+                    sha
+                }
+                _ if count == 0 => self
+                    .find_object_peeled(&sha, Some(Fmt::Commit))?
+                    .context(format!("object not found: {}", rev))?,
+                _ => commit
+                    .parents()
+                    .and_then(|parents| parents.get(count - 1))
+                    .context(format!("no such parent: {}^{}", rev, count))?
+                    .clone(),
+            };
+
+            rev.push_str(&rest[..1 + digits]);
+            rest = &rest[1 + digits..];
+        }
+
+        Ok(Some(sha))
+    }
+
+    /// read the commit named by sha, peeling tags, for the revision rev
+    fn ancestry_commit(
+        &self,
+        sha: &str,
+        rev: &str,
+    ) -> anyhow::Result<crate::objects::commit::Commit> {
+        let peeled = self
+            .find_object_peeled(sha, None)?
+            .context(format!("object not found: {}", rev))?;
+        let object = self.read_object(&peeled)?;
+
+        anyhow::ensure!(
+            object.header.fmt == Fmt::Commit,
+            "{} is a {}, not a commit",
+            rev,
+            object.header.fmt.to_str()
+        );
+
+        crate::objects::commit::Commit::from_bytes(object.data)
+    }
+
+    /// resolve a name to exactly one existing object
+    pub fn verify_object(&self, name: &str) -> anyhow::Result<String> {
+        let sha = self
+            .resolve_object(name)
+            .ok()
+            .flatten()
+            .filter(|sha| self.read_object(sha).is_ok());
+
+        sha.ok_or(anyhow::anyhow!("needed a single revision: {}", name))
+    }
+
+    /// the full sha of a loose object named by a sha or a short sha, which must be unique
+    fn expand_sha(&self, sha: &str) -> anyhow::Result<String> {
+        anyhow::ensure!(
+            sha.len() >= 4 && sha.len() <= 40 && sha.bytes().all(|c| c.is_ascii_hexdigit()),
+            "invalid object name: {}",
+            sha
+        );
+
+        let sha = sha.to_lowercase();
+        if sha.len() == 40 {
+            return Ok(sha);
+        }
+
+        let mut shas = self.objects_with_prefix(&sha)?;
+        if shas.len() > 1 {
+            return Err(self.ambiguous(&sha, &shas));
+        }
+
+        shas.pop().context(format!("objects not found: {}", sha))
+    }
+
+    /// the error for a name matching several objects, listing each candidate with its type
+    fn ambiguous(&self, name: &str, candidates: &[String]) -> anyhow::Error {
+        let mut message = format!("ambiguous object name: {}\nthe candidates are:", name);
+
+        for sha in candidates {
+            let fmt = match self.read_object_header(sha) {
+                Ok((fmt, _)) => fmt,
+                Err(_) => "missing".to_string(),
+            };
+            message.push_str(&format!("\n  {} {}", sha, fmt));
+        }
+
+        anyhow::anyhow!(message)
+    }
+
+    /// list the shas of all objects starting with prefix
+    ///
+    /// prefix must be at least 2 characters long
+    fn objects_with_prefix(&self, prefix: &str) -> anyhow::Result<Vec<String>> {
+        anyhow::ensure!(prefix.len() >= 2, "prefix too short: {}", prefix);
+
+        let dir = self.git_dir.join("objects").join(&prefix[..2]);
+        let rest = &prefix[2..];
+
+        let mut shas = self.packed_objects_with_prefix(prefix)?;
+
+        if !dir.exists() {
+            return Ok(shas);
+        }
+
+        // filter out non-files and non-matching files
+        let entries = walkdir::WalkDir::new(dir).into_iter().filter(|e| {
+            e.as_ref().is_ok_and(|e| {
+                e.file_type().is_file()
+                    && e.file_name()
+                        .to_str()
+                        .map(|s| s.starts_with(rest))
+                        .unwrap_or(false)
+            })
+        });
+
+        for entry in entries {
+            let entry = entry.context("failed to read entry")?;
+            let file_name = entry.file_name().to_str().context("invalid file name")?;
+            shas.push(prefix[..2].to_string() + file_name);
+        }
+
+        // an object may be both loose and packed
+        shas.sort();
+        shas.dedup();
+
+        Ok(shas)
+    }
+
+    /// abbreviate a sha to the shortest prefix, at least min_len long,
+    /// that does not match any other object
+    pub fn abbrev(&self, sha: &str, min_len: usize) -> anyhow::Result<String> {
+        let mut len = min_len.max(4);
+
+        for other in self.objects_with_prefix(&sha[..2])? {
+            if other == sha {
+                continue;
+            }
+
+            let common = sha
+                .chars()
+                .zip(other.chars())
+                .take_while(|(a, b)| a == b)
+                .count();
+
+            len = len.max(common + 1);
+        }
+
+        Ok(sha[..len.min(sha.len())].to_string())
+    }
+
+    /// read an object by its full sha, or by an unambiguous prefix of at least 4 digits
     pub fn read_object(&self, sha: &str) -> anyhow::Result<GitObject> {
+        GitObject::from_bytes(self.read_raw_object(sha)?)
+    }
+
+    /// read the raw type name and the length of an object, which may be of an unknown type
+    pub fn read_object_header(&self, sha: &str) -> anyhow::Result<(String, usize)> {
+        let data = self.read_raw_object(sha)?;
+        let (fmt, length, _) = GitObject::parse_header(&data)?;
+
+        Ok((fmt.to_string(), length))
+    }
+
+    /// read the decompressed bytes of an object, header included
+    fn read_raw_object(&self, sha: &str) -> anyhow::Result<Bytes> {
+        let sha = &self.expand_sha(sha)?;
         let path = self.git_dir.join("objects").join(&sha[..2]).join(&sha[2..]);
 
-        anyhow::ensure!(path.exists(), "objects not found: {}", sha);
+        if !path.exists() {
+            return self
+                .read_packed_object(sha)?
+                .context(format!("objects not found: {}", sha));
+        }
 
         let file = fs::File::open(&path)?;
 
@@ -326,18 +1138,21 @@ impl Repository {
         .read_to_end(&mut data)
         .context("failed to read zlib data")?;
 
-        let data = Bytes::from(data);
-
-        GitObject::from_bytes(data)
+        Ok(Bytes::from(data))
     }
 
     /// write objects to disk
     ///
     /// returns sha of objects
     pub fn write_object(&self, object: &GitObject) -> anyhow::Result<String> {
-        let data = object.serialize()?;
+        self.write_raw_object(&object.serialize()?)
+    }
 
-        let sha = sha(&data);
+    /// write an already serialized object to disk, see [GitObject::serialize_literally]
+    ///
+    /// returns sha of objects
+    pub fn write_raw_object(&self, data: &[u8]) -> anyhow::Result<String> {
+        let sha = sha(data);
 
         let path = self.git_dir.join("objects").join(&sha[..2]).join(&sha[2..]);
 
@@ -345,49 +1160,184 @@ impl Repository {
             return Ok(sha);
         }
 
-        fs::create_dir_all(
-            path.parent()
-                .context(format!("failed to get path parent: {}", path.display()))?,
-        )?;
-
-        let file = fs::File::create(&path)?;
+        let (tmp_path, tmp_file) = self.create_tmp_object()?;
 
-        let mut encoder = flate2::write::ZlibEncoder::new(file, flate2::Compression::default());
+        let result = (|| {
+            let mut encoder =
+                flate2::write::ZlibEncoder::new(tmp_file, flate2::Compression::default());
 
-        encoder
-            .write_all(&data)
-            .context("failed to write zlib data")?;
+            encoder
+                .write_all(data)
+                .context("failed to write zlib data")?;
 
-        encoder.finish().context("failed to write zlib data")?;
+            let file = encoder.finish().context("failed to write zlib data")?;
 
-        Ok(sha)
-    }
+            fs::create_dir_all(
+                path.parent()
+                    .context(format!("failed to get path parent: {}", path.display()))?,
+            )?;
+            self.sync_object(&file, &tmp_path)?;
+            fs::rename(&tmp_path, &path).context(format!("failed to write object {}", sha))?;
 
-    pub fn read_index(&self) -> anyhow::Result<Index> {
-        let index_path = self.git_dir.join("index");
+            Ok(sha)
+        })();
 
-        // New repositories have no index!
-        if !index_path.exists() {
-            return Ok(Index::default());
+        if result.is_err() {
+            let _ = fs::remove_file(&tmp_path);
         }
 
-        let data = fs::read(&index_path).context("failed to read index file")?;
-
-        let data = Bytes::from(data);
-
-        Index::from_bytes(data)
+        result
     }
 
-    pub fn write_index(&self, index: &Index) -> anyhow::Result<()> {
-        let index_path = self.git_dir.join("index");
+    /// create a temporary file in the objects directory, to be renamed to the object's path
+    /// once it is completely written, so a crash never leaves a truncated object behind
+    fn create_tmp_object(&self) -> anyhow::Result<(PathBuf, fs::File)> {
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)?
+            .subsec_nanos();
+        let tmp_path =
+            self.git_dir
+                .join("objects")
+                .join(format!("tmp_obj_{}_{}", std::process::id(), nanos));
+        let tmp_file = fs::File::options()
+            .write(true)
+            .create_new(true)
+            .open(&tmp_path)
+            .context(format!("failed to create {}", tmp_path.display()))?;
+
+        Ok((tmp_path, tmp_file))
+    }
 
-        let data = index.serialize()?;
+    /// Write a file as a blob, reading it in chunks rather than all at once.
+    ///
+    /// The object is compressed to a temporary file while its sha is computed, then
+    /// renamed to its final path, so memory use does not grow with the file size.
+    ///
+    /// returns sha of the blob
+    pub fn write_blob_from_file(&self, path: &Path) -> anyhow::Result<String> {
+        let mut input =
+            fs::File::open(path).context(format!("failed to open {}", path.display()))?;
+        let len = input.metadata().context("failed to read metadata")?.len();
+
+        let objects_dir = self.git_dir.join("objects");
+        let (tmp_path, tmp_file) = self.create_tmp_object()?;
+
+        let result = (|| {
+            let mut encoder =
+                flate2::write::ZlibEncoder::new(tmp_file, flate2::Compression::default());
+            let mut hasher = sha1::Sha1::new();
+
+            let header = format!("blob {}\0", len);
+            hasher.update(header.as_bytes());
+            encoder
+                .write_all(header.as_bytes())
+                .context("failed to write zlib data")?;
+
+            let mut buf = vec![0; 64 * 1024];
+            let mut written = 0;
+            loop {
+                let n = input
+                    .read(&mut buf)
+                    .context(format!("failed to read {}", path.display()))?;
+                if n == 0 {
+                    break;
+                }
+
+                hasher.update(&buf[..n]);
+                encoder
+                    .write_all(&buf[..n])
+                    .context("failed to write zlib data")?;
+                written += n as u64;
+            }
+
+            anyhow::ensure!(
+                written == len,
+                "{} changed while it was being read",
+                path.display()
+            );
+
+            let sha = hex::encode(hasher.finalize());
+            let file = encoder.finish().context("failed to write zlib data")?;
+
+            let object_path = objects_dir.join(&sha[..2]).join(&sha[2..]);
+            if object_path.exists() {
+                fs::remove_file(&tmp_path)?;
+                return Ok(sha);
+            }
 
-        fs::write(index_path, data).context("failed to write index file")?;
+            fs::create_dir_all(objects_dir.join(&sha[..2]))?;
+            self.sync_object(&file, &tmp_path)?;
+            fs::rename(&tmp_path, &object_path)
+                .context(format!("failed to write object {}", sha))?;
+
+            Ok(sha)
+        })();
+
+        if result.is_err() {
+            let _ = fs::remove_file(&tmp_path);
+        }
+
+        result
+    }
+
+    /// make sure a crash cannot leave a truncated object behind,
+    /// `core.fsyncObjectFiles = false` trades this for speed
+    fn sync_object(&self, file: &fs::File, path: &Path) -> anyhow::Result<()> {
+        let fsync = self
+            .config
+            .getboolcoerce("core", "fsyncObjectFiles")
+            .map_err(|e| anyhow::anyhow!(e))?
+            .unwrap_or(true);
+
+        if fsync {
+            file.sync_all().context("failed to sync object file")?;
+
+            // unwrap is safe because the object path is inside the objects directory
+            fs::File::open(path.parent().unwrap())
+                .and_then(|dir| dir.sync_all())
+                .context("failed to sync objects directory")?;
+        }
 
         Ok(())
     }
 
+    /// report the progress of long operations to `progress` rather than dropping it
+    pub fn set_progress(&mut self, progress: impl Progress + 'static) {
+        self.progress = RefCell::new(Box::new(progress));
+    }
+
+    /// report that `done` of the `total` steps of an operation are complete
+    fn progress(&self, title: &str, done: usize, total: usize) {
+        self.progress.borrow_mut().update(title, done, total);
+    }
+
+    /// the path of the index file
+    pub fn index_path(&self) -> &Path {
+        &self.index_file
+    }
+
+    pub fn read_index(&self) -> anyhow::Result<Index> {
+        let index_path = self.index_path();
+
+        // New repositories have no index!
+        if !index_path.exists() {
+            return Ok(Index::default());
+        }
+
+        let data = fs::read(index_path).context("failed to read index file")?;
+
+        let data = Bytes::from(data);
+
+        Index::from_bytes(data)
+    }
+
+    /// write the index through `index.lock`, which fails when another process holds it
+    pub fn write_index(&self, index: &Index) -> anyhow::Result<()> {
+        let data = index.serialize()?;
+
+        LockFile::acquire(self.index_path())?.commit(&data)
+    }
+
     pub fn read_ignore(&self) -> anyhow::Result<GitIgnore> {
         let mut ignore = GitIgnore::default();
 
@@ -397,7 +1347,10 @@ impl Repository {
         if exclude_path.exists() {
             let data = fs::read_to_string(&exclude_path).context("failed to read exclude file")?;
             let rules = GitIgnore::parse(&data);
-            ignore.global.push(rules);
+            let source = exclude_path
+                .strip_prefix(&self.work_tree)
+                .unwrap_or(&exclude_path);
+            ignore.global.push((repo_path(source)?, rules));
         }
 
         // Global configuration
@@ -413,7 +1366,9 @@ impl Repository {
             let data = fs::read_to_string(&global_ignore_path)
                 .context("failed to read global ignore file")?;
             let rules = GitIgnore::parse(&data);
-            ignore.global.push(rules);
+            ignore
+                .global
+                .push((global_ignore_path.display().to_string(), rules));
         }
 
         // .gitignore files in the index
@@ -431,7 +1386,7 @@ impl Repository {
                 .context("invalid path")?
                 .to_owned();
 
-            let object = self.read_object(&entry.sha)?;
+            let object = self.read_object(&entry.sha_hex())?;
 
             let lines = String::from_utf8_lossy(&object.data).to_string();
 
@@ -440,32 +1395,184 @@ impl Repository {
             ignore.local.insert(dirname, rules);
         }
 
+        // the root .gitignore in the worktree applies even before it is staged,
+        // and takes precedence over the staged version
+        let root_ignore_path = self.work_tree.join(".gitignore");
+
+        if root_ignore_path.is_file() {
+            let data =
+                fs::read_to_string(&root_ignore_path).context("failed to read .gitignore file")?;
+            let rules = GitIgnore::parse(&data);
+            ignore.local.insert("".to_string(), rules);
+        }
+
         Ok(ignore)
     }
 
-    pub fn active_branch(&self) -> anyhow::Result<String> {
+    /// what HEAD points at, see [Head]
+    pub fn head(&self) -> anyhow::Result<Head> {
         let head =
             fs::read_to_string(self.git_dir.join("HEAD")).context("failed to read HEAD file")?;
         let head = head.trim();
-        if head.starts_with("ref: refs/heads/") {
-            Ok(head.trim_start_matches("ref: refs/heads/").to_string())
-        } else {
-            anyhow::bail!("Detached HEAD found: {}", head);
+
+        if let Some(branch) = head.strip_prefix("ref: refs/heads/") {
+            return Ok(match self.resolve_ref(format!("refs/heads/{}", branch))? {
+                Some(_) => Head::Branch(branch.to_string()),
+                None => Head::Unborn(branch.to_string()),
+            });
+        }
+
+        anyhow::ensure!(
+            !head.starts_with("ref: "),
+            "HEAD points outside of refs/heads: {}",
+            head
+        );
+
+        Ok(Head::Detached(head.to_string()))
+    }
+
+    /// the branch HEAD points at, even if it has no commit yet, fails when HEAD is detached
+    pub fn active_branch(&self) -> anyhow::Result<String> {
+        match self.head()? {
+            Head::Branch(branch) | Head::Unborn(branch) => Ok(branch),
+            Head::Detached(sha) => anyhow::bail!("Detached HEAD found: {}", sha),
+        }
+    }
+
+    /// the short name of a ref, `HEAD` names the active branch, or stays `HEAD` when detached
+    pub fn abbrev_ref(&self, name: &str) -> anyhow::Result<String> {
+        if name == "HEAD" || name == "@" {
+            return Ok(self.active_branch().unwrap_or_else(|_| "HEAD".to_string()));
+        }
+
+        if name.starts_with("refs/") {
+            crate::refs::check_ref_name(name)?;
+            anyhow::ensure!(self.resolve_ref(name)?.is_some(), "not a ref: {}", name);
+
+            return Ok(name
+                .strip_prefix("refs/heads/")
+                .or(name.strip_prefix("refs/tags/"))
+                .unwrap_or(name)
+                .to_string());
+        }
+
+        // a short name is a branch or a tag, loose or packed
+        for dir in ["refs/heads", "refs/tags"] {
+            let full = format!("{}/{}", dir, name);
+            crate::refs::check_ref_name(&full)?;
+
+            if self.resolve_ref(&full)?.is_some() {
+                return Ok(name.to_string());
+            }
+        }
+
+        anyhow::bail!("not a ref: {}", name)
+    }
+
+    /// Flatten a tree-ish object (a tree, or a commit and its tree) into a dict
+    /// of repo-relative path -> blob sha.
+    ///
+    /// Submodules (git links) are left out, symlinks are included.
+    pub fn tree_to_dict(&self, name: &str) -> anyhow::Result<IndexMap<String, String>> {
+        Ok(self
+            .tree_entries(name)?
+            .into_iter()
+            .map(|(path, (_, sha))| (path, sha))
+            .collect())
+    }
+
+    /// Like [Self::tree_to_dict], with the mode of each entry: path -> (mode, sha).
+    fn tree_entries(&self, name: &str) -> anyhow::Result<IndexMap<String, (String, String)>> {
+        Ok(self
+            .read_tree_recursive(name)?
+            .into_iter()
+            .filter(|(_, (mode, _))| !mode.starts_with(&FileType::Commit.to_octal()))
+            .collect())
+    }
+
+    /// Flatten a tree-ish object into a dict of repo-relative path -> (mode, sha),
+    /// for every entry which is not a tree.
+    pub fn read_tree_recursive(
+        &self,
+        name: &str,
+    ) -> anyhow::Result<IndexMap<String, (String, String)>> {
+        let mut dict = IndexMap::new();
+
+        self.collect_tree(name, Path::new(""), &mut dict)?;
+
+        Ok(dict)
+    }
+
+    fn collect_tree(
+        &self,
+        name: &str,
+        prefix: &Path,
+        dict: &mut IndexMap<String, (String, String)>,
+    ) -> anyhow::Result<()> {
+        let tree_or_commit = self
+            .find_object(name, true)?
+            .ok_or(anyhow::anyhow!("object not found: {}", name))?;
+
+        let object = self.read_object(&tree_or_commit)?;
+
+        if let Fmt::Commit = object.header.fmt {
+            let commit = crate::objects::commit::Commit::from_bytes(object.data)?;
+            let tree = commit.tree().ok_or(anyhow::anyhow!("commit has no tree"))?;
+            return self.collect_tree(tree, prefix, dict);
+        }
+
+        anyhow::ensure!(
+            object.header.fmt == Fmt::Tree,
+            "objects type mismatch, expected tree"
+        );
+
+        let tree = Tree::from_bytes(object.data)?;
+
+        for tree_entry in tree.0 {
+            let file_type = tree_entry.file_type()?;
+
+            let dest = prefix.join(tree_entry.path);
+            let sha1 = tree_entry.sha1;
+
+            match file_type {
+                FileType::Tree => {
+                    self.collect_tree(&sha1, &dest, dict)?;
+                }
+                FileType::Blob | FileType::SymLink | FileType::Commit => {
+                    dict.insert(repo_path(&dest)?, (tree_entry.mode, sha1));
+                }
+            }
         }
+
+        Ok(())
     }
 
-    /// Create a tree from index object.
+    /// Create a tree from index object, like `git write-tree`.
     ///
     /// Returns the sha of the root tree object.
+    /// An index with unmerged entries, at a stage other than 0, cannot be written.
     ///
     /// Notice: this function will write tree objects to the disk.
-    fn create_tree_from_index(&self, index: &Index) -> anyhow::Result<String> {
+    pub fn create_tree_from_index(&self, index: &Index) -> anyhow::Result<String> {
+        if let Some(entry) = index.entries.iter().find(|entry| entry.flag_stage != 0) {
+            anyhow::bail!("{}: unmerged (stage {})", entry.name, entry.flag_stage);
+        }
+
+        self.build_tree_from_index(index, true)
+    }
+
+    /// Build the trees of an index, and return the sha of the root tree object.
+    ///
+    /// Tree objects are only written to the disk when `write` is true,
+    /// otherwise only their shas are computed.
+    fn build_tree_from_index(&self, index: &Index, write: bool) -> anyhow::Result<String> {
         enum T<'a> {
             IndexEntry(&'a crate::index::IndexEntry), // file in a dictionary
             TreeInfo((String, String)),               // file name, sha; dictionary in a dictionary
         }
 
-        let mut map = HashMap::new();
+        // the root tree always exists, an empty index gives the well-known empty tree
+        let mut map = HashMap::from([(String::new(), vec![])]);
 
         // collect entries by parent path
         for entry in &index.entries {
@@ -477,11 +1584,15 @@ impl Repository {
                 .to_owned();
             let parent_str = parent.to_str().context("invalid path")?.to_string();
 
-            while parent != PathBuf::from("") {
+            // every ancestor needs a tree, up to and including the root
+            loop {
                 let parent_str = parent.to_str().context("invalid path")?;
 
                 map.entry(parent_str.to_string()).or_insert(vec![]);
-                parent.pop();
+
+                if !parent.pop() {
+                    break;
+                }
             }
             map.entry(parent_str.to_string())
                 .or_insert(vec![])
@@ -490,7 +1601,9 @@ impl Repository {
 
         let mut sha1 = String::new();
 
-        // sort paths by length so we can create tree objects from bottom to top
+        // sort paths by length so we can create tree objects from bottom to top: a directory
+        // is longer than its parent, so each tree is complete when it is written, and is
+        // linked into its parent, which comes later, exactly once
         let mut paths: Vec<_> = map.keys().cloned().collect();
 
         paths.sort_by_key(|a| !a.len());
@@ -516,7 +1629,7 @@ impl Repository {
                                 index_entry.mode_type, index_entry.mode_perms
                             ),
                             file_name,
-                            index_entry.sha.clone(),
+                            index_entry.sha_hex(),
                         )?
                     }
                     T::TreeInfo((file_name, sha1)) => TreeEntry::try_new(
@@ -525,12 +1638,16 @@ impl Repository {
                         sha1.clone(),
                     )?,
                 };
-                tree.0.push(tree_entry);
+                tree.insert(tree_entry)?;
             }
 
             let tree_object = GitObject::new(Fmt::Tree, tree.serialize()?);
 
-            sha1 = self.write_object(&tree_object)?;
+            sha1 = if write {
+                self.write_object(&tree_object)?
+            } else {
+                tree_object.sha()?
+            };
 
             if path.is_empty() {
                 break;
@@ -549,6 +1666,26 @@ impl Repository {
 }
 
 impl Repository {
+    /// Check whether the index would produce the same tree as the HEAD commit,
+    /// i.e. there is nothing to commit.
+    ///
+    /// No tree objects are written.
+    pub fn staged_tree_matches_head(&self) -> anyhow::Result<bool> {
+        let index = self.read_index()?;
+
+        // HEAD may be detached at an annotated tag, which is peeled to its commit
+        let head = match self.find_object("HEAD", true)? {
+            Some(head) => head,
+            // unborn branch, nothing is staged only if the index is empty
+            None => return Ok(index.entries.is_empty()),
+        };
+
+        let commit = self.read_commit(&head)?;
+        let head_tree = commit.tree().context("commit has no tree")?;
+
+        Ok(self.build_tree_from_index(&index, false)? == *head_tree)
+    }
+
     /// rm files from index
     pub fn rm(
         &self,
@@ -557,89 +1694,79 @@ impl Repository {
         ignore_missing: bool,
     ) -> anyhow::Result<Index> {
         let mut index = self.read_index()?;
-        let mut abs_paths = IndexSet::with_capacity(paths.len());
+        let mut removed = Vec::with_capacity(paths.len());
 
         for path in paths {
             let path = PathBuf::from(path).canonicalize().context("invalid path")?;
-            if path.starts_with(&self.work_tree) {
-                abs_paths.insert(path);
-            } else {
+            if !path.starts_with(&self.work_tree) {
                 anyhow::bail!("path not in working directory: {}", path.display());
             }
-        }
 
-        let (remove, kept): (Vec<_>, Vec<_>) = index.entries.into_iter().partition(|path| {
-            let abs_path = self.work_tree.join(&path.name);
-            if abs_paths.contains(&abs_path) {
-                abs_paths.remove(&abs_path);
-                true
-            } else {
-                false
-            }
-        });
+            // unwrap is safe because we have ensured that path is a child of work_tree
+            let name = repo_path(path.strip_prefix(&self.work_tree).unwrap())?;
 
-        if !ignore_missing && !abs_paths.is_empty() {
-            anyhow::bail!(
-                "path not in index: {}",
-                // unwrap is safe because we have ensured that abs_paths is not empty
-                abs_paths.iter().next().unwrap().display()
-            );
+            match index.remove(&name) {
+                Some(entry) => removed.push(entry),
+                None if ignore_missing => {}
+                None => anyhow::bail!("path not in index: {}", path.display()),
+            }
         }
 
         if delete_file {
-            for e in remove {
-                fs::remove_file(&e.name).context(format!("failed to remove file: {}", e.name))?;
+            for e in removed {
+                fs::remove_file(self.work_tree.join(&e.name))
+                    .context(format!("failed to remove file: {}", e.name))?;
             }
         }
 
-        index.entries = kept;
-
         self.write_index(&index)?;
 
         Ok(index)
     }
 
+    /// Stage files, given by absolute paths or paths relative to the current directory.
+    ///
+    /// Nothing is staged when one of the paths is missing or outside the work tree.
     pub fn add(&self, paths: &Vec<String>) -> anyhow::Result<()> {
-        // rm ensures that paths are in working directory
-        let mut index = self.rm(paths, false, true)?;
+        let mut index = self.read_index()?;
+        let attributes = self.read_attributes()?;
 
         for path in paths {
-            let abs_path = PathBuf::from(path).canonicalize().context("invalid path")?;
-
-            let object = GitObject::from_file(&abs_path, Fmt::Blob)?;
-
-            let sha = self.write_object(&object)?;
-
-            let metadata = abs_path.metadata().context("failed to read metadata")?;
-
-            let ctime_s = metadata.st_ctime() as u32;
-            let ctime_ns = (metadata.st_ctime_nsec() % 1_000_000_000) as u32;
-
-            let mtime_s = metadata.st_mtime() as u32;
-            let mtime_ns = (metadata.st_mtime_nsec() % 1_000_000_000) as u32;
-
-            let index_entry = crate::index::IndexEntry {
-                name: abs_path
-                    .strip_prefix(&self.work_tree)
-                    .unwrap() // unwrap is safe because we have ensured that abs_path is a child of work_tree
-                    .to_str()
-                    .unwrap()
-                    .to_owned(),
-                ctime: (ctime_s, ctime_ns),
-                mtime: (mtime_s, mtime_ns),
-                dev: metadata.st_dev() as u32,
-                ino: metadata.st_ino() as u32,
-                mode_type: 0b1000,
-                mode_perms: 0o644,
-                uid: metadata.st_uid(),
-                gid: metadata.st_gid(),
-                fsize: metadata.st_size() as u32,
-                sha,
-                flag_assume_valid: false,
-                flag_stage: 0,
+            let abs_path = absolute_path(Path::new(path))
+                .context(format!("pathspec '{}' did not match any files", path))?;
+            let metadata = abs_path
+                .symlink_metadata()
+                .context(format!("pathspec '{}' did not match any files", path))?;
+            anyhow::ensure!(
+                abs_path.starts_with(&self.work_tree),
+                "path not in working directory: {}",
+                abs_path.display()
+            );
+
+            // unwrap is safe because we have ensured that abs_path is a child of work_tree
+            let name = repo_path(abs_path.strip_prefix(&self.work_tree).unwrap())?;
+
+            // a file without a clean filter is streamed, it is never held in memory
+            let sha = if metadata.file_type().is_symlink() {
+                // the blob of a symlink is the path it points to, not what it points to
+                let target = fs::read_link(&abs_path)?.into_os_string();
+
+                self.write_object(&GitObject::new(
+                    Fmt::Blob,
+                    Bytes::from(target.into_encoded_bytes()),
+                ))?
+            } else if self.has_filter(&attributes, &name, "clean")? {
+                let object = GitObject::from_file(&abs_path, Fmt::Blob)?;
+                let data = self.run_filter(&attributes, &name, "clean", object.data)?;
+
+                self.write_object(&GitObject::new(Fmt::Blob, data))?
+            } else {
+                self.write_blob_from_file(&abs_path)?
             };
 
-            index.entries.push(index_entry);
+            let index_entry = IndexEntry::from_metadata(name, sha_from_hex(&sha)?, &metadata);
+
+            index.upsert(index_entry);
         }
 
         self.write_index(&index)?;
@@ -647,9 +1774,90 @@ impl Repository {
         Ok(())
     }
 
-    pub fn read_config(&self) -> anyhow::Result<RepoConfig> {
-        let mut config = configparser::ini::Ini::new();
+    /// Interactively stage the changes of the modified tracked files, hunk by hunk.
+    ///
+    /// `choose` is given the path and the text of each hunk of its diff from the index to
+    /// the work tree: Some(true) stages the hunk, Some(false) skips it and None stops.
+    /// The blob staged for a file has only its chosen hunks applied. Binary files and
+    /// symlinks have no lines to choose from and are left out. Returns the paths with
+    /// staged hunks.
+    pub fn add_patch(
+        &self,
+        mut choose: impl FnMut(&str, &str) -> anyhow::Result<Option<bool>>,
+    ) -> anyhow::Result<Vec<String>> {
+        let mut index = self.read_index()?;
+        let attributes = self.read_attributes()?;
+
+        let mut staged = vec![];
+        // the files whose every hunk was chosen, added whole to record their stat data
+        let mut whole = vec![];
+
+        'files: for entry in self.status()?.entries {
+            let Some((mode, sha)) = entry.index.filter(|_| entry.unstaged == 'M') else {
+                continue;
+            };
+            if !mode.starts_with("100") {
+                continue;
+            }
+
+            // the work tree version as add would store it
+            let old = self.read_object(&sha)?.data;
+            let path = self.work_tree.join(&entry.path);
+            let new =
+                self.run_filter(&attributes, &entry.path, "clean", fs::read(&path)?.into())?;
+            if old.contains(&0) || new.contains(&0) {
+                continue;
+            }
+
+            let hunks = crate::diff::diff_hunks(&old, &new, 3);
+            let mut chosen = vec![];
+            let mut stop = false;
+
+            for hunk in &hunks {
+                match choose(&entry.path, &hunk.text)? {
+                    Some(true) => chosen.push(hunk),
+                    Some(false) => {}
+                    None => {
+                        stop = true;
+                        break;
+                    }
+                }
+            }
+
+            if chosen.len() == hunks.len() {
+                whole.push(path.display().to_string());
+                staged.push(entry.path);
+            } else if !chosen.is_empty() {
+                let blob = crate::diff::apply_hunks(&old, &new, chosen);
+                let blob_len = blob.len();
+                let blob = self.write_object(&GitObject::new(Fmt::Blob, Bytes::from(blob)))?;
+
+                // the work tree file does not match the blob, its stat data is not recorded,
+                // only the size the next status compares
+                let mut staged_entry = IndexEntry {
+                    name: entry.path.clone(),
+                    fsize: blob_len as u32,
+                    ..Default::default()
+                };
+                staged_entry.set_mode(&mode)?;
+                staged_entry.set_sha_hex(&blob)?;
+                index.upsert(staged_entry);
+                staged.push(entry.path);
+            }
+
+            if stop {
+                break 'files;
+            }
+        }
+
+        self.write_index(&index)?;
+        self.add(&whole)?;
+
+        Ok(staged)
+    }
 
+    /// the existing config files, from the lowest to the highest precedence
+    fn config_files(&self) -> anyhow::Result<Vec<PathBuf>> {
         let user_home = dirs::home_dir().context("failed to get home directory")?;
 
         let config_dir = if let Ok(xdg_config_home) = std::env::var("XDG_CONFIG_HOME") {
@@ -664,20 +1872,155 @@ impl Repository {
             self.git_dir.join("config"),
         ];
 
-        for config_file in config_files {
-            if config_file.exists() {
-                let config_file = config_file.canonicalize().context("invalid path")?;
+        config_files
+            .into_iter()
+            .filter(|config_file| config_file.exists())
+            .map(|config_file| config_file.canonicalize().context("invalid path"))
+            .collect()
+    }
+
+    /// the config merged from every config file, read once and cached
+    pub fn read_config(&self) -> anyhow::Result<Rc<RepoConfig>> {
+        if let Some(config) = self.merged_config.borrow().as_ref() {
+            return Ok(config.clone());
+        }
+
+        let mut config = configparser::ini::Ini::new();
+
+        for config_file in self.config_files()? {
+            config
+                .load_and_append(config_file)
+                .map_err(|e| anyhow::anyhow!(e))?;
+        }
+
+        let config = Rc::new(RepoConfig(config));
+        *self.merged_config.borrow_mut() = Some(config.clone());
 
-                config
-                    .load_and_append(config_file)
-                    .map_err(|e| anyhow::anyhow!(e))?;
+        Ok(config)
+    }
+
+    /// forget the cached config, to see the changes made to the config files since
+    pub fn reload_config(&self) {
+        self.merged_config.borrow_mut().take();
+    }
+
+    /// Set `section.key`, or `section.subsection.key`, in the repository's config file.
+    ///
+    /// Only the line of the key is written, see [set_config_line]. The cached config is
+    /// reloaded, so the new value is visible right away.
+    pub fn set_config(&mut self, name: &str, value: &str) -> anyhow::Result<()> {
+        let path = self.git_dir.join("config");
+        let text = if path.exists() {
+            fs::read_to_string(&path).context("failed to read config file")?
+        } else {
+            String::new()
+        };
+
+        let text = set_config_line(&text, name, value)?;
+        LockFile::acquire(&path)?.commit(text.as_bytes())?;
+
+        let mut config = configparser::ini::Ini::new();
+        config.load(&path).map_err(|e| anyhow::anyhow!(e))?;
+        self.config = RepoConfig(config);
+        self.reload_config();
+
+        Ok(())
+    }
+
+    /// list every entry of every config file, in the order they are applied
+    pub fn config_entries(&self) -> anyhow::Result<Vec<ConfigEntry>> {
+        let mut entries = vec![];
+
+        for config_file in self.config_files()? {
+            let mut config = configparser::ini::Ini::new();
+            config.load(&config_file).map_err(|e| anyhow::anyhow!(e))?;
+
+            let mut sections: Vec<_> = config.get_map_ref().iter().collect();
+            sections.sort_by_key(|(section, _)| *section);
+
+            for (section, values) in sections {
+                // `[remote "origin"]` is printed as `remote.origin`
+                let section = match section.split_once(' ') {
+                    Some((name, subsection)) => {
+                        format!("{}.{}", name, subsection.trim_matches('"'))
+                    }
+                    None => section.to_string(),
+                };
+
+                let mut values: Vec<_> = values.iter().collect();
+                values.sort_by_key(|(key, _)| *key);
+
+                for (key, value) in values {
+                    entries.push(ConfigEntry {
+                        origin: config_file.clone(),
+                        key: format!("{}.{}", section, key),
+                        value: value.clone(),
+                    });
+                }
             }
         }
 
-        Ok(RepoConfig(config))
+        Ok(entries)
+    }
+
+    /// Write an annotated tag object pointing at `object`, returns its sha.
+    ///
+    /// The tagger is the configured user, at `GIT_COMMITTER_DATE` or the current time.
+    pub fn write_tag_object(
+        &self,
+        name: &str,
+        object: &str,
+        message: String,
+    ) -> anyhow::Result<String> {
+        let tagger = format!(
+            "{} {}",
+            self.read_config()?.user().identity()?,
+            crate::objects::commit::format_time(signature_time("GIT_COMMITTER_DATE")?)
+        );
+
+        let tag = crate::objects::tag::Tag::new(
+            name.to_string(),
+            object.to_string(),
+            self.read_object(object)?.header.fmt,
+            tagger,
+            message,
+        );
+
+        self.write_object(&GitObject::new(Fmt::Tag, tag.serialize()?))
     }
 
-    pub fn commit(&self, message: String) -> anyhow::Result<String> {
+    /// Commit the index on top of HEAD, returns the sha of the new commit.
+    ///
+    /// The `pre-commit` and `commit-msg` hooks run first, unless `verify` is false.
+    pub fn commit(&self, message: String, verify: bool) -> anyhow::Result<String> {
+        if verify {
+            self.run_hook("pre-commit", &[])?;
+        }
+
+        // the commit-msg hook may edit the message file in place
+        let message_path = self.git_dir.join("COMMIT_EDITMSG");
+        fs::write(&message_path, &message).context("failed to write COMMIT_EDITMSG file")?;
+        let message = if verify && self.run_hook("commit-msg", &[message_path.as_os_str()])? {
+            fs::read_to_string(&message_path).context("failed to read COMMIT_EDITMSG file")?
+        } else {
+            message
+        };
+
+        let config = self.read_config()?;
+
+        let comment_char = match config.get("core", "commentChar") {
+            Some(c) if c != "auto" => c.chars().next().context("empty core.commentChar")?,
+            _ => '#',
+        };
+        let cleanup = config
+            .get("commit", "cleanup")
+            .unwrap_or("default".to_string());
+        let message = cleanup_message(&message, &cleanup, comment_char)?;
+        anyhow::ensure!(
+            !message.trim().is_empty(),
+            "aborting commit due to empty commit message"
+        );
+
         let index = self.read_index()?;
 
         // create tree object and write it to disk from index file
@@ -685,32 +2028,3146 @@ impl Repository {
 
         let parent = self.resolve_ref("HEAD")?;
 
-        let config = self.read_config()?;
-
         // create commit object and write it to disk
+        let commit_sha = self.commit_tree(&tree_sha, parent.as_deref(), message)?;
+
+        // Update HEAD so our commit is now the tip of the active branch,
+        // unless the branch moved since its tip was read as the parent.
+        let old = parent.as_deref().unwrap_or(NULL_SHA);
+
+        match self.head()? {
+            // If we're on a branch, we update refs/heads/BRANCH
+            Head::Branch(branch) | Head::Unborn(branch) => {
+                self.update_ref(&format!("refs/heads/{}", branch), &commit_sha, Some(old))?
+            }
+            // Otherwise, we update HEAD directly
+            Head::Detached(_) => self.update_ref("HEAD", &commit_sha, Some(old))?,
+        }
+
+        Ok(commit_sha)
+    }
+
+    /// Write a commit of a tree, like `git commit-tree`, returns the sha of the commit.
+    ///
+    /// The author and committer are the configured user, no ref is updated.
+    /// The message is used as is, only a missing final newline is added.
+    pub fn commit_tree(
+        &self,
+        tree: &str,
+        parent: Option<&str>,
+        mut message: String,
+    ) -> anyhow::Result<String> {
+        let tree = self
+            .find_object_peeled(tree, Some(Fmt::Tree))?
+            .ok_or(anyhow::anyhow!("not a valid object name: {}", tree))?;
+
+        let parent = match parent {
+            Some(parent) => Some(
+                self.find_object_peeled(parent, Some(Fmt::Commit))?
+                    .ok_or(anyhow::anyhow!("not a valid object name: {}", parent))?,
+            ),
+            None => None,
+        };
+
+        if !message.ends_with('\n') {
+            message.push('\n');
+        }
+
         let commit = crate::objects::commit::Commit::new(
-            tree_sha,
+            tree,
             parent,
-            config.user().context("failed to get user")?,
-            chrono::Local::now(),
+            self.read_config()?.user().identity()?,
+            signature_time("GIT_AUTHOR_DATE")?,
+            signature_time("GIT_COMMITTER_DATE")?,
             message,
         );
 
-        let commit_sha = self.write_object(&GitObject::new(Fmt::Commit, commit.serialize()?))?;
+        self.write_object(&GitObject::new(Fmt::Commit, commit.serialize()?))
+    }
 
-        // Update HEAD so our commit is now the tip of the active branch.
+    /// Switch HEAD to a branch or a commit, updating the work tree and the index.
+    ///
+    /// Files tracked by the old HEAD but absent from the new commit are deleted.
+    /// A commit which is not a branch tip is checked out as a detached HEAD.
+    /// Uncommitted changes, and untracked files the new commit would replace, make the
+    /// checkout fail, unless `force` is set to discard them.
+    pub fn checkout(&self, name: &str, force: bool) -> anyhow::Result<()> {
+        let target = self
+            .find_object(name, true)?
+            .ok_or(anyhow::anyhow!("object not found: {}", name))?;
+        self.read_commit(&target)?;
+
+        self.switch_work_tree(
+            &target,
+            force,
+            "checkout",
+            "Please commit them or use --force to discard them",
+        )?;
 
-        if let Ok(active_branch) = self.active_branch() {
-            // If we're on a branch, we update refs/heads/BRANCH
-            let branch_path = self.git_dir.join("refs").join("heads").join(active_branch);
-            fs::write(branch_path, format!("{}\n", commit_sha))
-                .context("failed to write branch file")?;
+        let head = if self.resolve_ref(format!("refs/heads/{}", name))?.is_some() {
+            format!("ref: refs/heads/{}\n", name)
         } else {
-            // Otherwise, we update HEAD directly
-            fs::write(self.git_dir.join("HEAD"), format!("{}\n", commit_sha))
-                .context("failed to write HEAD file")?;
+            format!("{}\n", target)
+        };
+
+        fs::write(self.git_dir.join("HEAD"), head).context("failed to write HEAD file")?;
+
+        Ok(())
+    }
+
+    /// Write the tree of a commit into an empty directory, like `checkout <commit> <path>`.
+    ///
+    /// The directory is created if missing. HEAD, the index and the work tree are left
+    /// alone.
+    pub fn checkout_to(&self, name: &str, path: &Path) -> anyhow::Result<()> {
+        let sha = self
+            .find_object(name, true)?
+            .ok_or(anyhow::anyhow!("object not found: {}", name))?;
+        let commit = self.read_commit(&sha)?;
+        let tree = commit.tree().ok_or(anyhow::anyhow!("commit has no tree"))?;
+
+        if path.exists() {
+            anyhow::ensure!(path.is_dir(), "path is not a directory: {}", path.display());
+            anyhow::ensure!(
+                path.read_dir()?.next().is_none(),
+                "path is not empty: {}",
+                path.display()
+            );
+        } else {
+            fs::create_dir_all(path)?;
         }
 
-        Ok(commit_sha)
+        self.write_tree_to(tree, path)
+    }
+
+    fn write_tree_to(&self, tree: &str, prefix: &Path) -> anyhow::Result<()> {
+        let tree_object = self.read_object(tree)?;
+        anyhow::ensure!(
+            tree_object.header.fmt == Fmt::Tree,
+            "objects type mismatch, expected tree"
+        );
+
+        for tree_entry in Tree::from_bytes(tree_object.data)?.0 {
+            let dest = prefix.join(&tree_entry.path);
+
+            match tree_entry.file_type()? {
+                FileType::Tree => {
+                    fs::create_dir_all(&dest)?;
+                    self.write_tree_to(&tree_entry.sha1, &dest)?;
+                }
+                FileType::Blob => {
+                    fs::write(&dest, self.read_object(&tree_entry.sha1)?.data)?;
+                    if tree_entry.mode == "100755" {
+                        crate::platform::set_executable(&dest, true)?;
+                    }
+                }
+                FileType::SymLink => {
+                    // the blob of a symlink holds the path it points to
+                    let target =
+                        String::from_utf8(self.read_object(&tree_entry.sha1)?.data.to_vec())
+                            .context(format!("invalid symlink target: {}", dest.display()))?;
+                    crate::platform::symlink(target, &dest)?;
+                }
+                FileType::Commit => {
+                    // the submodule's commit is not in this repository, git leaves an empty
+                    // directory in its place
+                    fs::create_dir_all(&dest)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Merge a commit into the current branch.
+    ///
+    /// Only fast-forwards are supported: when the branch is an ancestor of the commit,
+    /// the work tree and the index are updated to it, then the branch is moved to it.
+    /// An unborn branch is fast-forwarded too.
+    pub fn merge(&self, name: &str) -> anyhow::Result<Merge> {
+        let branch = self.active_branch()?;
+
+        let theirs = self
+            .find_object(name, true)?
+            .ok_or(anyhow::anyhow!("object not found: {}", name))?;
+        self.read_commit(&theirs)?;
+
+        let head = self.resolve_ref("HEAD")?;
+
+        if let Some(head) = &head {
+            let base = self.merge_base(head, &theirs)?;
+
+            if base.as_deref() == Some(&theirs) {
+                return Ok(Merge::UpToDate);
+            }
+            anyhow::ensure!(
+                base.as_deref() == Some(head),
+                "not up to date, true merge not implemented"
+            );
+        }
+
+        // the branch is locked and checked first, a concurrent update fails the merge
+        // before the work tree is touched
+        let lock = self.lock_ref(
+            &format!("refs/heads/{}", branch),
+            Some(head.as_deref().unwrap_or(NULL_SHA)),
+        )?;
+
+        self.switch_work_tree(
+            &theirs,
+            false,
+            "merge",
+            "Please commit them before you merge",
+        )?;
+        self.save_orig_head()?;
+        lock.commit(format!("{}\n", theirs).as_bytes())
+            .context(format!("failed to update ref refs/heads/{}", branch))?;
+
+        Ok(Merge::FastForward(theirs))
+    }
+
+    /// Make the work tree and the index match the commit target, from HEAD.
+    ///
+    /// Unless `force`, the update is refused as [Self::ensure_can_switch] does.
+    fn switch_work_tree(
+        &self,
+        target: &str,
+        force: bool,
+        operation: &str,
+        hint: &str,
+    ) -> anyhow::Result<()> {
+        let mut from = match self.resolve_ref("HEAD")? {
+            Some(head) => self.tree_to_dict(&head)?,
+            None => IndexMap::new(),
+        };
+        let to = self.tree_entries(target)?;
+
+        if force {
+            // the content of these files is unknown, so they are rewritten or removed
+            for entry in &self.status()?.entries {
+                from.insert(entry.path.clone(), String::new());
+            }
+        } else {
+            self.ensure_can_switch(&from, &to, operation, hint)?;
+        }
+
+        self.update_work_tree(&from, &to)
+    }
+
+    /// Check that the work tree and the index can be updated from the entries `from`,
+    /// those of HEAD, to the entries `to`.
+    ///
+    /// Staged changes, and local changes or untracked files the update would overwrite,
+    /// are refused, `operation` and `hint` are used in the error.
+    fn ensure_can_switch(
+        &self,
+        from: &IndexMap<String, String>,
+        to: &IndexMap<String, (String, String)>,
+        operation: &str,
+        hint: &str,
+    ) -> anyhow::Result<()> {
+        let status = self.status()?;
+
+        anyhow::ensure!(
+            status.entries.iter().all(|entry| entry.staged == '.'),
+            "cannot {}: your index contains uncommitted changes",
+            operation
+        );
+
+        let overwritten: Vec<_> = status
+            .entries
+            .iter()
+            .filter(|entry| matches!(entry.unstaged, 'M' | 'T'))
+            .map(|entry| entry.path.as_str())
+            .chain(
+                status
+                    .untracked
+                    .iter()
+                    .filter(|path| to.contains_key(*path) && !from.contains_key(*path))
+                    .map(|path| path.as_str()),
+            )
+            .collect();
+
+        anyhow::ensure!(
+            overwritten.is_empty(),
+            "Your local changes would be overwritten by {}:\n  {}\n{}",
+            operation,
+            overwritten.join("\n  "),
+            hint
+        );
+
+        Ok(())
+    }
+}
+
+/// what HEAD points at, see [Repository::head]
+#[derive(Debug, PartialEq)]
+pub enum Head {
+    /// a branch with at least one commit
+    Branch(String),
+    /// a commit, by its sha
+    Detached(String),
+    /// a branch without commits yet, as in a new repository
+    Unborn(String),
+}
+
+/// the outcome of [Repository::merge]
+#[derive(Debug, PartialEq)]
+pub enum Merge {
+    /// the branch already contains the commit
+    UpToDate,
+    /// the branch was an ancestor of the commit, and now points at it
+    FastForward(String),
+}
+
+/// the outcome of [Repository::rebase]
+#[derive(Debug, PartialEq)]
+pub enum Rebase {
+    /// the branch already contains the upstream
+    UpToDate,
+    /// the branch had no commits of its own, and now points at the upstream
+    FastForward(String),
+    /// the commits of the branch were replayed, the sha of the new tip
+    Rebased(String),
+}
+
+impl Repository {
+    /// read a commit object
+    fn read_commit(&self, sha: &str) -> anyhow::Result<crate::objects::commit::Commit> {
+        let object = self.read_object(sha)?;
+
+        anyhow::ensure!(
+            object.header.fmt == Fmt::Commit,
+            "objects type mismatch, expected commit: {}",
+            sha
+        );
+
+        crate::objects::commit::Commit::from_bytes(object.data)
+    }
+
+    /// all the commits reachable from a commit, including itself
+    ///
+    /// an annotated tag is peeled to the commit it points at first
+    fn ancestors(&self, sha: &str) -> anyhow::Result<std::collections::HashSet<String>> {
+        self.commits_from(sha, false)?
+            .map(|commit| commit.map(|(sha, _)| sha))
+            .collect()
+    }
+
+    /// find the best common ancestor of two commits, one which is not an ancestor of
+    /// another common ancestor, the closest to `b` when there are several
+    ///
+    /// returns None for unrelated histories
+    pub fn merge_base(&self, a: &str, b: &str) -> anyhow::Result<Option<String>> {
+        let ancestors = self.ancestors(a)?;
+
+        // closest to b first, which is not enough: a merge may reach an old common
+        // ancestor before a newer one
+        let mut common = vec![];
+        for commit in self.commits_from(b, false)? {
+            let (sha, _) = commit?;
+
+            if ancestors.contains(&sha) {
+                common.push(sha);
+            }
+        }
+
+        // the ancestors of a common ancestor are common ancestors too, never the best
+        let mut worse = std::collections::HashSet::new();
+        for sha in &common {
+            if worse.contains(sha) {
+                continue;
+            }
+
+            for commit in self.commits_from(sha, false)?.skip(1) {
+                worse.insert(commit?.0);
+            }
+        }
+
+        Ok(common.into_iter().find(|sha| !worse.contains(sha)))
+    }
+
+    /// whether the commit `a` is reachable from `b`, a commit is its own ancestor
+    pub fn is_ancestor(&self, a: &str, b: &str) -> anyhow::Result<bool> {
+        let a = self
+            .find_object(a, true)?
+            .ok_or(anyhow::anyhow!("object not found: {}", a))?;
+
+        for commit in self.commits_from(b, false)? {
+            if commit?.0 == a {
+                return Ok(true);
+            }
+        }
+
+        Ok(false)
+    }
+
+    /// write the trees of a dict of path -> (mode, blob sha), returns the sha of the root tree
+    fn write_tree_from_entries(
+        &self,
+        entries: &IndexMap<String, (String, String)>,
+    ) -> anyhow::Result<String> {
+        let mut index = Index::default();
+
+        for (path, (mode, sha)) in entries {
+            let mut entry = IndexEntry {
+                name: path.clone(),
+                sha: sha_from_hex(sha)?,
+                ..Default::default()
+            };
+            entry.set_mode(mode)?;
+            index.upsert(entry);
+        }
+
+        self.create_tree_from_index(&index)
+    }
+
+    /// Make the work tree and the index match the entries `to`, given that they match `from`.
+    ///
+    /// Files of `from` missing in `to` are deleted, and the index is rebuilt with
+    /// the metadata of the files in the work tree. Symlinks are created as symlinks.
+    fn update_work_tree(
+        &self,
+        from: &IndexMap<String, String>,
+        to: &IndexMap<String, (String, String)>,
+    ) -> anyhow::Result<()> {
+        for path in from.keys().filter(|path| !to.contains_key(*path)) {
+            let abs_path = self.work_tree.join(path);
+
+            // a dangling symlink does not exist for `exists`, but must be removed too
+            if abs_path.symlink_metadata().is_ok() {
+                fs::remove_file(&abs_path)
+                    .context(format!("failed to remove file: {}", abs_path.display()))?;
+            }
+
+            // remove the directories left empty
+            let mut parent = abs_path.parent();
+            while let Some(dir) = parent {
+                if dir == self.work_tree || !dir.exists() || dir.read_dir()?.next().is_some() {
+                    break;
+                }
+                fs::remove_dir(dir)?;
+                parent = dir.parent();
+            }
+        }
+
+        let mut index = Index::default();
+        let attributes = self.read_attributes()?;
+
+        for (done, (path, (mode, sha))) in to.iter().enumerate() {
+            self.progress("Updating files", done, to.len());
+            let abs_path = self.work_tree.join(path);
+
+            let symlink = mode.starts_with(&FileType::SymLink.to_octal());
+            let current = abs_path.symlink_metadata().ok();
+
+            if from.get(path) != Some(sha)
+                || current
+                    .as_ref()
+                    .is_none_or(|metadata| metadata.file_type().is_symlink() != symlink)
+            {
+                fs::create_dir_all(abs_path.parent().context("invalid path")?)?;
+
+                // writing through an existing symlink would change its target instead
+                if current.is_some() {
+                    fs::remove_file(&abs_path)
+                        .context(format!("failed to remove file: {}", abs_path.display()))?;
+                }
+
+                let object = self.read_object(sha)?;
+
+                if symlink {
+                    // the blob of a symlink holds the path it points to
+                    let target = String::from_utf8(object.data.to_vec())
+                        .context(format!("invalid symlink target: {}", path))?;
+                    crate::platform::symlink(target, &abs_path)
+                        .context(format!("failed to create symlink: {}", abs_path.display()))?;
+                } else {
+                    let data = self.run_filter(&attributes, path, "smudge", object.data)?;
+                    fs::write(&abs_path, data)
+                        .context(format!("failed to write file: {}", abs_path.display()))?;
+                }
+            }
+
+            // the executable bit follows the mode, even when the content is unchanged
+            let executable = mode == "100755";
+            if !symlink
+                && crate::platform::is_executable(&abs_path.symlink_metadata()?) != executable
+            {
+                crate::platform::set_executable(&abs_path, executable)
+                    .context(format!("failed to set the mode of {}", abs_path.display()))?;
+            }
+
+            let metadata = abs_path
+                .symlink_metadata()
+                .context("failed to read metadata")?;
+            let mut entry = IndexEntry::from_metadata(path.clone(), sha_from_hex(sha)?, &metadata);
+            // the mode comes from the tree, which knows about executables
+            entry.set_mode(mode)?;
+            index.upsert(entry);
+        }
+        self.progress("Updating files", to.len(), to.len());
+
+        self.write_index(&index)
+    }
+
+    /// Rebase the current branch onto `onto`.
+    ///
+    /// The commits of the branch since its merge base with `onto` are replayed on top
+    /// of `onto`, following first parents, and the branch is moved to the last one.
+    /// The commits are replayed before anything is written, so when one cannot be
+    /// applied cleanly the rebase fails with the branch, the index and the work tree
+    /// untouched, there is nothing to continue or abort.
+    /// Staged changes, and local changes or untracked files the new tip would
+    /// overwrite, are refused before the branch or the work tree is touched.
+    pub fn rebase(&self, onto: &str) -> anyhow::Result<Rebase> {
+        let branch = self.active_branch()?;
+
+        let head = self
+            .resolve_ref("HEAD")?
+            .context("cannot rebase: the current branch has no commits")?;
+        let upstream = self
+            .find_object(onto, true)?
+            .ok_or(anyhow::anyhow!("object not found: {}", onto))?;
+        self.read_commit(&upstream)?;
+
+        let base = self.merge_base(&head, &upstream)?;
+
+        if base.as_deref() == Some(&upstream) {
+            return Ok(Rebase::UpToDate);
+        }
+
+        let head_tree = self.tree_to_dict(&head)?;
+        let branch = format!("refs/heads/{}", branch);
+        let hint = "Please commit them before you rebase";
+
+        if base.as_deref() == Some(&head) {
+            let lock = self.lock_ref(&branch, Some(&head))?;
+            let upstream_tree = self.tree_entries(&upstream)?;
+            self.ensure_can_switch(&head_tree, &upstream_tree, "rebase", hint)?;
+
+            self.save_orig_head()?;
+            self.update_work_tree(&head_tree, &upstream_tree)?;
+            lock.commit(format!("{}\n", upstream).as_bytes())
+                .context(format!("failed to update ref {}", branch))?;
+
+            return Ok(Rebase::FastForward(upstream));
+        }
+
+        // local changes are refused before replaying, the untracked files the new tip
+        // would overwrite once it is known
+        self.ensure_can_switch(&head_tree, &IndexMap::new(), "rebase", hint)?;
+
+        // the commits to replay, oldest first
+        let mut commits = vec![];
+        let mut sha = head.clone();
+
+        while Some(&sha) != base.as_ref() {
+            let commit = self.read_commit(&sha)?;
+
+            let parent = match commit.parents().map(|parents| parents.as_slice()) {
+                Some([parent]) => Some(parent.clone()),
+                Some([]) | None => None,
+                Some(_) => anyhow::bail!("cannot rebase merge commit: {}", sha),
+            };
+
+            commits.push((sha, commit, parent.clone()));
+
+            match parent {
+                Some(parent) => sha = parent,
+                None => break,
+            }
+        }
+
+        let config = self.read_config()?;
+
+        let mut tip = upstream.clone();
+        let mut tip_tree = self.tree_entries(&upstream)?;
+
+        for (sha, commit, parent) in commits.into_iter().rev() {
+            let parent_tree = match parent {
+                Some(parent) => self.tree_entries(&parent)?,
+                None => IndexMap::new(),
+            };
+            let commit_tree = self.tree_entries(&sha)?;
+
+            let result = crate::merge::merge_trees(&parent_tree, &tip_tree, &commit_tree);
+
+            if !result.conflicts.is_empty() {
+                anyhow::bail!(
+                    "could not apply {}: conflict in {}",
+                    sha,
+                    result.conflicts.join(", ")
+                );
+            }
+
+            let tree = self.write_tree_from_entries(&result.merged)?;
+
+            let new_commit = crate::objects::commit::Commit::new(
+                tree,
+                Some(tip),
+                config.user().identity()?,
+                signature_time("GIT_AUTHOR_DATE")?,
+                signature_time("GIT_COMMITTER_DATE")?,
+                commit.message().cloned().unwrap_or_default(),
+            )
+            .with_author(commit.author().context("commit has no author")?.clone());
+
+            tip = self.write_object(&GitObject::new(Fmt::Commit, new_commit.serialize()?))?;
+            tip_tree = result.merged;
+        }
+
+        // the branch is locked and checked against the head the commits were replayed from
+        let lock = self.lock_ref(&branch, Some(&head))?;
+        self.ensure_can_switch(&head_tree, &tip_tree, "rebase", hint)?;
+
+        self.save_orig_head()?;
+        self.update_work_tree(&head_tree, &tip_tree)?;
+        lock.commit(format!("{}\n", tip).as_bytes())
+            .context(format!("failed to update ref {}", branch))?;
+
+        Ok(Rebase::Rebased(tip))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// create an empty directory for a test, removing leftovers from previous runs
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("gitlet-test-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir.canonicalize().unwrap()
+    }
+
+    /// create a repository with a configured user
+    fn test_repo(name: &str) -> Repository {
+        let repo = Repository::init(temp_dir(name)).unwrap();
+
+        let mut config = fs::OpenOptions::new()
+            .append(true)
+            .open(repo.git_dir.join("config"))
+            .unwrap();
+        config
+            .write_all(b"[user]\nname = tester\nemail = tester@example.com\n")
+            .unwrap();
+
+        repo
+    }
+
+    /// write a file into the work tree, stage and commit it
+    fn commit_file(repo: &Repository, name: &str, content: &str, message: &str) -> String {
+        let path = repo.work_tree.join(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, content).unwrap();
+
+        repo.add(&vec![path.to_str().unwrap().to_string()]).unwrap();
+
+        repo.commit(message.to_string(), true).unwrap()
+    }
+
+    /// commit a file on a `side` branch starting at `base`, and switch back to master
+    fn side_commit(repo: &Repository, base: &str) -> String {
+        fs::write(repo.git_dir.join("refs/heads/side"), format!("{}\n", base)).unwrap();
+        fs::write(repo.git_dir.join("HEAD"), "ref: refs/heads/side\n").unwrap();
+        let side = commit_file(repo, "side.txt", "side", "side");
+        fs::write(repo.git_dir.join("HEAD"), "ref: refs/heads/master\n").unwrap();
+
+        side
+    }
+
+    /// write a merge commit of two commits, with the tree of the first one
+    fn merge_commit(repo: &Repository, a: &str, b: &str) -> String {
+        let tree = repo.read_commit(a).unwrap().tree().unwrap().clone();
+        let merge = format!(
+            "tree {tree}\nparent {a}\nparent {b}\nauthor t <t@t.com> 0 +0000\ncommitter t <t@t.com> 0 +0000\n\nmerge\n"
+        );
+
+        repo.write_object(&GitObject::new(Fmt::Commit, Bytes::from(merge)))
+            .unwrap()
+    }
+
+    /// move loose objects into a pack of whole objects, as `git gc` would
+    fn pack_objects(repo: &Repository, shas: &[String]) {
+        let mut pack = b"PACK\0\0\0\x02".to_vec();
+        pack.extend((shas.len() as u32).to_be_bytes());
+        let mut offsets = vec![];
+
+        for sha in shas {
+            let object = repo.read_object(sha).unwrap();
+            offsets.push((sha.clone(), pack.len()));
+
+            // type and size, 4 bits then 7 bits at a time
+            let kind = match object.header.fmt {
+                Fmt::Commit => 1,
+                Fmt::Tree => 2,
+                Fmt::Blob => 3,
+                Fmt::Tag => 4,
+            };
+            let mut size = object.data.len();
+            pack.push((kind << 4) | (size & 0xf) as u8);
+            size >>= 4;
+            while size > 0 {
+                *pack.last_mut().unwrap() |= 0x80;
+                pack.push((size & 0x7f) as u8);
+                size >>= 7;
+            }
+
+            let mut encoder =
+                flate2::write::ZlibEncoder::new(vec![], flate2::Compression::default());
+            encoder.write_all(&object.data).unwrap();
+            pack.extend(encoder.finish().unwrap());
+
+            fs::remove_file(repo.git_dir.join("objects").join(&sha[..2]).join(&sha[2..])).unwrap();
+        }
+        pack.extend([0; 20]);
+
+        offsets.sort();
+        let mut idx = b"\xfftOc\0\0\0\x02".to_vec();
+        for byte in 0..=255 {
+            let count = offsets
+                .iter()
+                .filter(|(sha, _)| hex::decode(&sha[..2]).unwrap()[0] <= byte)
+                .count() as u32;
+            idx.extend(count.to_be_bytes());
+        }
+        for (sha, _) in &offsets {
+            idx.extend(hex::decode(sha).unwrap());
+        }
+        idx.extend(vec![0; offsets.len() * 4]);
+        for (_, offset) in &offsets {
+            idx.extend((*offset as u32).to_be_bytes());
+        }
+        idx.extend([0; 40]);
+
+        let dir = repo.git_dir.join("objects/pack");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("pack-test.pack"), pack).unwrap();
+        fs::write(dir.join("pack-test.idx"), idx).unwrap();
+    }
+
+    #[test]
+    fn test_add_then_status_unchanged() {
+        let repo = test_repo("add-then-status");
+        let path = repo.work_tree.join("a.txt");
+        fs::write(&path, "a").unwrap();
+        repo.add(&vec![path.display().to_string()]).unwrap();
+
+        // the recorded stat data matches the file, no need to hash it
+        let index = repo.read_index().unwrap();
+        let metadata = path.metadata().unwrap();
+        assert!(index.get("a.txt", 0).unwrap().is_unchanged(&metadata));
+
+        let status = repo.status().unwrap();
+        assert_eq!(status.entries.len(), 1);
+        assert_eq!(status.entries[0].unstaged, '.');
+    }
+
+    #[test]
+    fn test_untracked_files() {
+        let repo = test_repo("untracked-files");
+        commit_file(&repo, "tracked.txt", "t", "first");
+
+        for name in ["b.txt", "sub/a.txt", "sub/skip.log", "a.txt"] {
+            let path = repo.work_tree.join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, name).unwrap();
+        }
+        fs::write(repo.git_dir.join("info/exclude"), "*.log\n").unwrap();
+
+        assert_eq!(
+            repo.untracked_files(false).unwrap(),
+            vec!["a.txt", "b.txt", "sub/a.txt"]
+        );
+        assert_eq!(
+            repo.untracked_files(true).unwrap(),
+            vec!["a.txt", "b.txt", "sub/a.txt", "sub/skip.log"]
+        );
+    }
+
+    #[test]
+    fn test_write_object_twice() {
+        let repo = Repository::init(temp_dir("write-object-twice")).unwrap();
+        let object = GitObject::new(Fmt::Blob, Bytes::from_static(b"hello"));
+
+        let first = repo.write_object(&object).unwrap();
+        let second = repo.write_object(&object).unwrap();
+
+        assert_eq!(first, second);
+        assert_eq!(repo.read_object(&first).unwrap().data, object.data);
+
+        // a failed write leaves neither the object nor its temporary file behind
+        let object = GitObject::new(Fmt::Blob, Bytes::from_static(b"world"));
+        let sha = object.sha().unwrap();
+        let dir = repo.git_dir.join("objects").join(&sha[..2]);
+        fs::write(&dir, "").unwrap();
+
+        assert!(repo.write_object(&object).is_err());
+        assert!(!dir.join(&sha[2..]).exists());
+        assert!(fs::read_dir(repo.git_dir.join("objects"))
+            .unwrap()
+            .all(|entry| !entry
+                .unwrap()
+                .file_name()
+                .to_string_lossy()
+                .starts_with("tmp_obj_")));
+    }
+
+    #[test]
+    fn test_write_blob_from_file() {
+        let repo = Repository::init(temp_dir("write-blob-from-file")).unwrap();
+
+        // larger than the read buffer
+        let data: Vec<u8> = (0..200_000u32).map(|n| (n % 251) as u8).collect();
+        let path = repo.work_tree.join("big.bin");
+        fs::write(&path, &data).unwrap();
+
+        let sha = repo.write_blob_from_file(&path).unwrap();
+        let object = GitObject::new(Fmt::Blob, Bytes::from(data));
+        assert_eq!(sha, object.sha().unwrap());
+        assert_eq!(repo.read_object(&sha).unwrap().data, object.data);
+
+        // writing it again keeps the object, and leaves no temporary file behind
+        assert_eq!(repo.write_blob_from_file(&path).unwrap(), sha);
+        assert!(fs::read_dir(repo.git_dir.join("objects"))
+            .unwrap()
+            .all(|entry| !entry
+                .unwrap()
+                .file_name()
+                .to_string_lossy()
+                .starts_with("tmp_obj_")));
+    }
+
+    #[test]
+    fn test_read_object_short_sha() {
+        let repo = Repository::init(temp_dir("read-object-short-sha")).unwrap();
+        let object = GitObject::new(Fmt::Blob, Bytes::from_static(b"hello"));
+        let sha = repo.write_object(&object).unwrap();
+
+        assert_eq!(repo.read_object(&sha[..7]).unwrap().data, object.data);
+        assert_eq!(
+            repo.read_object(&sha[..7].to_uppercase()).unwrap().data,
+            object.data
+        );
+
+        // too short or not a sha is an error rather than a panic
+        assert!(repo.read_object("c").is_err());
+        assert!(repo.read_object("HEAD").is_err());
+        assert!(repo.read_object("0000000").is_err());
+    }
+
+    #[test]
+    fn test_resolve_ancestry() {
+        let repo = test_repo("resolve-ancestry");
+        let first = commit_file(&repo, "a.txt", "1", "first");
+        let second = commit_file(&repo, "a.txt", "2", "second");
+        let third = commit_file(&repo, "a.txt", "3", "third");
+
+        let resolve = |name: &str| repo.resolve_object(name).unwrap().unwrap();
+        assert_eq!(resolve("HEAD~"), second);
+        assert_eq!(resolve("HEAD~2"), first);
+        assert_eq!(resolve("HEAD^"), second);
+        assert_eq!(resolve("HEAD^^"), first);
+        assert_eq!(resolve("HEAD^0"), third);
+        assert_eq!(resolve(&format!("{}~1^1", &third[..7])), first);
+
+        // a merge of third and first, so its second parent is first
+        let tree = repo.read_commit(&third).unwrap().tree().unwrap().clone();
+        let merge = GitObject::new(
+            Fmt::Commit,
+            Bytes::from(format!(
+                "tree {}\nparent {}\nparent {}\nauthor A <a@b> 0 +0000\n\
+                 committer A <a@b> 0 +0000\n\nmerge\n",
+                tree, third, first
+            )),
+        );
+        let merge = repo.write_object(&merge).unwrap();
+        assert_eq!(resolve(&format!("{}^2", merge)), first);
+        assert_eq!(resolve(&format!("{}~3", merge)), first);
+
+        let error = repo.resolve_object("HEAD~3").unwrap_err().to_string();
+        assert!(error.contains("no such parent"), "{}", error);
+        let error = repo.resolve_object("HEAD^2").unwrap_err().to_string();
+        assert!(error.contains("no such parent"), "{}", error);
+        let error = repo.resolve_object(&format!("{}^", tree)).unwrap_err();
+        assert!(error.to_string().contains("not a commit"), "{}", error);
+        assert!(repo.resolve_object("HEAD~x").is_err());
+        assert!(repo.resolve_object("missing~1").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_read_packed_object() {
+        let repo = Repository::init(temp_dir("read-packed-object")).unwrap();
+        let blob = |data: &[u8]| GitObject::new(Fmt::Blob, Bytes::copy_from_slice(data));
+        let zlib = |data: &[u8]| {
+            let mut encoder =
+                flate2::write::ZlibEncoder::new(vec![], flate2::Compression::default());
+            encoder.write_all(data).unwrap();
+            encoder.finish().unwrap()
+        };
+        // type and size, 4 bits then 7 bits at a time
+        let entry_header = |kind: u8, size: usize| {
+            let mut bytes = vec![(kind << 4) | (size & 0xf) as u8];
+            let mut size = size >> 4;
+            while size > 0 {
+                *bytes.last_mut().unwrap() |= 0x80;
+                bytes.push((size & 0x7f) as u8);
+                size >>= 7;
+            }
+            bytes
+        };
+
+        let base = b"hello world, this is the base of two deltas\n";
+        let appended = [&base[..], b"more\n"].concat();
+
+        // copy the whole base, then insert "more\n"
+        let mut ofs_delta = vec![base.len() as u8, appended.len() as u8];
+        ofs_delta.extend([0x90, base.len() as u8, 5]);
+        ofs_delta.extend(b"more\n");
+        // insert "new", then copy " world" at offset 5
+        let ref_delta = [base.len() as u8, 9, 3, b'n', b'e', b'w', 0x91, 5, 6];
+
+        let base_sha = blob(base).sha().unwrap();
+        let mut pack = b"PACK\0\0\0\x02\0\0\0\x03".to_vec();
+        let mut offsets = vec![];
+
+        offsets.push((base_sha.clone(), pack.len()));
+        pack.extend(entry_header(3, base.len()));
+        pack.extend(zlib(base));
+
+        let offset = pack.len();
+        offsets.push((blob(&appended).sha().unwrap(), offset));
+        pack.extend(entry_header(6, ofs_delta.len()));
+        pack.push((offset - offsets[0].1) as u8);
+        pack.extend(zlib(&ofs_delta));
+
+        offsets.push((blob(b"new world").sha().unwrap(), pack.len()));
+        pack.extend(entry_header(7, ref_delta.len()));
+        pack.extend(hex::decode(&base_sha).unwrap());
+        pack.extend(zlib(&ref_delta));
+        pack.extend([0; 20]);
+
+        offsets.sort();
+        let mut idx = b"\xfftOc\0\0\0\x02".to_vec();
+        for byte in 0..=255 {
+            let count = offsets
+                .iter()
+                .filter(|(sha, _)| hex::decode(&sha[..2]).unwrap()[0] <= byte)
+                .count() as u32;
+            idx.extend(count.to_be_bytes());
+        }
+        for (sha, _) in &offsets {
+            idx.extend(hex::decode(sha).unwrap());
+        }
+        idx.extend([0; 12]);
+        for (_, offset) in &offsets {
+            idx.extend((*offset as u32).to_be_bytes());
+        }
+        idx.extend([0; 40]);
+
+        let dir = repo.git_dir.join("objects/pack");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("pack-test.pack"), pack).unwrap();
+        fs::write(dir.join("pack-test.idx"), idx).unwrap();
+
+        for data in [&base[..], &appended, b"new world"] {
+            let sha = blob(data).sha().unwrap();
+            assert_eq!(repo.read_object(&sha).unwrap().data, data, "{}", sha);
+            assert_eq!(repo.read_object(&sha[..7]).unwrap().data, data);
+        }
+        assert_eq!(
+            repo.read_object_header(&base_sha).unwrap(),
+            ("blob".to_string(), base.len())
+        );
+        assert!(repo.read_object(&blob(b"missing").sha().unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_read_ignore_unstaged_root_gitignore() {
+        let repo = Repository::init(temp_dir("unstaged-gitignore")).unwrap();
+        fs::write(repo.work_tree.join(".gitignore"), "*.log\n").unwrap();
+
+        let ignore = repo.read_ignore().unwrap();
+
+        assert!(ignore.is_ignored("debug.log").unwrap());
+        assert_eq!(ignore.check("main.rs").unwrap(), None);
+    }
+
+    #[test]
+    fn test_read_ignore_info_exclude() {
+        let repo = Repository::init(temp_dir("info-exclude")).unwrap();
+        assert!(repo.git_dir.join("hooks").is_dir());
+
+        // the template is made of comments only
+        let exclude = repo.git_dir.join("info").join("exclude");
+        let mut data = fs::read_to_string(&exclude).unwrap();
+        assert!(data.contains("# *.[oa]\n"));
+        assert!(GitIgnore::parse(&data).is_empty());
+        assert_eq!(repo.read_ignore().unwrap().check("a.o").unwrap(), None);
+
+        data.push_str("*.tmp\n");
+        fs::write(&exclude, data).unwrap();
+
+        let ignore = repo.read_ignore().unwrap();
+
+        assert!(ignore.is_ignored("scratch.tmp").unwrap());
+        assert_eq!(ignore.check("main.rs").unwrap(), None);
+    }
+
+    #[test]
+    fn test_add_nested_path() {
+        let repo = Repository::init(temp_dir("add-nested-path")).unwrap();
+        fs::create_dir_all(repo.work_tree.join("sub").join("dir")).unwrap();
+
+        let path = repo.work_tree.join("sub").join("dir").join("file.txt");
+        fs::write(&path, "hello").unwrap();
+
+        repo.add(&vec![path.to_str().unwrap().to_string()]).unwrap();
+
+        let index = repo.read_index().unwrap();
+        assert_eq!(index.entries[0].name, "sub/dir/file.txt");
+
+        // the committed tree uses the same names, so status sees the file unchanged
+        let tree = repo.create_tree_from_index(&index).unwrap();
+        let head = repo.tree_to_dict(&tree).unwrap();
+        assert_eq!(
+            head.get("sub/dir/file.txt"),
+            Some(&index.entries[0].sha_hex())
+        );
+    }
+
+    #[test]
+    fn test_write_tree() {
+        let repo = test_repo("write-tree");
+        let commit = commit_file(&repo, "sub/a.txt", "a", "first");
+
+        let mut index = repo.read_index().unwrap();
+        let tree = repo.create_tree_from_index(&index).unwrap();
+        assert_eq!(&tree, repo.read_commit(&commit).unwrap().tree().unwrap());
+        assert_eq!(repo.read_object(&tree).unwrap().header.fmt, Fmt::Tree);
+
+        // a conflict left in the index cannot be written
+        index.upsert(IndexEntry {
+            name: "sub/a.txt".to_string(),
+            sha: index.entries[0].sha,
+            mode_type: 0b1000,
+            mode_perms: 0o644,
+            flag_stage: 2,
+            ..Default::default()
+        });
+        let error = repo.create_tree_from_index(&index).unwrap_err();
+        assert_eq!(error.to_string(), "sub/a.txt: unmerged (stage 2)");
+    }
+
+    #[test]
+    fn test_create_tree_from_index_nested() {
+        let repo = Repository::init(temp_dir("tree-nested")).unwrap();
+        let add = |name: &str, content: &str| {
+            let path = repo.work_tree.join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, content).unwrap();
+            repo.add(&vec![path.display().to_string()]).unwrap();
+        };
+
+        // a file three levels deep, with no file in the directories above it, as given
+        // by git write-tree
+        add("a/b/c/d.txt", "deep\n");
+        let tree = repo.create_tree_from_index(&repo.read_index().unwrap());
+        assert_eq!(tree.unwrap(), "8552fe03ccbb4377dfbcd4e9d904cc27614cd247");
+
+        // "b.txt" sorts before the directory "b", compared as "b/"
+        add("a/b.txt", "mid\n");
+        add("top.txt", "top\n");
+        add("x/y.txt", "x\n");
+        let tree = repo
+            .create_tree_from_index(&repo.read_index().unwrap())
+            .unwrap();
+        assert_eq!(tree, "333a520eb6ec5e4f8ca6775bedf9990ca686f24c");
+
+        // every intermediate tree is linked once, from its parent
+        let entries = repo.read_tree_recursive(&tree).unwrap();
+        assert_eq!(
+            entries.keys().collect::<Vec<_>>(),
+            vec!["a/b.txt", "a/b/c/d.txt", "top.txt", "x/y.txt"]
+        );
+    }
+
+    #[test]
+    fn test_commit_tree() {
+        let repo = test_repo("commit-tree");
+        let first = commit_file(&repo, "a.txt", "a", "first");
+        let tree = repo.read_commit(&first).unwrap().tree().unwrap().clone();
+
+        let sha = repo
+            .commit_tree(&tree, Some("HEAD"), "again".to_string())
+            .unwrap();
+        let commit = repo.read_commit(&sha).unwrap();
+        assert_eq!(commit.tree(), Some(&tree));
+        assert_eq!(commit.parents(), Some(&vec![first.clone()]));
+        assert_eq!(commit.message().unwrap(), "again\n");
+        assert!(commit
+            .author()
+            .unwrap()
+            .starts_with("tester <tester@example.com> "));
+
+        // no ref moves
+        assert_eq!(repo.resolve_ref("HEAD").unwrap(), Some(first));
+
+        let root = repo
+            .commit_tree("HEAD", None, "root\n".to_string())
+            .unwrap();
+        assert_eq!(repo.read_commit(&root).unwrap().parents(), None);
+        assert!(repo.commit_tree("missing", None, "x".to_string()).is_err());
+        assert!(repo
+            .commit_tree(&tree, Some(&tree), "x".to_string())
+            .is_err());
+    }
+
+    #[test]
+    fn test_add_several_paths() {
+        let repo = Repository::init(temp_dir("add-several-paths")).unwrap();
+        let foo = repo.work_tree.join("foo.rs");
+        let bar = repo.work_tree.join("bar.rs");
+        fs::write(&foo, "foo").unwrap();
+        fs::write(&bar, "bar").unwrap();
+
+        let path = |path: &PathBuf| path.to_str().unwrap().to_string();
+
+        // a missing path fails the whole add, nothing is staged
+        let err = repo
+            .add(&vec![path(&foo), path(&repo.work_tree.join("missing.rs"))])
+            .unwrap_err();
+        assert!(err.to_string().contains("did not match any files"));
+        assert!(repo.read_index().unwrap().entries.is_empty());
+
+        let outside = temp_dir("add-several-paths-outside").join("out.rs");
+        fs::write(&outside, "out").unwrap();
+        assert!(repo.add(&vec![path(&outside)]).is_err());
+
+        repo.add(&vec![path(&foo), path(&bar)]).unwrap();
+        let names: Vec<_> = repo
+            .read_index()
+            .unwrap()
+            .entries
+            .into_iter()
+            .map(|entry| entry.name)
+            .collect();
+        assert_eq!(names, vec!["bar.rs", "foo.rs"]);
+    }
+
+    #[test]
+    fn test_resolve_full_sha_without_loose_object() {
+        let repo = Repository::init(temp_dir("resolve-full-sha")).unwrap();
+        let sha = "0123456789abcdef0123456789abcdef01234567";
+
+        assert_eq!(repo.resolve_object(sha).unwrap(), Some(sha.to_string()));
+        assert_eq!(repo.resolve_object(&sha[..7]).unwrap(), None);
+    }
+
+    #[test]
+    fn test_find_object_follow() {
+        let repo = Repository::init(temp_dir("find-object-follow")).unwrap();
+
+        let blob = repo
+            .write_object(&GitObject::new(Fmt::Blob, Bytes::from_static(b"hello")))
+            .unwrap();
+
+        // non-tag objects are returned as is
+        assert_eq!(repo.find_object(&blob, true).unwrap(), Some(blob.clone()));
+
+        let missing = "0123456789abcdef0123456789abcdef01234567";
+        let data = format!(
+            "object {missing}\ntype commit\ntag v1\ntagger t <t@t.com> 0 +0000\n\nbroken\n"
+        );
+        let tag = repo
+            .write_object(&GitObject::new(Fmt::Tag, Bytes::from(data)))
+            .unwrap();
+
+        let err = repo.find_object(&tag, true).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!("tag {} points at missing object {}", tag, missing)
+        );
+
+        // without follow, the tag object itself is returned
+        assert_eq!(repo.find_object(&tag, false).unwrap(), Some(tag));
+    }
+
+    #[test]
+    fn test_peeled_refs() {
+        let repo = test_repo("peeled-refs");
+        let commit = commit_file(&repo, "a.txt", "a", "first");
+
+        let data =
+            format!("object {commit}\ntype commit\ntag v1\ntagger t <t@t.com> 0 +0000\n\nv1\n");
+        let tag = repo
+            .write_object(&GitObject::new(Fmt::Tag, Bytes::from(data)))
+            .unwrap();
+        fs::write(repo.git_dir.join("refs/tags/v1"), format!("{}\n", tag)).unwrap();
+        fs::write(
+            repo.git_dir.join("refs/tags/light"),
+            format!("{}\n", commit),
+        )
+        .unwrap();
+
+        let refs = repo.peeled_refs().unwrap();
+
+        assert_eq!(refs["refs/heads/master"], (commit.clone(), None));
+        assert_eq!(refs["refs/tags/light"], (commit.clone(), None));
+        assert_eq!(refs["refs/tags/v1"], (tag, Some(commit)));
+    }
+
+    #[test]
+    fn test_branches_and_tags() {
+        let repo = test_repo("branches-and-tags");
+        let first = commit_file(&repo, "a.txt", "a", "first");
+        let second = commit_file(&repo, "a.txt", "b", "second");
+
+        fs::create_dir_all(repo.git_dir.join("refs/heads/feature")).unwrap();
+        fs::write(
+            repo.git_dir.join("refs/heads/feature/x"),
+            format!("{}\n", first),
+        )
+        .unwrap();
+        // loose refs override packed ones
+        fs::write(
+            repo.git_dir.join("packed-refs"),
+            format!(
+                "# pack-refs with: peeled\n{first} refs/heads/master\n{first} refs/heads/old\n{first} refs/tags/v0\n"
+            ),
+        )
+        .unwrap();
+        fs::write(repo.git_dir.join("refs/tags/v1"), format!("{}\n", second)).unwrap();
+
+        assert_eq!(
+            repo.branches().unwrap(),
+            vec![
+                Branch {
+                    name: "feature/x".to_string(),
+                    sha: first.clone()
+                },
+                Branch {
+                    name: "master".to_string(),
+                    sha: second.clone()
+                },
+                Branch {
+                    name: "old".to_string(),
+                    sha: first.clone()
+                },
+            ]
+        );
+
+        let tags = repo.tags().unwrap();
+        assert_eq!(
+            tags,
+            vec![
+                Tag::new("v0".to_string(), first),
+                Tag::new("v1".to_string(), second)
+            ]
+        );
+        assert_eq!(tags[0].peeled(), None);
+    }
+
+    #[test]
+    fn test_create_and_delete_branch() {
+        let repo = test_repo("create-delete-branch");
+        let first = commit_file(&repo, "a.txt", "a", "first");
+        let second = commit_file(&repo, "a.txt", "b", "second");
+
+        assert_eq!(repo.create_branch("feature/x", "HEAD~").unwrap(), first);
+        assert_eq!(repo.create_branch("topic", "HEAD").unwrap(), second);
+        assert!(repo.create_branch("topic", "HEAD").is_err());
+        assert!(repo.create_branch("bad..name", "HEAD").is_err());
+        assert!(repo.create_branch("other", "missing").is_err());
+        assert_eq!(
+            repo.branches()
+                .unwrap()
+                .iter()
+                .map(|b| b.name.as_str())
+                .collect::<Vec<_>>(),
+            vec!["feature/x", "master", "topic"]
+        );
+
+        let error = repo.delete_branch("master").unwrap_err().to_string();
+        assert!(error.contains("cannot delete branch 'master'"), "{}", error);
+        assert!(repo.delete_branch("missing").is_err());
+        // a name leaving refs/heads is refused before anything is removed
+        assert!(repo.delete_branch("../../config").is_err());
+        assert!(repo.git_dir.join("config").is_file());
+
+        assert_eq!(repo.delete_branch("feature/x").unwrap(), first);
+        assert!(!repo.git_dir.join("refs/heads/feature").exists());
+
+        // a packed branch is removed from packed-refs
+        fs::remove_file(repo.git_dir.join("refs/heads/topic")).unwrap();
+        fs::write(
+            repo.git_dir.join("packed-refs"),
+            format!("{second} refs/heads/topic\n{first} refs/tags/v1\n"),
+        )
+        .unwrap();
+
+        // another process rewriting packed-refs holds its lock
+        let lock = repo.git_dir.join("packed-refs.lock");
+        fs::write(&lock, "").unwrap();
+        assert!(repo.delete_branch("topic").is_err());
+        assert_eq!(
+            repo.resolve_ref("refs/heads/topic").unwrap(),
+            Some(second.clone())
+        );
+        fs::remove_file(&lock).unwrap();
+
+        assert_eq!(repo.delete_branch("topic").unwrap(), second);
+        assert!(!lock.exists());
+        assert_eq!(repo.resolve_ref("refs/heads/topic").unwrap(), None);
+        assert_eq!(repo.resolve_ref("refs/tags/v1").unwrap(), Some(first));
+    }
+
+    #[test]
+    fn test_diff() {
+        let repo = test_repo("diff");
+        commit_file(&repo, "kept.txt", "same\n", "first");
+        commit_file(&repo, "gone.txt", "bye\n", "second");
+        commit_file(&repo, "a.txt", "1\n2\n3\n", "third");
+        let path = |name: &str| repo.work_tree.join(name).display().to_string();
+        fs::write(path("a.txt"), "1\ntwo\n3\n").unwrap();
+        repo.add(&vec![path("a.txt")]).unwrap();
+        repo.rm(&vec![path("gone.txt")], true, false).unwrap();
+        commit_file(&repo, "bin", "\0\x01", "fourth");
+
+        let sha = |content: &str| {
+            GitObject::new(Fmt::Blob, Bytes::copy_from_slice(content.as_bytes()))
+                .sha()
+                .unwrap()[..7]
+                .to_string()
+        };
+
+        assert_eq!(
+            repo.diff("HEAD~", "HEAD").unwrap(),
+            format!(
+                "diff --git a/a.txt b/a.txt\n\
+                 index {}..{} 100644\n\
+                 --- a/a.txt\n\
+                 +++ b/a.txt\n\
+                 @@ -1,3 +1,3 @@\n 1\n-2\n+two\n 3\n\
+                 diff --git a/bin b/bin\n\
+                 new file mode 100644\n\
+                 index 0000000..{}\n\
+                 Binary files /dev/null and b/bin differ\n\
+                 diff --git a/gone.txt b/gone.txt\n\
+                 deleted file mode 100644\n\
+                 index {}..0000000\n\
+                 --- a/gone.txt\n\
+                 +++ /dev/null\n\
+                 @@ -1 +0,0 @@\n-bye\n",
+                sha("1\n2\n3\n"),
+                sha("1\ntwo\n3\n"),
+                sha("\0\x01"),
+                sha("bye\n"),
+            )
+        );
+        assert_eq!(repo.diff("HEAD", "HEAD").unwrap(), "");
+    }
+
+    #[test]
+    fn test_log() {
+        let repo = test_repo("log");
+        let first = commit_file(&repo, "a.txt", "1", "first");
+        let second = commit_file(&repo, "a.txt", "2", "second\n\nbody");
+
+        let log = repo.log("HEAD", None, false).unwrap();
+        let entries: Vec<&str> = log.split("\ncommit ").collect();
+        assert_eq!(entries.len(), 2);
+        assert!(entries[0].starts_with(&format!(
+            "commit {}\nAuthor: tester <tester@example.com>\nDate:   ",
+            second
+        )));
+        assert!(entries[0].ends_with("\n\n    second\n    \n    body\n"));
+        assert!(entries[1].starts_with(&first));
+        assert!(entries[1].ends_with("\n\n    first\n"));
+
+        assert_eq!(
+            repo.log("HEAD", Some(1), true).unwrap(),
+            format!("{} second\n", repo.abbrev(&second, 7).unwrap())
+        );
+        assert_eq!(repo.log("HEAD", Some(0), true).unwrap(), "");
+    }
+
+    #[test]
+    fn test_show() {
+        let repo = test_repo("show");
+        let first = commit_file(&repo, "a.txt", "1\n", "first");
+        let second = commit_file(&repo, "a.txt", "2\n", "second");
+
+        // a root commit is diffed against the empty tree
+        let show = repo.show(&first).unwrap();
+        assert!(show.starts_with(&format!(
+            "commit {}\nAuthor: tester <tester@example.com>\nDate:   ",
+            first
+        )));
+        assert!(show.ends_with(
+            "\n\n    first\n\n\
+             diff --git a/a.txt b/a.txt\n\
+             new file mode 100644\n\
+             index 0000000..d00491f\n\
+             --- /dev/null\n\
+             +++ b/a.txt\n\
+             @@ -0,0 +1 @@\n+1\n"
+        ));
+
+        let show = repo.show("HEAD").unwrap();
+        assert!(show.starts_with(&format!("commit {}\n", second)));
+        assert!(show.ends_with("@@ -1 +1 @@\n-1\n+2\n"));
+
+        // a tag is followed by the object it points at
+        let tag = repo
+            .write_tag_object("v1", &second, "Release 1\n".to_string())
+            .unwrap();
+        let show = repo.show(&tag).unwrap();
+        assert!(show.starts_with("tag v1\nTagger: tester <tester@example.com>\nDate:   "));
+        assert!(show.ends_with(&format!("\n\nRelease 1\n\n{}", repo.show(&second).unwrap())));
+
+        let tree = repo.read_commit(&second).unwrap().tree().unwrap().clone();
+        assert_eq!(
+            repo.show(&tree).unwrap(),
+            format!("tree {}\n\na.txt\n", tree)
+        );
+        let blob = repo.tree_to_dict(&tree).unwrap()["a.txt"].clone();
+        assert_eq!(repo.show(&blob).unwrap(), "2\n");
+    }
+
+    #[test]
+    fn test_packed_refs() {
+        let repo = test_repo("packed-refs");
+        let first = commit_file(&repo, "a.txt", "a", "first");
+        let second = commit_file(&repo, "a.txt", "b", "second");
+        let tag = repo
+            .write_tag_object("v1", &first, "release\n".to_string())
+            .unwrap();
+
+        // everything packed as git pack-refs --all leaves it, except master
+        fs::remove_dir_all(repo.git_dir.join("refs/tags")).unwrap();
+        fs::write(
+            repo.git_dir.join("packed-refs"),
+            format!(
+                "# pack-refs with: peeled fully-peeled sorted\n\
+                 {first} refs/heads/master\n\
+                 {first} refs/heads/old\n\
+                 {tag} refs/tags/v1\n\
+                 ^{first}\n"
+            ),
+        )
+        .unwrap();
+
+        assert_eq!(
+            repo.resolve_ref("refs/heads/old").unwrap(),
+            Some(first.clone())
+        );
+        assert_eq!(
+            repo.resolve_ref("refs/heads/master").unwrap(),
+            Some(second.clone())
+        );
+        assert_eq!(repo.resolve_ref("refs/heads/none").unwrap(), None);
+        assert_eq!(repo.resolve_object("old").unwrap(), Some(first.clone()));
+        assert_eq!(repo.resolve_object("v1").unwrap(), Some(tag.clone()));
+
+        let refs = repo.refs().unwrap();
+        assert_eq!(
+            refs.into_iter().collect::<Vec<_>>(),
+            vec![
+                ("refs/heads/master".to_string(), second),
+                ("refs/heads/old".to_string(), first.clone()),
+                ("refs/tags/v1".to_string(), tag.clone()),
+            ]
+        );
+        assert_eq!(
+            repo.peeled_refs().unwrap()["refs/tags/v1"],
+            (tag, Some(first))
+        );
+    }
+
+    #[test]
+    fn test_status_porcelain_v2() {
+        let repo = test_repo("status-porcelain-v2");
+        commit_file(&repo, "kept.txt", "kept", "first");
+        commit_file(&repo, "gone.txt", "gone", "second");
+        let head = repo.read_tree_recursive("HEAD").unwrap();
+        let path = |name: &str| repo.work_tree.join(name).display().to_string();
+
+        repo.rm(&vec![path("gone.txt")], true, false).unwrap();
+        fs::write(path("kept.txt"), "changed").unwrap();
+        fs::write(path("new.txt"), "new").unwrap();
+        repo.add(&vec![path("new.txt")]).unwrap();
+        fs::write(path("untracked.txt"), "?").unwrap();
+
+        let status = repo.status().unwrap();
+        let lines: Vec<_> = status.entries.iter().map(|e| e.porcelain_v2()).collect();
+        let null = "0".repeat(40);
+        let new = GitObject::new(Fmt::Blob, Bytes::from("new")).sha().unwrap();
+
+        assert_eq!(
+            lines,
+            vec![
+                format!(
+                    "1 D. N... 100644 000000 000000 {} {} gone.txt",
+                    head["gone.txt"].1, null
+                ),
+                format!(
+                    "1 .M N... 100644 100644 100644 {0} {0} kept.txt",
+                    head["kept.txt"].1
+                ),
+                format!("1 A. N... 000000 100644 100644 {} {} new.txt", null, new),
+            ]
+        );
+        assert_eq!(status.untracked, vec!["untracked.txt".to_string()]);
+    }
+
+    #[test]
+    fn test_add_patch() {
+        let repo = test_repo("add-patch");
+        let lines = |changed: &[usize]| -> String {
+            (1..=10)
+                .map(|i| {
+                    if changed.contains(&i) {
+                        format!("line {} changed\n", i)
+                    } else {
+                        format!("line {}\n", i)
+                    }
+                })
+                .collect()
+        };
+        commit_file(&repo, "a.txt", &lines(&[]), "first");
+        commit_file(&repo, "b.txt", "b\n", "second");
+        commit_file(&repo, "c.txt", "c\n", "third");
+
+        // a.txt has two hunks, far enough apart not to share one
+        fs::write(repo.work_tree.join("a.txt"), lines(&[1, 10])).unwrap();
+        fs::write(repo.work_tree.join("b.txt"), "changed\n").unwrap();
+        fs::write(repo.work_tree.join("c.txt"), "changed\n").unwrap();
+
+        let mut asked = vec![];
+        let staged = repo
+            .add_patch(|path, hunk| {
+                asked.push((path.to_string(), hunk.lines().next().unwrap().to_string()));
+                Ok(match (path, asked.len()) {
+                    ("a.txt", 1) => Some(false),
+                    ("a.txt", _) => Some(true),
+                    ("b.txt", _) => Some(true),
+                    _ => None,
+                })
+            })
+            .unwrap();
+
+        assert_eq!(
+            asked,
+            vec![
+                ("a.txt".to_string(), "@@ -1,4 +1,4 @@".to_string()),
+                ("a.txt".to_string(), "@@ -7,4 +7,4 @@".to_string()),
+                ("b.txt".to_string(), "@@ -1 +1 @@".to_string()),
+                ("c.txt".to_string(), "@@ -1 +1 @@".to_string()),
+            ]
+        );
+        assert_eq!(staged, vec!["a.txt", "b.txt"]);
+
+        // only the second hunk of a.txt is staged, the first is left in the work tree
+        let index = repo.read_index().unwrap();
+        let blob = repo
+            .read_object(&index.get("a.txt", 0).unwrap().sha_hex())
+            .unwrap();
+        assert_eq!(blob.data, lines(&[10]).as_bytes());
+        assert_eq!(
+            fs::read_to_string(repo.work_tree.join("a.txt")).unwrap(),
+            lines(&[1, 10])
+        );
+
+        let status = repo.status().unwrap();
+        let states: Vec<_> = status
+            .entries
+            .iter()
+            .map(|e| (e.path.as_str(), e.staged, e.unstaged))
+            .collect();
+        assert_eq!(
+            states,
+            vec![
+                ("a.txt", 'M', 'M'),
+                ("b.txt", 'M', '.'),
+                ("c.txt", '.', 'M')
+            ]
+        );
+    }
+
+    #[test]
+    fn test_find_object_peeled() {
+        let repo = test_repo("find-object-peeled");
+        let commit = commit_file(&repo, "a.txt", "a", "first");
+        let tree = repo.read_commit(&commit).unwrap().tree().unwrap().clone();
+
+        let tag = |target: &str, fmt: &str| {
+            let data =
+                format!("object {target}\ntype {fmt}\ntag t\ntagger t <t@t.com> 0 +0000\n\nt\n");
+            repo.write_object(&GitObject::new(Fmt::Tag, Bytes::from(data)))
+                .unwrap()
+        };
+        let inner = tag(&commit, "commit");
+        let outer = tag(&inner, "tag");
+
+        // no peeling, full peeling, and peeling to a kind of object
+        assert_eq!(
+            repo.find_object(&outer, false).unwrap(),
+            Some(outer.clone())
+        );
+        assert_eq!(
+            repo.find_object(&outer, true).unwrap(),
+            Some(commit.clone())
+        );
+        assert_eq!(
+            repo.find_object_peeled(&outer, Some(Fmt::Tag)).unwrap(),
+            Some(outer.clone())
+        );
+        assert_eq!(
+            repo.find_object_peeled(&outer, Some(Fmt::Commit)).unwrap(),
+            Some(commit.clone())
+        );
+        assert_eq!(
+            repo.find_object_peeled(&outer, Some(Fmt::Tree)).unwrap(),
+            Some(tree)
+        );
+        assert!(repo.find_object_peeled(&outer, Some(Fmt::Blob)).is_err());
+        assert!(repo.find_object_peeled(&commit, Some(Fmt::Tag)).is_err());
+    }
+
+    #[test]
+    fn test_write_object_literally() {
+        let repo = Repository::init(temp_dir("write-object-literally")).unwrap();
+
+        let data = GitObject::serialize_literally("bogus", b"hello").unwrap();
+        let sha = repo.write_raw_object(&data).unwrap();
+
+        assert_eq!(sha, crate::utils::sha(b"bogus 5\0hello"));
+        assert!(repo
+            .git_dir
+            .join("objects")
+            .join(&sha[..2])
+            .join(&sha[2..])
+            .exists());
+
+        // known types serialize as usual
+        let blob = GitObject::new(Fmt::Blob, Bytes::from_static(b"hello"));
+        assert_eq!(
+            GitObject::serialize_literally("blob", b"hello").unwrap(),
+            blob.serialize().unwrap()
+        );
+
+        assert!(GitObject::serialize_literally("two words", b"").is_err());
+
+        // only the header can be read back
+        assert!(repo.read_object(&sha).is_err());
+        assert_eq!(
+            repo.read_object_header(&sha).unwrap(),
+            ("bogus".to_string(), 5)
+        );
+    }
+
+    #[test]
+    fn test_write_object_without_fsync() {
+        let mut repo = Repository::init(temp_dir("write-object-without-fsync")).unwrap();
+        repo.config
+            .0
+            .set("core", "fsyncObjectFiles", Some("false".to_string()));
+
+        let sha = repo
+            .write_object(&GitObject::new(Fmt::Blob, Bytes::from_static(b"hello")))
+            .unwrap();
+
+        assert_eq!(&repo.read_object(&sha).unwrap().data[..], b"hello");
+    }
+
+    #[test]
+    fn test_resolve_at_alias() {
+        let repo = test_repo("resolve-at-alias");
+        let commit = commit_file(&repo, "a.txt", "a", "first");
+
+        assert_eq!(repo.resolve_object("@").unwrap(), Some(commit));
+        assert_eq!(
+            repo.resolve_object("@").unwrap(),
+            repo.resolve_object("HEAD").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_resolve_pseudo_refs() {
+        let repo = test_repo("resolve-pseudo-refs");
+        let first = commit_file(&repo, "a.txt", "a", "first");
+
+        assert_eq!(repo.resolve_object("ORIG_HEAD").unwrap(), None);
+        repo.save_orig_head().unwrap();
+
+        let second = commit_file(&repo, "a.txt", "b", "second");
+        assert_eq!(
+            repo.resolve_object("ORIG_HEAD").unwrap(),
+            Some(first.clone())
+        );
+
+        fs::write(repo.git_dir.join("MERGE_HEAD"), format!("{}\n", second)).unwrap();
+        assert_eq!(repo.resolve_object("MERGE_HEAD").unwrap(), Some(second));
+
+        fs::write(
+            repo.git_dir.join("FETCH_HEAD"),
+            format!("{}\t\tbranch 'master' of ../other\n", first),
+        )
+        .unwrap();
+        assert_eq!(repo.resolve_object("FETCH_HEAD").unwrap(), Some(first));
+    }
+
+    #[test]
+    fn test_config_entries() {
+        let repo = test_repo("config-entries");
+
+        let entries = repo.config_entries().unwrap();
+        let config_path = repo.git_dir.join("config").canonicalize().unwrap();
+
+        let entry = entries
+            .iter()
+            .find(|e| e.origin == config_path && e.key == "user.email")
+            .unwrap();
+        assert_eq!(entry.value.as_deref(), Some("tester@example.com"));
+
+        assert!(entries
+            .iter()
+            .any(|e| e.origin == config_path && e.key == "core.bare"));
+    }
+
+    #[test]
+    fn test_config_cache() {
+        let mut repo = test_repo("config-cache");
+        assert_eq!(repo.read_config().unwrap().get("core", "hooksPath"), None);
+
+        // a set is visible right away
+        repo.set_config("core.hooksPath", "hooks").unwrap();
+        repo.set_config("remote.origin.url", "../origin").unwrap();
+        let config = repo.read_config().unwrap();
+        assert_eq!(config.get("core", "hooksPath").as_deref(), Some("hooks"));
+        assert_eq!(
+            config.get_key("remote.origin.url").unwrap().as_deref(),
+            Some("../origin")
+        );
+        assert_eq!(config.get("user", "name").as_deref(), Some("tester"));
+        assert!(repo.set_config("bare", "true").is_err());
+
+        // an edit of the file is only seen after a reload
+        let mut file = fs::OpenOptions::new()
+            .append(true)
+            .open(repo.git_dir.join("config"))
+            .unwrap();
+        file.write_all(b"[commit]\ncleanup = strip\n").unwrap();
+        assert_eq!(repo.read_config().unwrap().get("commit", "cleanup"), None);
+
+        repo.reload_config();
+        assert_eq!(
+            repo.read_config()
+                .unwrap()
+                .get("commit", "cleanup")
+                .as_deref(),
+            Some("strip")
+        );
+    }
+
+    #[test]
+    fn test_set_config_line() {
+        let text = "# written by git\n[core]\n\tbare = false\n[remote \"Origin\"]\n\turl = ../a\n\tfetch = +refs/heads/a:refs/a\n\tfetch = +refs/heads/b:refs/b\n";
+
+        // the value is replaced in place, the comment and the case of names are kept
+        assert_eq!(
+            set_config_line(text, "remote.Origin.URL", "../b").unwrap(),
+            text.replace("url = ../a", "url = ../b")
+        );
+        // a new key goes at the end of its section
+        assert_eq!(
+            set_config_line(text, "core.editor", "vi").unwrap(),
+            text.replace("bare = false\n", "bare = false\n\teditor = vi\n")
+        );
+        // subsections are case-sensitive, a new section is added at the end
+        assert_eq!(
+            set_config_line(text, "remote.origin.url", " a;b").unwrap(),
+            format!("{}[remote \"origin\"]\n\turl = \" a;b\"\n", text)
+        );
+        assert!(set_config_line(text, "remote.Origin.fetch", "x").is_err());
+        assert_eq!(
+            set_config_line("", "core.bare", "true").unwrap(),
+            "[core]\n\tbare = true\n"
+        );
+    }
+
+    #[test]
+    fn test_config_user() {
+        let config = |name: Option<&str>, email: Option<&str>| {
+            let mut ini = configparser::ini::Ini::new();
+            if let Some(name) = name {
+                ini.setstr("user", "name", Some(name));
+            }
+            if let Some(email) = email {
+                ini.setstr("user", "email", Some(email));
+            }
+            RepoConfig(ini)
+        };
+
+        assert_eq!(
+            config(Some("tester"), Some("t@t.com")).user(),
+            User::Identity("tester <t@t.com>".to_string())
+        );
+        assert_eq!(config(None, Some("t@t.com")).user(), User::MissingName);
+        assert_eq!(config(Some("tester"), None).user(), User::MissingEmail);
+        assert_eq!(config(None, None).user(), User::MissingBoth);
+
+        let err = config(Some("tester"), None).user().identity().unwrap_err();
+        assert!(err.to_string().contains("user.email"));
+    }
+
+    #[test]
+    fn test_commit_date_from_env() {
+        let date = |value: Option<&str>| {
+            parse_signature_time("GIT_AUTHOR_DATE", value.map(str::to_string))
+        };
+
+        let time = date(Some("1700000000 +0800")).unwrap();
+        assert_eq!(
+            crate::objects::commit::format_time(time),
+            "1700000000 +0800"
+        );
+
+        assert!(date(Some("yesterday")).is_err());
+        assert!(date(None).unwrap() > chrono::Utc::now() - chrono::Duration::minutes(1));
+    }
+
+    #[test]
+    fn test_find_with_git_dir_env() {
+        let repo = test_repo("find-git-dir-env");
+        let commit = commit_file(&repo, "a.txt", "a", "first");
+
+        let elsewhere = temp_dir("find-git-dir-env-elsewhere");
+
+        let git_dir = repo.git_dir.clone();
+        let found = Repository::find_with(&elsewhere, |name| {
+            (name == "GIT_DIR").then(|| git_dir.clone().into_os_string())
+        })
+        .unwrap();
+        assert_eq!(found.git_dir, repo.git_dir);
+        assert_eq!(found.work_tree, elsewhere);
+        assert_eq!(found.resolve_object("HEAD").unwrap(), Some(commit));
+    }
+
+    #[test]
+    fn test_find_with_index_file_env() {
+        let repo = test_repo("find-index-file-env");
+        commit_file(&repo, "a.txt", "a", "first");
+        let index_file = temp_dir("find-index-file-env-elsewhere").join("index");
+
+        let found = Repository::find_with(&repo.work_tree, |name| {
+            (name == "GIT_INDEX_FILE").then(|| index_file.clone().into_os_string())
+        })
+        .unwrap();
+
+        // the alternate index starts empty, and the real one is left alone
+        assert_eq!(found.index_path(), index_file);
+        assert!(found.read_index().unwrap().entries.is_empty());
+
+        let path = repo.work_tree.join("b.txt");
+        fs::write(&path, "b").unwrap();
+        found.add(&vec![path.display().to_string()]).unwrap();
+
+        assert!(index_file.is_file());
+        assert!(found.read_index().unwrap().contains("b.txt"));
+        assert!(!repo.read_index().unwrap().contains("b.txt"));
+    }
+
+    #[test]
+    fn test_rebase() {
+        let repo = test_repo("rebase");
+        let base = commit_file(&repo, "a.txt", "a", "base");
+        fs::write(repo.git_dir.join("refs/heads/feature"), &base).unwrap();
+
+        let upstream = commit_file(&repo, "b.txt", "b", "upstream");
+
+        // switch to feature, which does not have b.txt
+        fs::write(repo.git_dir.join("HEAD"), "ref: refs/heads/feature\n").unwrap();
+        let b = repo.work_tree.join("b.txt").to_str().unwrap().to_string();
+        repo.rm(&vec![b], true, false).unwrap();
+
+        let feature = commit_file(&repo, "c/c.txt", "c", "feature");
+
+        // another process updating the branch holds its lock, nothing is touched
+        let lock = repo.git_dir.join("refs/heads/feature.lock");
+        fs::write(&lock, "").unwrap();
+        assert!(repo.rebase("master").is_err());
+        assert_eq!(
+            repo.resolve_object("feature").unwrap(),
+            Some(feature.clone())
+        );
+        assert!(!repo.work_tree.join("b.txt").exists());
+        fs::remove_file(&lock).unwrap();
+
+        let Rebase::Rebased(tip) = repo.rebase("master").unwrap() else {
+            panic!("expected the branch to be rebased");
+        };
+
+        assert_eq!(repo.resolve_object("feature").unwrap(), Some(tip.clone()));
+        assert_eq!(
+            repo.resolve_object("ORIG_HEAD").unwrap(),
+            Some(feature.clone())
+        );
+
+        let commit = repo.read_commit(&tip).unwrap();
+        assert_eq!(commit.parents(), Some(&vec![upstream.clone()]));
+        assert_eq!(commit.message().unwrap(), "feature\n");
+        assert_eq!(
+            commit.author(),
+            repo.read_commit(&feature).unwrap().author()
+        );
+
+        let tree = repo.tree_to_dict(&tip).unwrap();
+        let paths: Vec<_> = tree.keys().map(|k| k.as_str()).collect();
+        assert_eq!(paths, vec!["a.txt", "b.txt", "c/c.txt"]);
+
+        // the work tree and the index follow the branch
+        assert_eq!(
+            fs::read_to_string(repo.work_tree.join("b.txt")).unwrap(),
+            "b"
+        );
+        assert!(repo.staged_tree_matches_head().unwrap());
+
+        assert_eq!(repo.rebase("master").unwrap(), Rebase::UpToDate);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_checkout_executable() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let repo = test_repo("checkout-executable");
+        let path = repo.work_tree.join("run.sh");
+        let mode = || fs::metadata(&path).unwrap().permissions().mode() & 0o777;
+
+        commit_file(&repo, "run.sh", "echo", "plain");
+        assert_eq!(
+            repo.read_tree_recursive("HEAD").unwrap()["run.sh"].0,
+            "100644"
+        );
+
+        // add records the executable bit
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        repo.add(&vec![path.display().to_string()]).unwrap();
+        let executable = repo.commit("executable".to_string(), true).unwrap();
+        assert_eq!(
+            repo.read_tree_recursive("HEAD").unwrap()["run.sh"].0,
+            "100755"
+        );
+
+        // a mode change alone is checked out both ways
+        repo.checkout("HEAD~", false).unwrap();
+        assert_eq!(mode(), 0o644);
+        repo.checkout(&executable, false).unwrap();
+        assert_eq!(mode(), 0o755);
+        assert_eq!(repo.status().unwrap(), Status::default());
+
+        // a file written anew gets it too, and adding it again keeps it
+        fs::remove_file(&path).unwrap();
+        repo.checkout(&executable, true).unwrap();
+        assert_eq!(mode() & 0o111, 0o111);
+        repo.add(&vec![path.display().to_string()]).unwrap();
+        assert_eq!(repo.read_index().unwrap().entries[0].mode(), 0o100755);
+
+        let dir = temp_dir("checkout-executable-to");
+        repo.checkout_to(&executable, &dir.join("out")).unwrap();
+        let copy = fs::metadata(dir.join("out/run.sh")).unwrap();
+        assert_eq!(copy.permissions().mode() & 0o111, 0o111);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_rebase_keeps_modes() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let repo = test_repo("rebase-modes");
+        commit_file(&repo, "a.txt", "a", "a");
+
+        // an executable and a symlink, which the rebased commit does not touch
+        let run = repo.work_tree.join("run.sh");
+        fs::write(&run, "echo").unwrap();
+        fs::set_permissions(&run, fs::Permissions::from_mode(0o755)).unwrap();
+        let link = repo.work_tree.join("link");
+        crate::platform::symlink("a.txt", &link).unwrap();
+
+        let mut index = repo.read_index().unwrap();
+        for (name, content, mode) in [("run.sh", "echo", "100755"), ("link", "a.txt", "120000")] {
+            let blob = GitObject::new(Fmt::Blob, Bytes::from(content));
+            let sha = sha_from_hex(&repo.write_object(&blob).unwrap()).unwrap();
+            let metadata = repo.work_tree.join(name).symlink_metadata().unwrap();
+            let mut entry = IndexEntry::from_metadata(name.to_string(), sha, &metadata);
+            entry.set_mode(mode).unwrap();
+            index.upsert(entry);
+        }
+        repo.write_index(&index).unwrap();
+        let base = repo.commit("modes".to_string(), true).unwrap();
+
+        let upstream = commit_file(&repo, "b.txt", "b", "upstream");
+        fs::write(repo.git_dir.join("refs/heads/master"), &base).unwrap();
+        repo.rm(
+            &vec![repo.work_tree.join("b.txt").display().to_string()],
+            true,
+            false,
+        )
+        .unwrap();
+        let feature = commit_file(&repo, "c.txt", "c", "feature");
+
+        // an unstaged change would be lost when the work tree is rewritten
+        fs::write(repo.work_tree.join("a.txt"), "local").unwrap();
+        let err = repo.rebase(&upstream).unwrap_err();
+        assert!(err.to_string().contains("would be overwritten by rebase"));
+        assert_eq!(repo.resolve_object("master").unwrap(), Some(feature));
+        fs::write(repo.work_tree.join("a.txt"), "a").unwrap();
+
+        let Rebase::Rebased(tip) = repo.rebase(&upstream).unwrap() else {
+            panic!("expected the branch to be rebased");
+        };
+
+        let entries = repo.read_tree_recursive(&tip).unwrap();
+        assert_eq!(entries["run.sh"].0, "100755");
+        assert_eq!(entries["link"].0, "120000");
+        assert_eq!(fs::read_link(&link).unwrap(), Path::new("a.txt"));
+        assert_eq!(repo.status().unwrap(), Status::default());
+    }
+
+    #[test]
+    fn test_rebase_conflict() {
+        let repo = test_repo("rebase-conflict");
+        let base = commit_file(&repo, "a.txt", "a", "base");
+        fs::write(repo.git_dir.join("refs/heads/feature"), &base).unwrap();
+        let upstream = commit_file(&repo, "a.txt", "upstream", "upstream");
+
+        fs::write(repo.git_dir.join("refs/heads/master"), &base).unwrap();
+        let feature = commit_file(&repo, "a.txt", "feature", "feature");
+        fs::write(repo.git_dir.join("refs/heads/master"), &upstream).unwrap();
+        fs::write(repo.git_dir.join("refs/heads/feature"), &feature).unwrap();
+        fs::write(repo.git_dir.join("HEAD"), "ref: refs/heads/feature\n").unwrap();
+
+        let err = repo.rebase("master").unwrap_err();
+        assert!(err.to_string().contains("conflict in a.txt"));
+
+        // nothing was written, there is no rebase in progress to continue or abort
+        assert_eq!(
+            repo.resolve_object("feature").unwrap(),
+            Some(feature.clone())
+        );
+        assert_eq!(repo.resolve_object("REBASE_HEAD").unwrap(), None);
+        assert!(!repo.git_dir.join("refs/heads/feature.lock").exists());
+        assert_eq!(
+            fs::read_to_string(repo.work_tree.join("a.txt")).unwrap(),
+            "feature"
+        );
+        assert_eq!(repo.status().unwrap(), Status::default());
+    }
+
+    #[test]
+    fn test_abbrev() {
+        let repo = Repository::init(temp_dir("abbrev")).unwrap();
+
+        let sha = repo
+            .write_object(&GitObject::new(Fmt::Blob, Bytes::from_static(b"hello")))
+            .unwrap();
+
+        assert_eq!(repo.abbrev(&sha, 7).unwrap(), sha[..7]);
+
+        // fake an object sharing the first 9 characters
+        let mut other = sha[..9].to_string();
+        other.push_str(if &sha[9..10] == "0" { "1" } else { "0" });
+        other.push_str(&"0".repeat(30));
+        fs::write(
+            repo.git_dir
+                .join("objects")
+                .join(&other[..2])
+                .join(&other[2..]),
+            b"",
+        )
+        .unwrap();
+
+        assert_eq!(repo.abbrev(&sha, 7).unwrap(), sha[..10]);
+        assert_eq!(repo.abbrev(&sha, 12).unwrap(), sha[..12]);
+    }
+
+    #[test]
+    fn test_bisect() {
+        let repo = test_repo("bisect");
+        let commits: Vec<_> = (0..6)
+            .map(|i| commit_file(&repo, "a.txt", &i.to_string(), &format!("c{}", i)))
+            .collect();
+
+        repo.bisect_start().unwrap();
+        assert_eq!(repo.bisect_mark("HEAD", false).unwrap(), Bisect::Waiting);
+
+        // the bug was introduced by the fifth commit
+        let mut step = repo.bisect_mark(&commits[0], true).unwrap();
+        for _ in 0..commits.len() {
+            step = match step {
+                Bisect::Testing { sha, .. } => {
+                    let index = commits.iter().position(|c| *c == sha).unwrap();
+                    assert_eq!(
+                        fs::read_to_string(repo.work_tree.join("a.txt")).unwrap(),
+                        index.to_string()
+                    );
+                    repo.bisect_mark(&sha, index < 4).unwrap()
+                }
+                _ => break,
+            };
+        }
+        assert_eq!(step, Bisect::Found(commits[4].clone()));
+
+        // a local change the checkout would overwrite stops the bisection, HEAD stays
+        let head = repo.resolve_ref("HEAD").unwrap();
+        fs::write(repo.work_tree.join("a.txt"), "local").unwrap();
+        let err = repo.bisect_reset().unwrap_err().to_string();
+        assert!(err.starts_with("Your local changes would be overwritten by bisect:\n  a.txt"));
+        assert_eq!(repo.resolve_ref("HEAD").unwrap(), head);
+        assert_eq!(
+            fs::read_to_string(repo.work_tree.join("a.txt")).unwrap(),
+            "local"
+        );
+        assert!(!repo.git_dir.join("HEAD.lock").exists());
+        repo.checkout(head.as_deref().unwrap(), true).unwrap();
+
+        repo.bisect_reset().unwrap();
+        assert_eq!(repo.active_branch().unwrap(), "master");
+        assert_eq!(
+            fs::read_to_string(repo.work_tree.join("a.txt")).unwrap(),
+            "5"
+        );
+        assert!(!repo.git_dir.join("BISECT_START").exists());
+    }
+
+    #[test]
+    fn test_notes() {
+        let repo = test_repo("notes");
+        let first = commit_file(&repo, "a.txt", "a", "first");
+        let second = commit_file(&repo, "a.txt", "b", "second");
+
+        assert_eq!(repo.note("HEAD").unwrap(), None);
+
+        let notes = repo.notes_add(&first, "reviewed").unwrap();
+        repo.notes_add("HEAD", "tested\n").unwrap();
+
+        assert_eq!(repo.note(&first).unwrap(), Some("reviewed\n".to_string()));
+        assert_eq!(repo.note(&second).unwrap(), Some("tested\n".to_string()));
+        assert!(repo.notes_add(&first, "again").is_err());
+
+        // each addition is a commit on top of the previous notes
+        let tip = repo.resolve_ref("refs/notes/commits").unwrap().unwrap();
+        assert_eq!(
+            repo.read_commit(&tip).unwrap().parents(),
+            Some(&vec![notes])
+        );
+        // the branch is untouched
+        assert_eq!(repo.resolve_ref("HEAD").unwrap(), Some(second.clone()));
+
+        // show and log print the note under the message
+        assert!(repo
+            .show("HEAD")
+            .unwrap()
+            .contains("\n    second\n\nNotes:\n    tested\n\ndiff --git"));
+        let log = repo.log("HEAD", None, false).unwrap();
+        assert!(log.contains("\n    second\n\nNotes:\n    tested\n\ncommit "));
+        assert!(log.ends_with("\n    first\n\nNotes:\n    reviewed\n"));
+        assert!(!repo.log("HEAD", None, true).unwrap().contains("Notes:"));
+
+        // another process adding a note holds the lock of the notes ref
+        fs::write(repo.git_dir.join("refs/notes/commits.lock"), "").unwrap();
+        let third = commit_file(&repo, "a.txt", "c", "third");
+        assert!(repo.notes_add(&third, "late").is_err());
+        assert_eq!(repo.note(&third).unwrap(), None);
+        assert_eq!(repo.resolve_ref("refs/notes/commits").unwrap(), Some(tip));
+    }
+
+    #[test]
+    fn test_reflog_expire() {
+        let repo = Repository::init(temp_dir("reflog-expire")).unwrap();
+        let (old, new) = ("0".repeat(40), "1".repeat(40));
+        let line = |ts: i64| {
+            format!(
+                "{} {} tester <t@example.com> {} +0100\tcommit: c\n",
+                old, new, ts
+            )
+        };
+
+        fs::create_dir_all(repo.git_dir.join("logs/refs/heads")).unwrap();
+        fs::write(
+            repo.git_dir.join("logs/HEAD"),
+            line(100) + &line(200) + "garbage\n" + &line(300),
+        )
+        .unwrap();
+        fs::write(repo.git_dir.join("logs/refs/heads/master"), line(100)).unwrap();
+        // a ref name with a dot is a reflog, a name ending in `.lock` is not
+        fs::create_dir_all(repo.git_dir.join("logs/refs/tags")).unwrap();
+        fs::write(repo.git_dir.join("logs/refs/tags/v1.0"), line(100)).unwrap();
+        fs::write(repo.git_dir.join("logs/refs/heads/topic.lock"), line(100)).unwrap();
+
+        assert_eq!(repo.reflog_expire(200).unwrap(), 3);
+
+        let entries = repo.reflog("HEAD").unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(
+            entries[0],
+            ReflogEntry {
+                old: old.clone(),
+                new: new.clone(),
+                identity: "tester <t@example.com>".to_string(),
+                timestamp: 200,
+                message: "commit: c".to_string(),
+            }
+        );
+        // unparsable lines are kept
+        assert!(fs::read_to_string(repo.git_dir.join("logs/HEAD"))
+            .unwrap()
+            .contains("garbage"));
+        assert!(repo.reflog("refs/heads/master").unwrap().is_empty());
+        assert!(repo.reflog("refs/tags/v1.0").unwrap().is_empty());
+        assert!(!repo.git_dir.join("logs/refs/tags/v1.0.lock").exists());
+        assert_eq!(
+            fs::read_to_string(repo.git_dir.join("logs/refs/heads/topic.lock")).unwrap(),
+            line(100)
+        );
+
+        // a reflog locked by another process is left to it
+        fs::write(repo.git_dir.join("logs/HEAD.lock"), "").unwrap();
+        assert!(repo.reflog_expire(300).is_err());
+        assert_eq!(repo.reflog("HEAD").unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_rev_parse() {
+        let repo = test_repo("rev-parse");
+        let first = commit_file(&repo, "a.txt", "a", "first");
+        let second = commit_file(&repo, "a.txt", "b", "second");
+        fs::write(repo.git_dir.join("refs/tags/v1"), format!("{}\n", first)).unwrap();
+
+        // a branch
+        assert_eq!(repo.verify_object("master").unwrap(), second);
+        assert_eq!(repo.abbrev_ref("HEAD").unwrap(), "master");
+        assert_eq!(repo.abbrev_ref("refs/heads/master").unwrap(), "master");
+
+        // a tag
+        assert_eq!(repo.verify_object("v1").unwrap(), first);
+        assert_eq!(repo.abbrev_ref("v1").unwrap(), "v1");
+
+        // --short
+        assert_eq!(repo.abbrev(&first, 7).unwrap(), first[..7]);
+
+        assert!(repo.verify_object("nope").is_err());
+        assert!(repo.verify_object(&"0".repeat(40)).is_err());
+        assert!(repo.abbrev_ref("nope").is_err());
+
+        // a packed branch is a ref, a name outside the refs is not
+        fs::write(
+            repo.git_dir.join("packed-refs"),
+            format!("{} refs/heads/packed\n", first),
+        )
+        .unwrap();
+        assert_eq!(repo.abbrev_ref("packed").unwrap(), "packed");
+        assert_eq!(repo.abbrev_ref("refs/heads/packed").unwrap(), "packed");
+        assert!(repo.abbrev_ref("../config").is_err());
+        assert!(repo.abbrev_ref("refs/../config").is_err());
+
+        // detached HEAD
+        fs::write(repo.git_dir.join("HEAD"), format!("{}\n", first)).unwrap();
+        assert_eq!(repo.verify_object("HEAD").unwrap(), first);
+        assert_eq!(repo.abbrev_ref("HEAD").unwrap(), "HEAD");
+    }
+
+    #[test]
+    fn test_ambiguous_short_sha() {
+        let repo = test_repo("ambiguous-short-sha");
+
+        // two blobs whose shas share their first 4 digits
+        let mut seen = HashMap::new();
+        let (a, b) = (0..)
+            .map(|i: u32| GitObject::new(Fmt::Blob, Bytes::from(i.to_string())))
+            .find_map(|object| {
+                let sha = object.sha().unwrap();
+                seen.insert(sha[..4].to_string(), object)
+                    .map(|other| (other, seen.remove(&sha[..4]).unwrap()))
+            })
+            .unwrap();
+        let (a, b) = (
+            repo.write_object(&a).unwrap(),
+            repo.write_object(&b).unwrap(),
+        );
+        let commit = commit_file(&repo, "a.txt", "a", "first");
+
+        let err = repo.resolve_object(&a[..4]).unwrap_err().to_string();
+        assert!(err.starts_with(&format!("ambiguous object name: {}\n", &a[..4])));
+        assert!(err.contains(&format!("\n  {} blob", a)));
+        assert!(err.contains(&format!("\n  {} blob", b)));
+        assert!(repo.read_object(&a[..4]).is_err());
+
+        // a longer prefix is unique
+        assert_eq!(repo.resolve_object(&a[..12]).unwrap(), Some(a.clone()));
+
+        // a short sha which is also a tag name
+        let prefix = &commit[..4];
+        fs::write(
+            repo.git_dir.join("refs/tags").join(prefix),
+            format!("{}\n", a),
+        )
+        .unwrap();
+        let err = repo.resolve_object(prefix).unwrap_err().to_string();
+        assert!(err.contains(&format!("\n  {} commit", commit)));
+        assert!(err.contains(&format!("\n  {} blob", a)));
+    }
+
+    #[test]
+    fn test_commit_empty_index() {
+        let repo = test_repo("commit-empty-index");
+
+        let sha = repo.commit("empty".to_string(), true).unwrap();
+        let tree = repo.read_commit(&sha).unwrap().tree().unwrap().clone();
+
+        assert_eq!(tree, "4b825dc642cb6eb9a060e54bf8d69288fbee4904");
+        assert_eq!(repo.read_object(&tree).unwrap().header.fmt, Fmt::Tree);
+    }
+
+    #[test]
+    fn test_checkout_removes_files() {
+        let repo = test_repo("checkout-removes-files");
+        let first = commit_file(&repo, "a.txt", "a", "first");
+        commit_file(&repo, "sub/b.txt", "b", "second");
+
+        repo.checkout(&first, false).unwrap();
+        assert!(repo.work_tree.join("a.txt").exists());
+        assert!(!repo.work_tree.join("sub").exists());
+        assert!(repo.active_branch().is_err());
+        assert!(repo.staged_tree_matches_head().unwrap());
+
+        repo.checkout("master", false).unwrap();
+        assert_eq!(repo.active_branch().unwrap(), "master");
+        assert_eq!(
+            fs::read_to_string(repo.work_tree.join("sub/b.txt")).unwrap(),
+            "b"
+        );
+        assert!(repo.staged_tree_matches_head().unwrap());
+
+        // a packed branch is checked out as a branch too, not as a detached HEAD
+        fs::write(
+            repo.git_dir.join("packed-refs"),
+            format!("{} refs/heads/old\n", first),
+        )
+        .unwrap();
+        repo.checkout("old", false).unwrap();
+        assert_eq!(repo.active_branch().unwrap(), "old");
+        assert!(!repo.work_tree.join("sub").exists());
+    }
+
+    #[test]
+    fn test_checkout_then_status_clean() {
+        let repo = test_repo("checkout-then-status-clean");
+        let first = commit_file(&repo, "a.txt", "a", "first");
+        commit_file(&repo, "a.txt", "changed", "second");
+        commit_file(&repo, "sub/b.txt", "b", "third");
+
+        repo.checkout(&first, false).unwrap();
+        assert_eq!(repo.status().unwrap(), Status::default());
+
+        // the index holds the stat of the written files, not zeroes
+        let index = repo.read_index().unwrap();
+        let entry = index.get("a.txt", 0).unwrap();
+        let metadata = repo.work_tree.join("a.txt").metadata().unwrap();
+        assert!(entry.is_unchanged(&metadata));
+        assert_ne!(entry.mtime, (0, 0));
+
+        repo.checkout("master", false).unwrap();
+        assert_eq!(repo.status().unwrap(), Status::default());
+    }
+
+    #[test]
+    fn test_status_changes() {
+        let repo = test_repo("status-changes");
+        commit_file(&repo, "a.txt", "a", "first");
+        commit_file(&repo, "b.txt", "b", "second");
+        let path = |name: &str| repo.work_tree.join(name).display().to_string();
+
+        fs::write(path("c.txt"), "c").unwrap();
+        repo.add(&vec![path("c.txt")]).unwrap();
+        repo.rm(&vec![path("b.txt")], true, false).unwrap();
+        fs::write(path("a.txt"), "changed").unwrap();
+
+        let status = repo.status().unwrap();
+        let change = |path: &str, kind| Change {
+            path: path.to_string(),
+            kind,
+        };
+        assert_eq!(
+            status.staged(),
+            vec![
+                change("b.txt", ChangeKind::Deleted),
+                change("c.txt", ChangeKind::Added)
+            ]
+        );
+        assert_eq!(
+            status.unstaged(),
+            vec![change("a.txt", ChangeKind::Modified)]
+        );
+    }
+
+    #[test]
+    fn test_modified_and_deleted_files() {
+        let repo = test_repo("modified-and-deleted-files");
+        commit_file(&repo, "a.txt", "a", "first");
+        commit_file(&repo, "b.txt", "b", "second");
+        commit_file(&repo, "c.txt", "c", "third");
+
+        fs::write(repo.work_tree.join("a.txt"), "changed").unwrap();
+        fs::remove_file(repo.work_tree.join("b.txt")).unwrap();
+        fs::write(repo.work_tree.join("d.txt"), "d").unwrap();
+
+        assert_eq!(repo.modified_files().unwrap(), vec!["a.txt", "b.txt"]);
+        assert_eq!(repo.deleted_files().unwrap(), vec!["b.txt"]);
+        assert_eq!(repo.untracked_files(false).unwrap(), vec!["d.txt"]);
+
+        // an unmerged path is listed once, not once per stage
+        let mut index = repo.read_index().unwrap();
+        let conflicted: Vec<_> = index
+            .entries
+            .iter()
+            .filter(|entry| entry.name != "c.txt")
+            .map(|entry| (entry.name.clone(), entry.sha))
+            .collect();
+        index.entries.retain(|entry| entry.name == "c.txt");
+        for (name, sha) in conflicted {
+            for flag_stage in [1, 2, 3] {
+                index.upsert(IndexEntry {
+                    name: name.clone(),
+                    sha,
+                    mode_type: 0b1000,
+                    mode_perms: 0o644,
+                    flag_stage,
+                    ..Default::default()
+                });
+            }
+        }
+        repo.write_index(&index).unwrap();
+
+        assert_eq!(repo.modified_files().unwrap(), vec!["a.txt", "b.txt"]);
+        assert_eq!(repo.deleted_files().unwrap(), vec!["b.txt"]);
+    }
+
+    #[test]
+    fn test_status_refreshes_stat() {
+        let repo = test_repo("status-refreshes-stat");
+        commit_file(&repo, "a.txt", "a", "first");
+        let path = repo.work_tree.join("a.txt");
+        let set_mtime = |path: &Path, seconds: u64| {
+            fs::File::options()
+                .write(true)
+                .open(path)
+                .unwrap()
+                .set_modified(std::time::UNIX_EPOCH + std::time::Duration::from_secs(seconds))
+                .unwrap()
+        };
+
+        // a touched file is hashed once, then its new stat data is trusted
+        set_mtime(&path, 1_000_000_000);
+        assert_eq!(repo.status().unwrap(), Status::default());
+        let index = repo.read_index().unwrap();
+        assert_eq!(index.get("a.txt", 0).unwrap().mtime, (1_000_000_000, 0));
+        assert!(index
+            .get("a.txt", 0)
+            .unwrap()
+            .is_unchanged(&path.metadata().unwrap()));
+
+        // an index older than its entries is racy, the entries are hashed and the index
+        // written again
+        set_mtime(repo.index_path(), 1);
+        assert_eq!(repo.status().unwrap(), Status::default());
+        let mtime = repo.index_path().metadata().unwrap().modified().unwrap();
+        assert!(mtime > std::time::UNIX_EPOCH + std::time::Duration::from_secs(1));
+
+        // the refresh is skipped while another process holds the index lock
+        let lock = repo.git_dir.join("index.lock");
+        fs::write(&lock, "").unwrap();
+        set_mtime(&path, 2_000_000_000);
+        let before = fs::read(repo.index_path()).unwrap();
+        assert_eq!(repo.status().unwrap(), Status::default());
+        assert_eq!(fs::read(repo.index_path()).unwrap(), before);
+        assert!(lock.exists());
+        fs::remove_file(&lock).unwrap();
+
+        // a change of size is a change, whatever the timestamps
+        fs::write(&path, "ab").unwrap();
+        set_mtime(&path, 1_000_000_000);
+        assert_eq!(repo.status().unwrap().entries[0].unstaged, 'M');
+    }
+
+    #[test]
+    fn test_checkout_progress() {
+        struct Record(std::rc::Rc<RefCell<Vec<(String, usize, usize)>>>);
+
+        impl Progress for Record {
+            fn update(&mut self, title: &str, done: usize, total: usize) {
+                self.0.borrow_mut().push((title.to_string(), done, total));
+            }
+        }
+
+        let mut repo = test_repo("checkout-progress");
+        let first = commit_file(&repo, "a.txt", "a", "first");
+        commit_file(&repo, "b.txt", "b", "second");
+
+        let updates = std::rc::Rc::new(RefCell::new(vec![]));
+        repo.set_progress(Record(updates.clone()));
+        repo.checkout(&first, false).unwrap();
+        repo.checkout("master", false).unwrap();
+
+        let title = "Updating files".to_string();
+        assert_eq!(
+            *updates.borrow(),
+            vec![
+                (title.clone(), 0, 1),
+                (title.clone(), 1, 1),
+                (title.clone(), 0, 2),
+                (title.clone(), 1, 2),
+                (title.clone(), 2, 2),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_checkout_local_changes() {
+        let repo = test_repo("checkout-local-changes");
+        let first = commit_file(&repo, "a.txt", "a", "first");
+        commit_file(&repo, "a.txt", "b", "second");
+
+        fs::write(repo.work_tree.join("a.txt"), "local").unwrap();
+
+        let err = repo.checkout(&first, false).unwrap_err();
+        assert!(err.to_string().contains("would be overwritten"));
+        assert!(err.to_string().contains("a.txt"));
+        assert_eq!(repo.active_branch().unwrap(), "master");
+        assert_eq!(
+            fs::read_to_string(repo.work_tree.join("a.txt")).unwrap(),
+            "local"
+        );
+
+        repo.checkout(&first, true).unwrap();
+        assert_eq!(
+            fs::read_to_string(repo.work_tree.join("a.txt")).unwrap(),
+            "a"
+        );
+        assert!(repo.status().unwrap().entries.is_empty());
+    }
+
+    #[test]
+    fn test_head() {
+        let repo = test_repo("head");
+        assert_eq!(repo.head().unwrap(), Head::Unborn("master".to_string()));
+        assert_eq!(repo.active_branch().unwrap(), "master");
+
+        let sha = commit_file(&repo, "a.txt", "a", "first");
+        assert_eq!(repo.head().unwrap(), Head::Branch("master".to_string()));
+
+        fs::write(repo.git_dir.join("HEAD"), format!("{}\n", sha)).unwrap();
+        assert_eq!(repo.head().unwrap(), Head::Detached(sha));
+        assert!(repo.active_branch().is_err());
+    }
+
+    #[test]
+    fn test_commit_on_nested_branch() {
+        let repo = test_repo("commit-nested-branch");
+        fs::write(repo.git_dir.join("HEAD"), "ref: refs/heads/feature/x\n").unwrap();
+
+        let sha = commit_file(&repo, "a.txt", "a", "first");
+
+        assert_eq!(repo.active_branch().unwrap(), "feature/x");
+        assert_eq!(repo.resolve_ref("refs/heads/feature/x").unwrap(), Some(sha));
+
+        crate::refs::tag::Tag::new("release/v1".to_string(), "abc".to_string())
+            .write_to(&repo)
+            .unwrap();
+        assert!(repo.git_dir.join("refs/tags/release/v1").is_file());
+    }
+
+    #[test]
+    fn test_update_ref_race() {
+        let repo = test_repo("update-ref-race");
+        let first = commit_file(&repo, "a.txt", "a", "first");
+        let second = commit_file(&repo, "a.txt", "b", "second");
+
+        // another process moves the branch after we read it
+        let read = repo.resolve_ref("refs/heads/master").unwrap().unwrap();
+        repo.update_ref("refs/heads/master", &first, Some(&second))
+            .unwrap();
+
+        let err = repo
+            .update_ref("refs/heads/master", &read, Some(&read))
+            .unwrap_err();
+        assert!(err.to_string().contains("expected"));
+        assert_eq!(repo.resolve_ref("refs/heads/master").unwrap(), Some(first));
+        assert!(!repo.git_dir.join("refs/heads/master.lock").exists());
+
+        // the ref must not exist yet
+        assert!(repo
+            .update_ref("refs/heads/master", &second, Some(NULL_SHA))
+            .is_err());
+        repo.update_ref("refs/heads/new", &second, Some(NULL_SHA))
+            .unwrap();
+
+        // a commit fails while another process holds the lock
+        fs::write(repo.git_dir.join("refs/heads/master.lock"), "").unwrap();
+        fs::write(repo.work_tree.join("a.txt"), "c").unwrap();
+        repo.add(&vec![repo.work_tree.join("a.txt").display().to_string()])
+            .unwrap();
+        assert!(repo.commit("third".to_string(), true).is_err());
+    }
+
+    #[test]
+    fn test_commit_message_cleanup() {
+        let mut repo = test_repo("commit-message-cleanup");
+        let message = |repo: &Repository, sha: &str| {
+            repo.read_commit(sha).unwrap().message().unwrap().clone()
+        };
+
+        let sha = commit_file(&repo, "a.txt", "a", "subject  \n# comment\n\n");
+        assert_eq!(message(&repo, &sha), "subject\n# comment\n");
+
+        repo.set_config("core.commentChar", "%").unwrap();
+        repo.set_config("commit.cleanup", "strip").unwrap();
+
+        let sha = commit_file(&repo, "a.txt", "b", "subject\n% comment\n# kept\n");
+        assert_eq!(message(&repo, &sha), "subject\n# kept\n");
+
+        // nothing is left of a message made of comments
+        fs::write(repo.work_tree.join("a.txt"), "c").unwrap();
+        repo.add(&vec![repo.work_tree.join("a.txt").display().to_string()])
+            .unwrap();
+        assert!(repo.commit("% only a comment\n".to_string(), true).is_err());
+    }
+
+    #[test]
+    fn test_filters() {
+        let mut repo = test_repo("filters");
+        // committed before the filter is set up, as the clean filter would store it
+        let first = commit_file(&repo, "a.txt", "FIRST", "first");
+
+        repo.set_config("filter.upper.clean", "tr a-z A-Z").unwrap();
+        repo.set_config("filter.upper.smudge", "tr A-Z a-z")
+            .unwrap();
+        repo.set_config("filter.broken.clean", "false").unwrap();
+        fs::write(
+            repo.work_tree.join(".gitattributes"),
+            "*.txt filter=upper\n*.bin filter=broken\n",
+        )
+        .unwrap();
+
+        // the blob holds the cleaned content
+        let second = commit_file(&repo, "a.txt", "hello", "second");
+        let blob = &repo.tree_to_dict(&second).unwrap()["a.txt"];
+        assert_eq!(repo.read_object(blob).unwrap().data, "HELLO");
+
+        // the work tree gets the smudged content
+        repo.checkout(&first, false).unwrap();
+        repo.checkout(&second, false).unwrap();
+        assert_eq!(
+            fs::read_to_string(repo.work_tree.join("a.txt")).unwrap(),
+            "hello"
+        );
+
+        fs::write(repo.work_tree.join("b.bin"), "b").unwrap();
+        assert!(repo
+            .add(&vec![repo.work_tree.join("b.bin").display().to_string()])
+            .is_err());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_commit_hooks() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let mut repo = test_repo("commit-hooks");
+        let hooks_dir = repo.work_tree.join("hooks");
+        let hook = |name: &str, script: &str| {
+            let path = hooks_dir.join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, script).unwrap();
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        };
+
+        // hooks outside core.hooksPath are ignored
+        hook("pre-commit", "#!/bin/sh\nexit 1\n");
+        commit_file(&repo, "a.txt", "a", "first");
+
+        repo.set_config("core.hooksPath", "hooks").unwrap();
+
+        let path = repo.work_tree.join("a.txt");
+        fs::write(&path, "b").unwrap();
+        repo.add(&vec![path.to_str().unwrap().to_string()]).unwrap();
+        assert!(repo.commit("second".to_string(), true).is_err());
+
+        // --no-verify skips both hooks
+        hook(
+            "commit-msg",
+            "#!/bin/sh\necho 'Signed-off-by: tester' >> \"$1\"\n",
+        );
+        let sha = repo.commit("skipped\n".to_string(), false).unwrap();
+        assert_eq!(
+            repo.read_commit(&sha).unwrap().message().unwrap(),
+            "skipped\n"
+        );
+
+        fs::write(&path, "c").unwrap();
+        repo.add(&vec![path.to_str().unwrap().to_string()]).unwrap();
+        hook("pre-commit", "#!/bin/sh\nexit 0\n");
+        let sha = repo.commit("second\n".to_string(), true).unwrap();
+
+        assert_eq!(
+            repo.read_commit(&sha).unwrap().message().unwrap(),
+            "second\nSigned-off-by: tester\n"
+        );
+
+        // the message file is given to the hook as a path, which need not be utf-8
+        use std::os::unix::ffi::OsStrExt;
+        let dir = temp_dir("commit-hooks-non-utf8").join(std::ffi::OsStr::from_bytes(b"\xff"));
+        let mut repo = Repository::init(dir).unwrap();
+        repo.set_config("user.name", "tester").unwrap();
+        repo.set_config("user.email", "tester@example.com").unwrap();
+        let hook = repo.git_dir.join("hooks/commit-msg");
+        fs::write(&hook, "#!/bin/sh\necho 'Signed-off-by: tester' >> \"$1\"\n").unwrap();
+        fs::set_permissions(&hook, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let sha = repo.commit("empty\n".to_string(), true).unwrap();
+        assert_eq!(
+            repo.read_commit(&sha).unwrap().message().unwrap(),
+            "empty\nSigned-off-by: tester\n"
+        );
+    }
+
+    #[test]
+    fn test_write_tag_object() {
+        let repo = test_repo("write-tag-object");
+        let commit = commit_file(&repo, "a.txt", "a", "first");
+
+        let sha = repo
+            .write_tag_object("v1", &commit, "Release 1\n".to_string())
+            .unwrap();
+
+        let object = repo.read_object(&sha).unwrap();
+        assert_eq!(object.header.fmt, Fmt::Tag);
+        let tag = crate::objects::tag::Tag::from_bytes(object.data).unwrap();
+        assert_eq!(tag.object().unwrap(), &commit);
+        assert_eq!(tag.message().unwrap(), "Release 1\n");
+        assert!(tag
+            .tagger()
+            .unwrap()
+            .starts_with("tester <tester@example.com> "));
+    }
+
+    #[test]
+    fn test_detached_at_tag() {
+        let repo = test_repo("detached-at-tag");
+        let first = commit_file(&repo, "a.txt", "a", "first");
+
+        let data =
+            format!("object {first}\ntype commit\ntag v1\ntagger t <t@t.com> 0 +0000\n\nv1\n");
+        let tag = repo
+            .write_object(&GitObject::new(Fmt::Tag, Bytes::from(data)))
+            .unwrap();
+        fs::write(repo.git_dir.join("HEAD"), format!("{}\n", tag)).unwrap();
+
+        // what status compares against
+        assert!(repo.staged_tree_matches_head().unwrap());
+        assert_eq!(
+            repo.tree_to_dict("HEAD").unwrap(),
+            repo.tree_to_dict(&first).unwrap()
+        );
+        assert_eq!(
+            repo.ancestors(&tag).unwrap(),
+            std::collections::HashSet::from([first])
+        );
+    }
+
+    #[test]
+    fn test_commits_from() {
+        let repo = test_repo("commits-from");
+        let first = commit_file(&repo, "a.txt", "a", "first");
+        let second = commit_file(&repo, "a.txt", "b", "second");
+
+        // a merge of second and a side commit on top of first
+        let side = side_commit(&repo, &first);
+        let merge = merge_commit(&repo, &second, &side);
+
+        let walk = |first_parent_only| -> Vec<String> {
+            repo.commits_from(&merge, first_parent_only)
+                .unwrap()
+                .map(|commit| commit.unwrap().0)
+                .collect()
+        };
+
+        assert_eq!(
+            walk(false),
+            vec![merge.clone(), second.clone(), side, first.clone()]
+        );
+        assert_eq!(walk(true), vec![merge.clone(), second, first.clone()]);
+
+        // the walk is lazy, a missing ancestor is not read before it is reached
+        fs::remove_file(
+            repo.git_dir
+                .join("objects")
+                .join(&first[..2])
+                .join(&first[2..]),
+        )
+        .unwrap();
+        let mut commits = repo.commits_from(&merge, false).unwrap();
+        assert_eq!(
+            commits
+                .by_ref()
+                .take(3)
+                .filter(|commit| commit.is_ok())
+                .count(),
+            3
+        );
+        assert!(commits.next().unwrap().is_err());
+        assert!(commits.next().is_none());
+    }
+
+    #[test]
+    fn test_read_tree_recursive() {
+        let repo = Repository::init(temp_dir("read-tree-recursive")).unwrap();
+        let blob = repo
+            .write_object(&GitObject::new(Fmt::Blob, Bytes::from_static(b"target")))
+            .unwrap();
+
+        let mut index = Index::default();
+        for (name, mode_type, mode_perms) in [
+            ("bin/run.sh", 0b1000, 0o755),
+            ("link", 0b1010, 0),
+            ("readme", 0b1000, 0o644),
+        ] {
+            index.upsert(IndexEntry {
+                name: name.to_string(),
+                sha: sha_from_hex(&blob).unwrap(),
+                mode_type,
+                mode_perms,
+                ..Default::default()
+            });
+        }
+        let tree = repo.create_tree_from_index(&index).unwrap();
+
+        let dict = repo.read_tree_recursive(&tree).unwrap();
+
+        assert_eq!(dict["bin/run.sh"], ("100755".to_string(), blob.clone()));
+        assert_eq!(dict["link"], ("120000".to_string(), blob.clone()));
+        assert_eq!(dict["readme"], ("100644".to_string(), blob.clone()));
+        assert_eq!(repo.tree_to_dict(&tree).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_checkout_symlink() {
+        let repo = test_repo("checkout-symlink");
+        let first = commit_file(&repo, "a.txt", "a", "first");
+
+        // a symlink to a.txt is staged as a symlink, its blob is the target path
+        let link = repo.work_tree.join("link");
+        crate::platform::symlink("a.txt", &link).unwrap();
+        repo.add(&vec![link.display().to_string()]).unwrap();
+        let index = repo.read_index().unwrap();
+        let entry = index.get("link", 0).unwrap();
+        assert_eq!(entry.mode(), 0o120000);
+        assert_eq!(
+            repo.read_object(&entry.sha_hex()).unwrap().data,
+            Bytes::from_static(b"a.txt")
+        );
+        let second = repo.commit("link".to_string(), true).unwrap();
+
+        // as `checkout <commit> <path>` does
+        let dir = temp_dir("checkout-symlink-to");
+        repo.checkout_to(&second, &dir).unwrap();
+        assert_eq!(fs::read_link(dir.join("link")).unwrap(), Path::new("a.txt"));
+        assert_eq!(fs::read_to_string(dir.join("a.txt")).unwrap(), "a");
+
+        repo.checkout(&first, false).unwrap();
+        assert!(link.symlink_metadata().is_err());
+
+        repo.checkout("master", false).unwrap();
+        assert_eq!(fs::read_link(&link).unwrap(), Path::new("a.txt"));
+        assert_eq!(fs::read_to_string(&link).unwrap(), "a");
+        assert_eq!(repo.status().unwrap(), Status::default());
+    }
+
+    #[test]
+    fn test_merge_base_and_is_ancestor() {
+        let repo = test_repo("merge-base");
+        let first = commit_file(&repo, "a.txt", "a", "first");
+        let second = commit_file(&repo, "a.txt", "b", "second");
+        let side = side_commit(&repo, &first);
+        let merge = merge_commit(&repo, &second, &side);
+
+        assert_eq!(
+            repo.merge_base(&second, &side).unwrap(),
+            Some(first.clone())
+        );
+        assert_eq!(repo.merge_base(&merge, &side).unwrap(), Some(side.clone()));
+
+        // through the merge, first is reached before second, which is the lowest
+        let other = side_commit(&repo, &second);
+        let shortcut = merge_commit(&repo, &first, &second);
+        assert_eq!(
+            repo.merge_base(&other, &shortcut).unwrap(),
+            Some(second.clone())
+        );
+
+        // unrelated histories
+        let tree = repo.read_commit(&first).unwrap().tree().unwrap().clone();
+        let root = repo.commit_tree(&tree, None, "root".to_string()).unwrap();
+        assert_eq!(repo.merge_base(&root, &merge).unwrap(), None);
+
+        assert!(repo.is_ancestor(&side, &merge).unwrap());
+        assert!(repo.is_ancestor(&first, &merge).unwrap());
+        assert!(repo.is_ancestor(&merge, &merge).unwrap());
+        assert!(!repo.is_ancestor(&merge, &side).unwrap());
+        assert!(!repo.is_ancestor(&second, &side).unwrap());
+    }
+
+    #[test]
+    fn test_merge_fast_forward() {
+        let repo = test_repo("merge-fast-forward");
+        let first = commit_file(&repo, "a.txt", "a", "first");
+        let side = side_commit(&repo, &first);
+        // side_commit leaves the files of side behind
+        repo.checkout("master", true).unwrap();
+
+        assert_eq!(repo.merge("master").unwrap(), Merge::UpToDate);
+
+        // a file the merge would write is in the way
+        fs::write(repo.work_tree.join("side.txt"), "local").unwrap();
+        let err = repo.merge("side").unwrap_err().to_string();
+        assert!(err.starts_with("Your local changes would be overwritten by merge:\n  side.txt"));
+        fs::remove_file(repo.work_tree.join("side.txt")).unwrap();
+
+        assert_eq!(
+            repo.merge("side").unwrap(),
+            Merge::FastForward(side.clone())
+        );
+        assert_eq!(repo.resolve_ref("HEAD").unwrap(), Some(side.clone()));
+        assert_eq!(repo.active_branch().unwrap(), "master");
+        assert_eq!(
+            fs::read_to_string(repo.work_tree.join("side.txt")).unwrap(),
+            "side"
+        );
+        assert_eq!(repo.status().unwrap(), Status::default());
+        assert_eq!(repo.resolve_ref("ORIG_HEAD").unwrap(), Some(first.clone()));
+
+        // diverged histories
+        let second = commit_file(&repo, "a.txt", "b", "second");
+        side_commit(&repo, &first);
+        repo.checkout("master", true).unwrap();
+        let err = repo.merge("side").unwrap_err().to_string();
+        assert_eq!(err, "not up to date, true merge not implemented");
+        assert_eq!(repo.resolve_ref("HEAD").unwrap(), Some(second));
+    }
+
+    #[test]
+    fn test_merge_locked_branch() {
+        let repo = test_repo("merge-locked-branch");
+        let first = commit_file(&repo, "a.txt", "a", "first");
+        side_commit(&repo, &first);
+        repo.checkout("master", true).unwrap();
+
+        // another process is updating master, the work tree is left as it was
+        let lock = repo.git_dir.join("refs/heads/master.lock");
+        fs::write(&lock, "").unwrap();
+        let err = repo.merge("side").unwrap_err().to_string();
+        assert!(err.starts_with("cannot lock ref refs/heads/master"));
+        assert!(!repo.work_tree.join("side.txt").exists());
+        assert_eq!(repo.resolve_ref("HEAD").unwrap(), Some(first.clone()));
+        assert_eq!(repo.status().unwrap(), Status::default());
+        assert!(lock.exists());
+
+        // a failed merge releases the lock it took
+        fs::remove_file(&lock).unwrap();
+        fs::write(repo.work_tree.join("side.txt"), "local").unwrap();
+        assert!(repo.merge("side").is_err());
+        assert!(!lock.exists());
+    }
+
+    #[test]
+    fn test_push() {
+        let local = test_repo("push-local");
+        let remote = test_repo("push-remote");
+        let remote_path = remote.work_tree.to_str().unwrap();
+
+        let first = commit_file(&local, "a.txt", "a", "first");
+        let result = local
+            .push(remote_path, Some("master:feature"), false)
+            .unwrap();
+        assert_eq!(
+            result,
+            Push::Updated {
+                old: None,
+                new: first.clone(),
+                objects: 3
+            }
+        );
+        assert_eq!(
+            remote.resolve_ref("refs/heads/feature").unwrap(),
+            Some(first.clone())
+        );
+        assert_eq!(
+            remote.tree_to_dict(&first).unwrap(),
+            local.tree_to_dict(&first).unwrap()
+        );
+
+        // only the new objects are copied
+        let second = commit_file(&local, "a.txt", "b", "second");
+        let result = local
+            .push(remote_path, Some("master:feature"), false)
+            .unwrap();
+        assert_eq!(
+            result,
+            Push::Updated {
+                old: Some(first.clone()),
+                new: second.clone(),
+                objects: 3
+            }
+        );
+        assert_eq!(
+            local
+                .push(remote_path, Some("master:feature"), false)
+                .unwrap(),
+            Push::UpToDate
+        );
+
+        // rewinding is not a fast-forward
+        assert!(local
+            .push(remote_path, Some(&format!("{}:feature", first)), false)
+            .is_err());
+        local
+            .push(remote_path, Some(&format!("+{}:feature", first)), false)
+            .unwrap();
+        assert_eq!(
+            remote.resolve_ref("refs/heads/feature").unwrap(),
+            Some(first.clone())
+        );
+
+        // another process updating the remote ref holds its lock
+        let lock = remote.git_dir.join("refs/heads/feature.lock");
+        fs::write(&lock, "").unwrap();
+        assert!(local
+            .push(remote_path, Some("master:feature"), false)
+            .is_err());
+        assert_eq!(
+            remote.resolve_ref("refs/heads/feature").unwrap(),
+            Some(first.clone())
+        );
+        fs::remove_file(&lock).unwrap();
+
+        // the branch checked out in the remote is left alone
+        assert!(local.push(remote_path, None, false).is_err());
+
+        // a directory inside the remote is not the remote
+        let inside = remote.work_tree.join("inside");
+        fs::create_dir(&inside).unwrap();
+        assert!(local
+            .push(inside.to_str().unwrap(), Some("master:other"), false)
+            .is_err());
+    }
+
+    #[test]
+    fn test_push_packed_old_tip() {
+        let local = test_repo("push-packed-local");
+        let remote = test_repo("push-packed-remote");
+        let remote_path = remote.work_tree.to_str().unwrap();
+
+        let first = commit_file(&local, "a.txt", "a", "first");
+        local
+            .push(remote_path, Some("master:feature"), false)
+            .unwrap();
+
+        // the first commit, its tree and its blob end up packed on both sides
+        let tree = local.read_commit(&first).unwrap().tree().unwrap().clone();
+        let blob = local.tree_to_dict(&first).unwrap()["a.txt"].clone();
+        let packed = [first.clone(), tree, blob];
+        pack_objects(&local, &packed);
+        pack_objects(&remote, &packed);
+        assert!(local.has_object(&first).unwrap());
+
+        // still a fast-forward, and only the new objects are copied
+        let second = commit_file(&local, "a.txt", "b", "second");
+        assert_eq!(
+            local
+                .push(remote_path, Some("master:feature"), false)
+                .unwrap(),
+            Push::Updated {
+                old: Some(first),
+                new: second,
+                objects: 3
+            }
+        );
+    }
+
+    #[test]
+    fn test_fetch_depth() {
+        let remote = test_repo("fetch-depth-remote");
+        let local = test_repo("fetch-depth-local");
+        let remote_path = remote.work_tree.to_str().unwrap();
+
+        commit_file(&remote, "a.txt", "a", "first");
+        let second = commit_file(&remote, "a.txt", "b", "second");
+        let third = commit_file(&remote, "a.txt", "c", "third");
+
+        let fetched = local.fetch(remote_path, Some(1)).unwrap();
+        assert_eq!(fetched.get("master"), Some(&third));
+        assert!(!local.has_object(&second).unwrap());
+        assert_eq!(
+            local.shallow().unwrap(),
+            std::collections::HashSet::from([third.clone()])
+        );
+
+        // the walk stops at the shallow boundary instead of failing on the missing parent
+        let shas: Vec<_> = local
+            .commits_from(&third, false)
+            .unwrap()
+            .map(|commit| commit.unwrap().0)
+            .collect();
+        assert_eq!(shas, vec![third.clone()]);
+
+        let fetch_head = fs::read_to_string(local.git_dir.join("FETCH_HEAD")).unwrap();
+        assert!(fetch_head.starts_with(&format!("{}\t\tbranch 'master' of ", third)));
+    }
+
+    #[test]
+    fn test_fetch_remote_refs() {
+        let remote = test_repo("fetch-refs-remote");
+        let mut local = test_repo("fetch-refs-local");
+        local
+            .set_config("remote.origin.url", remote.work_tree.to_str().unwrap())
+            .unwrap();
+
+        let first = commit_file(&remote, "a.txt", "a", "first");
+        local.fetch("origin", None).unwrap();
+        assert_eq!(
+            local.resolve_ref("refs/remotes/origin/master").unwrap(),
+            Some(first.clone())
+        );
+
+        // another process updating the remote-tracking branch holds its lock
+        let second = commit_file(&remote, "a.txt", "b", "second");
+        let lock = local.git_dir.join("refs/remotes/origin/master.lock");
+        fs::write(&lock, "").unwrap();
+        assert!(local.fetch("origin", None).is_err());
+        assert_eq!(
+            local.resolve_ref("refs/remotes/origin/master").unwrap(),
+            Some(first)
+        );
+
+        fs::remove_file(&lock).unwrap();
+        local.fetch("origin", None).unwrap();
+        assert_eq!(
+            local.resolve_ref("refs/remotes/origin/master").unwrap(),
+            Some(second)
+        );
     }
 }
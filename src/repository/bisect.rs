@@ -0,0 +1,175 @@
+//! Binary search through the history for the commit that introduced a bug.
+//!
+//! The state of a bisection lives at the top of the git directory:
+//!
+//! 1. `BISECT_START`: the content of HEAD when the bisection started, restored by reset;
+//! 2. `BISECT_BAD`: the known bad commit;
+//! 3. `BISECT_GOOD`: the known good commits, one per line.
+
+use crate::repository::Repository;
+use anyhow::Context;
+use std::collections::HashSet;
+use std::fs;
+
+/// the advice given when local changes would be overwritten by a step
+const BISECT_HINT: &str = "Please commit them before you bisect";
+
+/// the state of a bisection after a step
+#[derive(Debug, PartialEq)]
+pub enum Bisect {
+    /// both a good and a bad commit are needed before bisecting
+    Waiting,
+    /// the commit checked out to be tested, and the number of revisions left to test after it
+    Testing { sha: String, remaining: usize },
+    /// the first bad commit
+    Found(String),
+}
+
+impl Repository {
+    /// start a bisection from the current HEAD
+    pub fn bisect_start(&self) -> anyhow::Result<()> {
+        let start_path = self.git_dir.join("BISECT_START");
+
+        anyhow::ensure!(
+            !start_path.exists(),
+            "already bisecting, use bisect reset first"
+        );
+        anyhow::ensure!(
+            self.staged_tree_matches_head()?,
+            "cannot bisect: your index contains uncommitted changes"
+        );
+
+        let head =
+            fs::read_to_string(self.git_dir.join("HEAD")).context("failed to read HEAD file")?;
+
+        fs::write(start_path, head).context("failed to write BISECT_START file")?;
+
+        Ok(())
+    }
+
+    /// mark a revision as good or bad, and check out the next commit to test
+    pub fn bisect_mark(&self, rev: &str, good: bool) -> anyhow::Result<Bisect> {
+        anyhow::ensure!(
+            self.git_dir.join("BISECT_START").exists(),
+            "not bisecting, use bisect start first"
+        );
+
+        let sha = self
+            .find_object(rev, true)?
+            .ok_or(anyhow::anyhow!("object not found: {}", rev))?;
+        self.read_commit(&sha)?;
+
+        if good {
+            let mut goods = self.bisect_goods()?;
+            goods.push(sha);
+            fs::write(self.git_dir.join("BISECT_GOOD"), goods.join("\n") + "\n")
+                .context("failed to write BISECT_GOOD file")?;
+        } else {
+            fs::write(self.git_dir.join("BISECT_BAD"), format!("{}\n", sha))
+                .context("failed to write BISECT_BAD file")?;
+        }
+
+        self.bisect_next()
+    }
+
+    /// stop bisecting and go back to the HEAD the bisection started from
+    pub fn bisect_reset(&self) -> anyhow::Result<()> {
+        let start_path = self.git_dir.join("BISECT_START");
+
+        anyhow::ensure!(start_path.exists(), "not bisecting");
+
+        let current = self.resolve_ref("HEAD")?.context("HEAD not found")?;
+
+        // HEAD is restored as it was, often a symbolic ref to a branch
+        let start = fs::read_to_string(&start_path).context("failed to read BISECT_START file")?;
+        let target = match start.trim().strip_prefix("ref: ") {
+            Some(branch) => self.resolve_ref(branch)?,
+            None => Some(start.trim().to_string()),
+        }
+        .context("the bisection start cannot be resolved")?;
+
+        let lock = self.lock_ref("HEAD", Some(&current))?;
+        self.switch_work_tree(&target, false, "bisect", BISECT_HINT)?;
+        lock.commit(start.as_bytes())
+            .context("failed to update ref HEAD")?;
+
+        for file in ["BISECT_START", "BISECT_BAD", "BISECT_GOOD"] {
+            let path = self.git_dir.join(file);
+            if path.exists() {
+                fs::remove_file(path).context(format!("failed to remove {} file", file))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn bisect_goods(&self) -> anyhow::Result<Vec<String>> {
+        let path = self.git_dir.join("BISECT_GOOD");
+
+        if !path.exists() {
+            return Ok(vec![]);
+        }
+
+        Ok(fs::read_to_string(path)
+            .context("failed to read BISECT_GOOD file")?
+            .lines()
+            .map(|line| line.to_string())
+            .collect())
+    }
+
+    /// pick the commit halving the remaining candidates best, and check it out
+    fn bisect_next(&self) -> anyhow::Result<Bisect> {
+        let bad = self.resolve_ref("BISECT_BAD")?;
+        let goods = self.bisect_goods()?;
+
+        let bad = match bad {
+            Some(bad) if !goods.is_empty() => bad,
+            _ => return Ok(Bisect::Waiting),
+        };
+
+        let mut good_ancestors = HashSet::new();
+        for good in &goods {
+            good_ancestors.extend(self.ancestors(good)?);
+        }
+
+        // the commits which may have introduced the bug, including the bad one
+        let candidates: HashSet<String> = self
+            .ancestors(&bad)?
+            .into_iter()
+            .filter(|sha| !good_ancestors.contains(sha))
+            .collect();
+
+        if candidates.len() <= 1 {
+            return Ok(Bisect::Found(bad));
+        }
+
+        let mut sorted: Vec<_> = candidates.iter().filter(|sha| **sha != bad).collect();
+        sorted.sort();
+
+        // if the tested commit is bad, the candidates are the ones it reaches,
+        // otherwise the others are, so the best split keeps both halves balanced
+        let mut best = None;
+        for sha in sorted {
+            let reach = self.ancestors(sha)?.intersection(&candidates).count();
+            let score = reach.min(candidates.len() - reach);
+
+            if best.as_ref().is_none_or(|(_, _, best)| score > *best) {
+                best = Some((sha.clone(), reach, score));
+            }
+        }
+
+        // unwrap is safe because there are at least two candidates
+        let (sha, reach, _) = best.unwrap();
+
+        let current = self.resolve_ref("HEAD")?.context("HEAD not found")?;
+        let lock = self.lock_ref("HEAD", Some(&current))?;
+        self.switch_work_tree(&sha, false, "bisect", BISECT_HINT)?;
+        lock.commit(format!("{}\n", sha).as_bytes())
+            .context("failed to update ref HEAD")?;
+
+        Ok(Bisect::Testing {
+            sha,
+            remaining: (reach - 1).max(candidates.len() - reach - 1),
+        })
+    }
+}
@@ -0,0 +1,101 @@
+//! Comparing two trees as a patch, like `git diff <a> <b>`.
+
+use crate::diff::unified_diff;
+use crate::repository::Repository;
+use indexmap::IndexMap;
+
+/// the unchanged lines printed around changes
+const CONTEXT: usize = 3;
+
+impl Repository {
+    /// the patch from the tree-ish a to the tree-ish b, in git's format
+    ///
+    /// a path is added, deleted, or modified, in the order of the paths, a blob containing
+    /// a NUL byte is reported as binary instead of being diffed
+    pub fn diff(&self, a: &str, b: &str) -> anyhow::Result<String> {
+        self.diff_entries(&self.tree_entries(a)?, &self.tree_entries(b)?)
+    }
+
+    /// the patch between two flattened trees, path -> (mode, sha), see [Self::diff]
+    pub(super) fn diff_entries(
+        &self,
+        old: &IndexMap<String, (String, String)>,
+        new: &IndexMap<String, (String, String)>,
+    ) -> anyhow::Result<String> {
+        let mut paths: Vec<&String> = old.keys().chain(new.keys()).collect();
+        paths.sort();
+        paths.dedup();
+
+        let mut out = String::new();
+
+        for path in paths {
+            let (old, new) = (old.get(path), new.get(path));
+            if old == new {
+                continue;
+            }
+
+            out.push_str(&format!("diff --git a/{0} b/{0}\n", path));
+
+            let (old_sha, new_sha) = match (old, new) {
+                (None, Some((mode, sha))) => {
+                    out.push_str(&format!("new file mode {}\n", mode));
+                    (None, Some(sha))
+                }
+                (Some((mode, sha)), None) => {
+                    out.push_str(&format!("deleted file mode {}\n", mode));
+                    (Some(sha), None)
+                }
+                (Some((old_mode, old_sha)), Some((new_mode, new_sha))) => {
+                    if old_mode != new_mode {
+                        out.push_str(&format!("old mode {}\nnew mode {}\n", old_mode, new_mode));
+                    }
+                    if old_sha == new_sha {
+                        continue;
+                    }
+                    (Some(old_sha), Some(new_sha))
+                }
+                (None, None) => unreachable!("the path comes from one of the trees"),
+            };
+
+            let short = |sha: Option<&String>| match sha {
+                Some(sha) => sha[..7].to_string(),
+                None => "0".repeat(7),
+            };
+            out.push_str(&format!("index {}..{}", short(old_sha), short(new_sha)));
+            match (old, new) {
+                (Some((old_mode, _)), Some((new_mode, _))) if old_mode == new_mode => {
+                    out.push_str(&format!(" {}\n", old_mode))
+                }
+                _ => out.push('\n'),
+            }
+
+            let content = |sha: Option<&String>| match sha {
+                Some(sha) => Ok(self.read_object(sha)?.data),
+                None => Ok::<_, anyhow::Error>(bytes::Bytes::new()),
+            };
+            let (old_data, new_data) = (content(old_sha)?, content(new_sha)?);
+
+            let old_name = match old_sha {
+                Some(_) => format!("a/{}", path),
+                None => "/dev/null".to_string(),
+            };
+            let new_name = match new_sha {
+                Some(_) => format!("b/{}", path),
+                None => "/dev/null".to_string(),
+            };
+
+            if old_data.contains(&0) || new_data.contains(&0) {
+                out.push_str(&format!(
+                    "Binary files {} and {} differ\n",
+                    old_name, new_name
+                ));
+                continue;
+            }
+
+            out.push_str(&format!("--- {}\n+++ {}\n", old_name, new_name));
+            out.push_str(&unified_diff(&old_data, &new_data, CONTEXT));
+        }
+
+        Ok(out)
+    }
+}
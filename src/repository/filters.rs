@@ -0,0 +1,112 @@
+//! Running the clean and smudge filters of the paths, as set in `.gitattributes`.
+//!
+//! A path with `filter=<driver>` goes through the `filter.<driver>.clean` command when it
+//! is added, and through `filter.<driver>.smudge` when it is written to the work tree.
+//! A driver without the command leaves the content unchanged.
+
+use crate::attributes::{Attr, GitAttributes};
+use crate::repository::Repository;
+use anyhow::Context;
+use bytes::Bytes;
+use std::fs;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+impl Repository {
+    /// the attributes of the work tree's `.gitattributes`, overridden by `info/attributes`
+    pub fn read_attributes(&self) -> anyhow::Result<GitAttributes> {
+        let mut attributes = GitAttributes::default();
+
+        for path in [
+            self.work_tree.join(".gitattributes"),
+            self.git_dir.join("info").join("attributes"),
+        ] {
+            if path.is_file() {
+                attributes.extend(
+                    &fs::read_to_string(&path)
+                        .context(format!("failed to read {}", path.display()))?,
+                );
+            }
+        }
+
+        Ok(attributes)
+    }
+
+    /// whether a repo-relative path goes through a `clean` or `smudge` command
+    pub(super) fn has_filter(
+        &self,
+        attributes: &GitAttributes,
+        path: &str,
+        kind: &str,
+    ) -> anyhow::Result<bool> {
+        Ok(self.filter_command(attributes, path, kind)?.is_some())
+    }
+
+    /// the driver and the `clean` or `smudge` command of a repo-relative path, if any
+    fn filter_command(
+        &self,
+        attributes: &GitAttributes,
+        path: &str,
+        kind: &str,
+    ) -> anyhow::Result<Option<(String, String)>> {
+        let Some(Attr::Value(driver)) = attributes.get(path, "filter") else {
+            return Ok(None);
+        };
+
+        Ok(self
+            .read_config()?
+            .get(&format!("filter \"{}\"", driver), kind)
+            .map(|command| (driver, command)))
+    }
+
+    /// pipe the content of a repo-relative path through its `clean` or `smudge` filter
+    ///
+    /// fails if the filter exits with a non-zero status
+    pub(super) fn run_filter(
+        &self,
+        attributes: &GitAttributes,
+        path: &str,
+        kind: &str,
+        data: Bytes,
+    ) -> anyhow::Result<Bytes> {
+        let Some((driver, command)) = self.filter_command(attributes, path, kind)? else {
+            return Ok(data);
+        };
+
+        // %f is replaced by the path, as git does
+        let command = command.replace("%f", &format!("'{}'", path.replace('\'', "'\\''")));
+
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(&command)
+            .current_dir(&self.work_tree)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .context(format!("failed to run {} filter {}", kind, driver))?;
+
+        // write from another thread, the filter may fill its output before reading all input
+        let mut stdin = child.stdin.take().context("failed to open filter input")?;
+        let writer = std::thread::spawn(move || stdin.write_all(&data));
+
+        let output = child
+            .wait_with_output()
+            .context(format!("failed to run {} filter {}", kind, driver))?;
+        let written = writer.join();
+
+        anyhow::ensure!(
+            output.status.success(),
+            "{} filter {} failed on {}: {}",
+            kind,
+            driver,
+            path,
+            output.status
+        );
+
+        written
+            .map_err(|_| anyhow::anyhow!("failed to write to {} filter {}", kind, driver))?
+            .context(format!("failed to write to {} filter {}", kind, driver))?;
+
+        Ok(output.stdout.into())
+    }
+}
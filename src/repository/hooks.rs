@@ -0,0 +1,48 @@
+//! Running the hook scripts of a repository.
+//!
+//! Hooks are opt-in: a hook only runs when an executable file with its name exists
+//! in `.gitlet/hooks`, or in the directory set by `core.hooksPath`.
+
+use crate::platform::is_executable;
+use crate::repository::Repository;
+use anyhow::Context;
+use std::ffi::OsStr;
+use std::path::PathBuf;
+use std::process::Command;
+
+impl Repository {
+    /// the directory holding the hooks, `core.hooksPath` is relative to the work tree
+    fn hooks_dir(&self) -> anyhow::Result<PathBuf> {
+        Ok(match self.read_config()?.get("core", "hooksPath") {
+            Some(path) => self.work_tree.join(path),
+            None => self.git_dir.join("hooks"),
+        })
+    }
+
+    /// run a hook from the work tree, returns false if the hook does not exist
+    ///
+    /// fails if the hook exits with a non-zero status
+    pub(super) fn run_hook(&self, name: &str, args: &[&OsStr]) -> anyhow::Result<bool> {
+        let path = self.hooks_dir()?.join(name);
+
+        let executable = path
+            .metadata()
+            .map(|metadata| metadata.is_file() && is_executable(&metadata))
+            .unwrap_or(false);
+
+        if !executable {
+            return Ok(false);
+        }
+
+        let status = Command::new(&path)
+            .args(args)
+            .current_dir(&self.work_tree)
+            .env("GIT_DIR", &self.git_dir)
+            .status()
+            .context(format!("failed to run {} hook", name))?;
+
+        anyhow::ensure!(status.success(), "{} hook failed: {}", name, status);
+
+        Ok(true)
+    }
+}
@@ -0,0 +1,91 @@
+//! Free-form notes attached to objects.
+//!
+//! Like git, notes are stored in a commit history under `refs/notes/commits`,
+//! whose tree maps the sha of an annotated object to the blob holding its note.
+
+use crate::objects::commit::Commit;
+use crate::objects::{Fmt, GitObject, GitObjectTrait};
+use crate::repository::{signature_time, Repository};
+use crate::utils::NULL_SHA;
+use anyhow::Context;
+use bytes::Bytes;
+use indexmap::IndexMap;
+
+const NOTES_REF: &str = "refs/notes/commits";
+
+impl Repository {
+    /// attach a note to an object, returns the sha of the new notes commit
+    pub fn notes_add(&self, object: &str, note: &str) -> anyhow::Result<String> {
+        let sha = self
+            .find_object(object, false)?
+            .ok_or(anyhow::anyhow!("object not found: {}", object))?;
+
+        let parent = self.resolve_ref(NOTES_REF)?;
+        let mut notes = self.notes()?;
+
+        anyhow::ensure!(
+            !notes.contains_key(&sha),
+            "cannot add notes, found existing notes for object {}",
+            sha
+        );
+
+        let mut note = note.to_string();
+        if !note.ends_with('\n') {
+            note.push('\n');
+        }
+        let blob = self.write_object(&GitObject::new(Fmt::Blob, Bytes::from(note)))?;
+        notes.insert(sha, blob);
+
+        let entries = notes
+            .into_iter()
+            .map(|(path, sha)| (path, ("100644".to_string(), sha)))
+            .collect();
+        let tree = self.write_tree_from_entries(&entries)?;
+
+        let commit = Commit::new(
+            tree,
+            parent.clone(),
+            self.read_config()?.user().identity()?,
+            signature_time("GIT_AUTHOR_DATE")?,
+            signature_time("GIT_COMMITTER_DATE")?,
+            "Notes added by 'gitlet notes add'\n".to_string(),
+        );
+        let commit_sha = self.write_object(&GitObject::new(Fmt::Commit, commit.serialize()?))?;
+
+        // a note added concurrently since the notes were read fails the update
+        self.update_ref(
+            NOTES_REF,
+            &commit_sha,
+            Some(parent.as_deref().unwrap_or(NULL_SHA)),
+        )?;
+
+        Ok(commit_sha)
+    }
+
+    /// read the note attached to an object, if any
+    pub fn note(&self, object: &str) -> anyhow::Result<Option<String>> {
+        let sha = self
+            .find_object(object, false)?
+            .ok_or(anyhow::anyhow!("object not found: {}", object))?;
+
+        self.notes()?
+            .shift_remove(&sha)
+            .map(|blob| self.read_note(&blob))
+            .transpose()
+    }
+
+    /// the text of a note blob
+    pub(super) fn read_note(&self, blob: &str) -> anyhow::Result<String> {
+        let data = self.read_object(blob)?.data;
+
+        String::from_utf8(data.to_vec()).context("note is not valid utf-8")
+    }
+
+    /// the dict of annotated object sha -> note blob sha
+    pub(super) fn notes(&self) -> anyhow::Result<IndexMap<String, String>> {
+        match self.resolve_ref(NOTES_REF)? {
+            Some(commit) => self.tree_to_dict(&commit),
+            None => Ok(IndexMap::new()),
+        }
+    }
+}
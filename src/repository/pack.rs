@@ -0,0 +1,360 @@
+//! Reading objects stored in packfiles, `objects/pack/pack-<sha>.pack`.
+//!
+//! Each pack comes with a version 2 index, `pack-<sha>.idx`, mapping the sha of every
+//! object in the pack to the offset of its entry. An entry is either a whole object or a
+//! delta against a base object, named by its offset in the same pack (OFS_DELTA) or by
+//! its sha (REF_DELTA).
+
+use crate::repository::Repository;
+use anyhow::Context;
+use bytes::Bytes;
+use std::fs;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+const IDX_MAGIC: &[u8] = b"\xfftOc";
+
+const OBJ_OFS_DELTA: u8 = 6;
+const OBJ_REF_DELTA: u8 = 7;
+
+/// the packs of a repository, with their parsed index
+pub(super) type Packs = Vec<(PathBuf, PackIndex)>;
+
+impl Repository {
+    /// read the decompressed bytes of a packed object, header included like a loose one
+    ///
+    /// returns None if no pack holds the object
+    pub(super) fn read_packed_object(&self, sha: &str) -> anyhow::Result<Option<Bytes>> {
+        let raw = hex::decode(sha).context(format!("invalid object name: {}", sha))?;
+
+        let mut packs = self.pack_indexes()?;
+
+        if find_packed(&packs, &raw)?.is_none() {
+            // the object may be in a pack written since, as by `git gc`
+            self.packs.borrow_mut().take();
+            packs = self.pack_indexes()?;
+        }
+
+        let Some((pack, offset)) = find_packed(&packs, &raw)? else {
+            return Ok(None);
+        };
+
+        let (fmt, data) = self
+            .read_pack_entry(pack, offset)
+            .with_context(|| format!("failed to read {} from {}", sha, pack.display()))?;
+
+        let mut object = format!("{} {}\0", fmt, data.len()).into_bytes();
+        object.extend_from_slice(&data);
+
+        Ok(Some(Bytes::from(object)))
+    }
+
+    /// whether a pack holds the object named by a full sha
+    pub(super) fn has_packed_object(&self, sha: &str) -> anyhow::Result<bool> {
+        let raw = hex::decode(sha).context(format!("invalid object name: {}", sha))?;
+
+        let packs = self.pack_indexes()?;
+
+        Ok(find_packed(&packs, &raw)?.is_some())
+    }
+
+    /// list the shas of all packed objects starting with prefix
+    pub(super) fn packed_objects_with_prefix(&self, prefix: &str) -> anyhow::Result<Vec<String>> {
+        let mut shas = vec![];
+
+        for (_, index) in self.pack_indexes()?.iter() {
+            shas.extend(
+                (0..index.count)
+                    .map(|i| hex::encode(index.sha(i)))
+                    .filter(|sha| sha.starts_with(prefix)),
+            );
+        }
+
+        Ok(shas)
+    }
+
+    /// the packs of the repository, read on first use and cached
+    fn pack_indexes(&self) -> anyhow::Result<Rc<Packs>> {
+        if let Some(packs) = self.packs.borrow().as_ref() {
+            return Ok(packs.clone());
+        }
+
+        let dir = self.git_dir.join("objects").join("pack");
+        let mut packs = vec![];
+
+        if dir.is_dir() {
+            for entry in fs::read_dir(&dir).context("failed to read pack directory")? {
+                let path = entry.context("failed to read pack directory")?.path();
+
+                if path.extension().is_none_or(|ext| ext != "idx") {
+                    continue;
+                }
+
+                let data = fs::read(&path).context(format!("failed to read {}", path.display()))?;
+                let index = PackIndex::parse(data)
+                    .context(format!("invalid pack index {}", path.display()))?;
+
+                packs.push((path.with_extension("pack"), index));
+            }
+        }
+
+        let packs = Rc::new(packs);
+        *self.packs.borrow_mut() = Some(packs.clone());
+
+        Ok(packs)
+    }
+
+    /// read the entry at offset in a pack, resolving deltas
+    ///
+    /// returns the type name of the object and its content
+    fn read_pack_entry(&self, pack: &Path, offset: u64) -> anyhow::Result<(String, Vec<u8>)> {
+        let mut file = fs::File::open(pack).context("failed to open pack")?;
+        file.seek(SeekFrom::Start(offset))?;
+        let mut reader = std::io::BufReader::new(file);
+
+        // type in bits 4-6 of the first byte, then the size 4 + 7 * n bits at a time
+        let mut byte = read_byte(&mut reader)?;
+        let kind = (byte >> 4) & 0b111;
+        let mut size = (byte & 0b1111) as u64;
+        let mut shift = 4;
+        while byte & 0x80 != 0 {
+            anyhow::ensure!(shift < u64::BITS, "invalid pack entry size");
+            byte = read_byte(&mut reader)?;
+            size |= ((byte & 0x7f) as u64) << shift;
+            shift += 7;
+        }
+
+        let base = match kind {
+            OBJ_OFS_DELTA => {
+                // big endian, adding one for each continuation byte so every offset has
+                // a single encoding
+                let mut byte = read_byte(&mut reader)?;
+                let mut distance = (byte & 0x7f) as u64;
+                while byte & 0x80 != 0 {
+                    anyhow::ensure!(distance < 1 << 56, "invalid delta base offset");
+                    byte = read_byte(&mut reader)?;
+                    distance = ((distance + 1) << 7) | (byte & 0x7f) as u64;
+                }
+
+                let base = offset
+                    .checked_sub(distance)
+                    .context("delta base offset out of the pack")?;
+                Some(self.read_pack_entry(pack, base)?)
+            }
+            OBJ_REF_DELTA => {
+                let mut sha = [0; 20];
+                reader.read_exact(&mut sha)?;
+
+                let object = self.read_raw_object(&hex::encode(sha))?;
+                let (fmt, _, data) = crate::objects::GitObject::parse_header(&object)?;
+                Some((fmt.to_string(), data.to_vec()))
+            }
+            _ => None,
+        };
+
+        // the size comes from the pack, the data is read no further than one byte past it
+        let mut data = Vec::with_capacity(preallocated(size));
+        flate2::bufread::ZlibDecoder::new(reader)
+            .take(size.saturating_add(1))
+            .read_to_end(&mut data)
+            .context("failed to read zlib data")?;
+        anyhow::ensure!(data.len() as u64 == size, "pack entry size mismatch");
+
+        Ok(match base {
+            Some((fmt, base)) => (fmt, apply_delta(&base, &data)?),
+            None => {
+                let fmt = match kind {
+                    1 => "commit",
+                    2 => "tree",
+                    3 => "blob",
+                    4 => "tag",
+                    kind => anyhow::bail!("invalid pack entry type: {}", kind),
+                };
+                (fmt.to_string(), data)
+            }
+        })
+    }
+}
+
+/// the pack holding the object named by a 20 bytes sha, and the offset of its entry
+fn find_packed<'a>(packs: &'a Packs, sha: &[u8]) -> anyhow::Result<Option<(&'a Path, u64)>> {
+    for (pack, index) in packs {
+        if let Some(offset) = index.find(sha)? {
+            return Ok(Some((pack, offset)));
+        }
+    }
+
+    Ok(None)
+}
+
+/// a version 2 pack index
+pub(super) struct PackIndex {
+    data: Vec<u8>,
+    count: usize,
+}
+
+impl PackIndex {
+    // magic, version, then 256 cumulative counts by first byte of sha
+    const FANOUT: usize = 8;
+    const SHAS: usize = Self::FANOUT + 256 * 4;
+
+    fn parse(data: Vec<u8>) -> anyhow::Result<Self> {
+        anyhow::ensure!(
+            data.len() >= Self::SHAS && data.starts_with(IDX_MAGIC),
+            "not a version 2 pack index"
+        );
+        anyhow::ensure!(
+            read_u32(&data, 4) == 2,
+            "unsupported pack index version {}",
+            read_u32(&data, 4)
+        );
+
+        let count = read_u32(&data, Self::FANOUT + 255 * 4) as usize;
+
+        // shas, crc32s and 32 bit offsets, then the two trailing checksums at least
+        anyhow::ensure!(
+            data.len() >= Self::SHAS + count * 28 + 40,
+            "truncated pack index"
+        );
+
+        Ok(Self { data, count })
+    }
+
+    fn sha(&self, i: usize) -> &[u8] {
+        &self.data[Self::SHAS + i * 20..Self::SHAS + (i + 1) * 20]
+    }
+
+    /// the offset in the pack of the object named by a 20 bytes sha
+    fn find(&self, sha: &[u8]) -> anyhow::Result<Option<u64>> {
+        // the shas starting with the same byte are the range between two fanout entries
+        let first = sha[0] as usize;
+        let end = read_u32(&self.data, Self::FANOUT + first * 4) as usize;
+        let start = match first {
+            0 => 0,
+            _ => read_u32(&self.data, Self::FANOUT + (first - 1) * 4) as usize,
+        };
+
+        anyhow::ensure!(
+            start <= end && end <= self.count,
+            "corrupt pack index fanout"
+        );
+
+        let (mut low, mut high) = (start, end);
+        while low < high {
+            let middle = (low + high) / 2;
+            match self.sha(middle).cmp(sha) {
+                std::cmp::Ordering::Less => low = middle + 1,
+                std::cmp::Ordering::Greater => high = middle,
+                std::cmp::Ordering::Equal => return self.offset(middle).map(Some),
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn offset(&self, i: usize) -> anyhow::Result<u64> {
+        let offsets = Self::SHAS + self.count * 24;
+        let offset = read_u32(&self.data, offsets + i * 4);
+
+        // offsets past 2 GiB live in a table of 64 bit offsets, indexed by the low bits
+        if offset & 0x8000_0000 == 0 {
+            return Ok(offset as u64);
+        }
+
+        let large = offsets + self.count * 4 + (offset & 0x7fff_ffff) as usize * 8;
+        let bytes = self
+            .data
+            .get(large..large + 8)
+            .context("truncated pack index")?;
+
+        Ok(u64::from_be_bytes(bytes.try_into()?))
+    }
+}
+
+/// the capacity to reserve for an object of a size read from a pack, which may be corrupt,
+/// larger objects grow as they are read
+fn preallocated(size: u64) -> usize {
+    size.min(1 << 20) as usize
+}
+
+fn read_u32(data: &[u8], at: usize) -> u32 {
+    u32::from_be_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]])
+}
+
+fn read_byte(reader: &mut impl Read) -> anyhow::Result<u8> {
+    let mut byte = [0];
+    reader
+        .read_exact(&mut byte)
+        .context("truncated pack entry")?;
+    Ok(byte[0])
+}
+
+/// rebuild an object from its base and a delta
+///
+/// the delta starts with the sizes of the base and of the result, followed by
+/// instructions copying a range of the base or inserting new bytes
+fn apply_delta(base: &[u8], delta: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let mut delta = delta.iter().copied();
+
+    anyhow::ensure!(
+        read_delta_size(&mut delta)? == base.len(),
+        "delta base size mismatch"
+    );
+    let target_size = read_delta_size(&mut delta)?;
+
+    let mut target = Vec::with_capacity(preallocated(target_size as u64));
+
+    while let Some(op) = delta.next() {
+        if op & 0x80 != 0 {
+            // copy: bits 0-3 tell which offset bytes follow, bits 4-6 which size bytes
+            let mut offset = 0;
+            let mut length = 0;
+            for i in 0..4 {
+                if op & (1 << i) != 0 {
+                    offset |= (delta.next().context("truncated delta")? as usize) << (i * 8);
+                }
+            }
+            for i in 0..3 {
+                if op & (0x10 << i) != 0 {
+                    length |= (delta.next().context("truncated delta")? as usize) << (i * 8);
+                }
+            }
+            if length == 0 {
+                length = 0x10000;
+            }
+
+            let range = base
+                .get(offset..offset + length)
+                .context("delta copies past the end of its base")?;
+            target.extend_from_slice(range);
+        } else {
+            anyhow::ensure!(op != 0, "invalid delta instruction");
+
+            for _ in 0..op {
+                target.push(delta.next().context("truncated delta")?);
+            }
+        }
+    }
+
+    anyhow::ensure!(target.len() == target_size, "delta result size mismatch");
+
+    Ok(target)
+}
+
+/// read a size at the start of a delta, little endian 7 bits at a time
+fn read_delta_size(delta: &mut impl Iterator<Item = u8>) -> anyhow::Result<usize> {
+    let mut size = 0;
+    let mut shift = 0;
+
+    loop {
+        anyhow::ensure!(shift < usize::BITS, "invalid delta size");
+        let byte = delta.next().context("truncated delta")?;
+        size |= ((byte & 0x7f) as usize) << shift;
+        shift += 7;
+
+        if byte & 0x80 == 0 {
+            return Ok(size);
+        }
+    }
+}
@@ -0,0 +1,108 @@
+//! Reading and pruning the reflogs stored under `logs/` in the git directory.
+//!
+//! Each line of a reflog records one update of a ref:
+//! `<old sha> <new sha> <name> <<email>> <timestamp> <tz>\t<message>`.
+
+use crate::repository::Repository;
+use crate::utils::LockFile;
+use anyhow::Context;
+use std::fs;
+use std::path::Path;
+
+/// an entry of a reflog
+#[derive(Debug, PartialEq)]
+pub struct ReflogEntry {
+    pub old: String,
+    pub new: String,
+    /// `name <email>`
+    pub identity: String,
+    /// seconds since the unix epoch
+    pub timestamp: i64,
+    pub message: String,
+}
+
+impl ReflogEntry {
+    fn parse(line: &str) -> Option<Self> {
+        let (signature, message) = line.split_once('\t').unwrap_or((line, ""));
+
+        let (old, rest) = signature.split_once(' ')?;
+        let (new, rest) = rest.split_once(' ')?;
+        // the identity may contain spaces, the timestamp and timezone come last
+        let (rest, _tz) = rest.rsplit_once(' ')?;
+        let (identity, timestamp) = rest.rsplit_once(' ')?;
+
+        Some(Self {
+            old: old.to_string(),
+            new: new.to_string(),
+            identity: identity.to_string(),
+            timestamp: timestamp.parse().ok()?,
+            message: message.to_string(),
+        })
+    }
+}
+
+impl Repository {
+    /// read the reflog of a ref, oldest entry first
+    pub fn reflog(&self, name: &str) -> anyhow::Result<Vec<ReflogEntry>> {
+        let path = self.git_dir.join("logs").join(name);
+
+        if !path.is_file() {
+            return Ok(vec![]);
+        }
+
+        Ok(fs::read_to_string(&path)
+            .context(format!("failed to read reflog: {}", path.display()))?
+            .lines()
+            .filter_map(ReflogEntry::parse)
+            .collect())
+    }
+
+    /// drop the entries older than `cutoff` (a unix timestamp) from every reflog
+    ///
+    /// returns the number of dropped entries
+    pub fn reflog_expire(&self, cutoff: i64) -> anyhow::Result<usize> {
+        let logs = self.git_dir.join("logs");
+        let mut expired = 0;
+
+        if !logs.is_dir() {
+            return Ok(0);
+        }
+
+        for entry in walkdir::WalkDir::new(&logs) {
+            let entry = entry.context("failed to read reflog entry")?;
+
+            // ref names may contain dots, but never end with `.lock`, those are locks
+            let lock = entry.file_name().to_string_lossy().ends_with(".lock");
+
+            if entry.file_type().is_file() && !lock {
+                expired += expire_file(entry.path(), cutoff)?;
+            }
+        }
+
+        Ok(expired)
+    }
+}
+
+/// rewrite a reflog without its expired entries, lines which cannot be parsed are kept
+fn expire_file(path: &Path, cutoff: i64) -> anyhow::Result<usize> {
+    // locked before reading, so an entry appended meanwhile is not dropped by the rewrite
+    let lock = LockFile::acquire(path)?;
+    let data =
+        fs::read_to_string(path).context(format!("failed to read reflog: {}", path.display()))?;
+
+    let kept: Vec<_> = data
+        .lines()
+        .filter(|line| ReflogEntry::parse(line).is_none_or(|entry| entry.timestamp >= cutoff))
+        .collect();
+
+    let expired = data.lines().count() - kept.len();
+
+    if expired > 0 {
+        // the lock is renamed over the reflog, which is never left half written
+        let content: String = kept.iter().map(|line| format!("{}\n", line)).collect();
+
+        lock.commit(content.as_bytes())?;
+    }
+
+    Ok(expired)
+}
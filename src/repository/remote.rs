@@ -0,0 +1,287 @@
+//! Exchanging objects and refs with other repositories on the local filesystem.
+
+use crate::objects::commit::Commit;
+use crate::objects::tag::Tag;
+use crate::objects::tree::{FileType, Tree};
+use crate::objects::{Fmt, GitObjectTrait};
+use crate::repository::Repository;
+use crate::utils::NULL_SHA;
+use anyhow::Context;
+use indexmap::IndexMap;
+use std::collections::{HashSet, VecDeque};
+use std::fs;
+use std::path::PathBuf;
+
+/// the outcome of [Repository::push]
+#[derive(Debug, PartialEq)]
+pub enum Push {
+    /// the remote ref already points at the pushed commit
+    UpToDate,
+    /// the remote ref was created, or moved from the old sha
+    Updated {
+        old: Option<String>,
+        new: String,
+        /// the number of objects copied to the remote
+        objects: usize,
+    },
+}
+
+impl Repository {
+    /// the path of a remote, either the `url` of a configured remote or a path
+    pub fn remote_path(&self, remote: &str) -> anyhow::Result<PathBuf> {
+        let url = self
+            .read_config()?
+            .get(&format!("remote \"{}\"", remote), "url")
+            .unwrap_or(remote.to_string());
+
+        Ok(PathBuf::from(url.strip_prefix("file://").unwrap_or(&url)))
+    }
+
+    /// Push a local ref to a repository on the local filesystem.
+    ///
+    /// The refspec is `[+]<src>[:<dst>]`, it defaults to the active branch, and `dst`
+    /// defaults to `src`. Only fast-forwards are allowed, unless forced with `+` or `force`.
+    /// As git does by default, the branch checked out in the remote cannot be updated.
+    pub fn push(&self, remote: &str, refspec: Option<&str>, force: bool) -> anyhow::Result<Push> {
+        let remote = Repository::load(self.remote_path(remote)?)
+            .context(format!("failed to open remote: {}", remote))?;
+
+        let refspec = match refspec {
+            Some(refspec) => refspec.to_string(),
+            None => self.active_branch()?,
+        };
+        let (force, refspec) = match refspec.strip_prefix('+') {
+            Some(refspec) => (true, refspec),
+            None => (force, refspec.as_str()),
+        };
+        let (src, dst) = refspec.split_once(':').unwrap_or((refspec, refspec));
+
+        let new = self
+            .find_object(src, false)?
+            .ok_or(anyhow::anyhow!("src refspec does not match any: {}", src))?;
+
+        let dst = self.full_ref_name(src, dst);
+        crate::refs::check_ref_name(&dst)?;
+        let old = remote.resolve_ref(&dst)?;
+
+        if old.as_ref() == Some(&new) {
+            return Ok(Push::UpToDate);
+        }
+
+        if let Some(old) = &old {
+            let fast_forward = self.has_object(old)? && self.is_ancestor(old, &new)?;
+            anyhow::ensure!(
+                force || fast_forward,
+                "rejected {} (non-fast-forward), fetch first or use --force",
+                dst
+            );
+        }
+
+        let head = fs::read_to_string(remote.git_dir.join("HEAD"))
+            .context("failed to read remote HEAD file")?;
+        anyhow::ensure!(
+            head.trim() != format!("ref: {}", dst),
+            "refusing to update checked out branch: {}",
+            dst
+        );
+
+        let (missing, shallow) = self.missing_objects(&new, &remote, None)?;
+        remote.add_shallow(&shallow)?;
+        for (done, sha) in missing.iter().enumerate() {
+            self.progress("Writing objects", done, missing.len());
+            remote.write_raw_object(&self.read_raw_object(sha)?)?;
+        }
+        self.progress("Writing objects", missing.len(), missing.len());
+
+        // a push landing since the remote ref was read fails the update, forced or not
+        remote.update_ref(&dst, &new, Some(old.as_deref().unwrap_or(NULL_SHA)))?;
+
+        Ok(Push::Updated {
+            old,
+            new,
+            objects: missing.len(),
+        })
+    }
+
+    /// Fetch the branches of a repository on the local filesystem.
+    ///
+    /// The branches of a configured remote are stored as `refs/remotes/<remote>/<branch>`,
+    /// and every fetched branch is recorded in FETCH_HEAD. With a depth, only the last
+    /// `depth` commits of each branch are fetched, and the history is cut in `shallow`.
+    ///
+    /// returns the fetched branches and their shas
+    pub fn fetch(
+        &self,
+        remote: &str,
+        depth: Option<usize>,
+    ) -> anyhow::Result<IndexMap<String, String>> {
+        let configured = self
+            .read_config()?
+            .get(&format!("remote \"{}\"", remote), "url")
+            .is_some();
+        let path = self.remote_path(remote)?;
+        let source =
+            Repository::load(&path).context(format!("failed to open remote: {}", remote))?;
+
+        anyhow::ensure!(depth != Some(0), "depth must be positive");
+
+        let mut fetched = IndexMap::new();
+        let mut fetch_head = String::new();
+
+        for (name, sha) in source.refs()? {
+            let Some(branch) = name.strip_prefix("refs/heads/") else {
+                continue;
+            };
+
+            let (missing, shallow) = source.missing_objects(&sha, self, depth)?;
+            for (done, object) in missing.iter().enumerate() {
+                self.progress("Receiving objects", done, missing.len());
+                self.write_raw_object(&source.read_raw_object(object)?)?;
+            }
+            self.progress("Receiving objects", missing.len(), missing.len());
+            self.add_shallow(&shallow)?;
+
+            if configured {
+                let name = format!("refs/remotes/{}/{}", remote, branch);
+                let old = self.resolve_ref(&name)?;
+                self.update_ref(&name, &sha, Some(old.as_deref().unwrap_or(NULL_SHA)))?;
+            }
+
+            fetch_head.push_str(&format!(
+                "{}\t\tbranch '{}' of {}\n",
+                sha,
+                branch,
+                path.display()
+            ));
+            fetched.insert(branch.to_string(), sha);
+        }
+
+        fs::write(self.git_dir.join("FETCH_HEAD"), fetch_head)
+            .context("failed to write FETCH_HEAD file")?;
+
+        Ok(fetched)
+    }
+
+    /// the commits whose parents are missing from a shallow repository
+    pub fn shallow(&self) -> anyhow::Result<HashSet<String>> {
+        let path = self.git_dir.join("shallow");
+
+        if !path.exists() {
+            return Ok(HashSet::new());
+        }
+
+        Ok(fs::read_to_string(path)
+            .context("failed to read shallow file")?
+            .lines()
+            .map(|line| line.to_string())
+            .collect())
+    }
+
+    /// whether the parents of a commit are missing from a shallow repository
+    pub(super) fn is_shallow(&self, sha: &str) -> anyhow::Result<bool> {
+        Ok(self.shallow()?.contains(sha))
+    }
+
+    /// record new shallow boundaries
+    fn add_shallow(&self, commits: &[String]) -> anyhow::Result<()> {
+        if commits.is_empty() {
+            return Ok(());
+        }
+
+        let mut shallow: Vec<_> = self.shallow()?.into_iter().collect();
+        shallow.extend(commits.iter().cloned());
+        shallow.sort();
+        shallow.dedup();
+
+        fs::write(self.git_dir.join("shallow"), shallow.join("\n") + "\n")
+            .context("failed to write shallow file")
+    }
+
+    /// whether an object named by its full sha is stored in the repository, loose or packed
+    pub fn has_object(&self, sha: &str) -> anyhow::Result<bool> {
+        if sha.len() > 2
+            && self
+                .git_dir
+                .join("objects")
+                .join(&sha[..2])
+                .join(&sha[2..])
+                .is_file()
+        {
+            return Ok(true);
+        }
+
+        self.has_packed_object(sha)
+    }
+
+    /// the full name of the destination ref, a short name is a branch, or a tag when src is one
+    fn full_ref_name(&self, src: &str, dst: &str) -> String {
+        if dst.starts_with("refs/") {
+            dst.to_string()
+        } else if self.git_dir.join("refs/tags").join(src).is_file() {
+            format!("refs/tags/{}", dst)
+        } else {
+            format!("refs/heads/{}", dst)
+        }
+    }
+
+    /// the objects reachable from `sha` which `dest` does not have
+    ///
+    /// the walk stops at objects `dest` has, their own history is assumed present.
+    /// With a depth, only that many commits of history are walked, and the commits
+    /// whose parents are left out are returned as the new shallow boundaries.
+    fn missing_objects(
+        &self,
+        sha: &str,
+        dest: &Repository,
+        depth: Option<usize>,
+    ) -> anyhow::Result<(Vec<String>, Vec<String>)> {
+        let mut missing = vec![];
+        let mut shallow = vec![];
+        let mut seen = HashSet::new();
+        // breadth first, so the depth of a commit is its distance to the tip
+        let mut queue = VecDeque::from([(sha.to_string(), 1)]);
+
+        while let Some((sha, level)) = queue.pop_front() {
+            if !seen.insert(sha.clone()) || dest.has_object(&sha)? {
+                continue;
+            }
+
+            let object = self.read_object(&sha)?;
+
+            match object.header.fmt {
+                Fmt::Commit => {
+                    let commit = Commit::from_bytes(object.data)?;
+                    queue.extend(commit.tree().map(|tree| (tree.clone(), level)));
+
+                    let parents = commit.parents().cloned().unwrap_or_default();
+                    if depth.is_some_and(|depth| level >= depth) || self.is_shallow(&sha)? {
+                        if !parents.is_empty() {
+                            shallow.push(sha.clone());
+                        }
+                    } else {
+                        queue.extend(parents.into_iter().map(|parent| (parent, level + 1)));
+                    }
+                }
+                Fmt::Tree => {
+                    // git links point at commits of other repositories
+                    queue.extend(
+                        Tree::from_bytes(object.data)?
+                            .0
+                            .into_iter()
+                            .filter(|entry| !matches!(entry.file_type(), Ok(FileType::Commit)))
+                            .map(|entry| (entry.sha1, level)),
+                    );
+                }
+                Fmt::Tag => {
+                    let tag = Tag::from_bytes(object.data)?;
+                    queue.extend(tag.object().map(|object| (object.clone(), level)));
+                }
+                Fmt::Blob => {}
+            }
+
+            missing.push(sha);
+        }
+
+        Ok((missing, shallow))
+    }
+}
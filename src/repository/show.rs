@@ -0,0 +1,173 @@
+//! Describing objects for people, like `git show <object>` and `git log`.
+
+use crate::objects::commit::{parse_signature, Commit};
+use crate::objects::tag::Tag;
+use crate::objects::tree::Tree;
+use crate::objects::{Fmt, GitObjectTrait};
+use crate::repository::Repository;
+use anyhow::Context;
+use indexmap::IndexMap;
+
+/// how dates are printed, git's `--date=default`
+const DATE_FORMAT: &str = "%a %b %-d %H:%M:%S %Y %z";
+
+impl Repository {
+    /// describe the object named name the way `git show` does
+    ///
+    /// a commit is its metadata and message followed by the patch against its first
+    /// parent, or against the empty tree for a root commit. A tag is its metadata and
+    /// message followed by the object it points at, a tree lists its entries and a blob
+    /// is its content.
+    pub fn show(&self, name: &str) -> anyhow::Result<String> {
+        let sha = self
+            .find_object(name, false)?
+            .context(format!("object not found: {}", name))?;
+
+        self.show_object(name, &sha)
+    }
+
+    /// the first-parent history of start, the way `git log --first-parent` prints it
+    ///
+    /// each commit is its header, indented message and note, or `<short sha> <subject>` with
+    /// `oneline`, at most max_count commits are listed
+    pub fn log(
+        &self,
+        start: &str,
+        max_count: Option<usize>,
+        oneline: bool,
+    ) -> anyhow::Result<String> {
+        let mut entries = vec![];
+        let notes = self.notes()?;
+
+        for commit in self
+            .commits_from(start, true)?
+            .take(max_count.unwrap_or(usize::MAX))
+        {
+            let (sha, commit) = commit?;
+
+            entries.push(if oneline {
+                format!("{} {}\n", self.abbrev(&sha, 7)?, subject(&commit))
+            } else {
+                let note = notes
+                    .get(&sha)
+                    .map(|blob| self.read_note(blob))
+                    .transpose()?;
+                format_commit(&sha, &commit, note.as_deref())?
+            });
+        }
+
+        // full entries are separated by a blank line
+        Ok(entries.join(if oneline { "" } else { "\n" }))
+    }
+
+    fn show_object(&self, name: &str, sha: &str) -> anyhow::Result<String> {
+        let object = self.read_object(sha)?;
+
+        Ok(match object.header.fmt {
+            Fmt::Commit => self.show_commit(sha, &Commit::from_bytes(object.data)?)?,
+            Fmt::Tag => {
+                let tag = Tag::from_bytes(object.data)?;
+                let target = tag.object().context("tag has no object")?;
+
+                let mut out = format!("tag {}\n", tag.tag().context("tag has no name")?);
+                out.push_str(&show_tagger(tag.tagger().context("tag has no tagger")?)?);
+                out.push('\n');
+                if let Some(message) = tag.message() {
+                    out.push_str(message);
+                    if !message.ends_with('\n') {
+                        out.push('\n');
+                    }
+                }
+                out.push('\n');
+                out.push_str(&self.show_object(target, target)?);
+                out
+            }
+            Fmt::Tree => {
+                let tree = Tree::from_bytes(object.data)?;
+
+                let mut out = format!("tree {}\n\n", name);
+                for entry in &tree.0 {
+                    out.push_str(&entry.sort_key());
+                    out.push('\n');
+                }
+                out
+            }
+            Fmt::Blob => String::from_utf8_lossy(&object.data).into_owned(),
+        })
+    }
+
+    fn show_commit(&self, sha: &str, commit: &Commit) -> anyhow::Result<String> {
+        let mut out = format_commit(sha, commit, self.note(sha)?.as_deref())?;
+
+        let old = match commit.parents().and_then(|parents| parents.first()) {
+            Some(parent) => self.tree_entries(parent)?,
+            None => IndexMap::new(),
+        };
+        let patch = self.diff_entries(&old, &self.tree_entries(sha)?)?;
+
+        if !patch.is_empty() {
+            out.push('\n');
+            out.push_str(&patch);
+        }
+
+        Ok(out)
+    }
+}
+
+/// the header of a commit and its message, indented, then its note if any
+fn format_commit(sha: &str, commit: &Commit, note: Option<&str>) -> anyhow::Result<String> {
+    let mut out = format!("commit {}\n", sha);
+
+    let parents = commit.parents().cloned().unwrap_or_default();
+    if parents.len() > 1 {
+        let short: Vec<&str> = parents.iter().map(|parent| &parent[..7]).collect();
+        out.push_str(&format!("Merge: {}\n", short.join(" ")));
+    }
+
+    out.push_str(&format!(
+        "Author: {} <{}>\nDate:   {}\n\n",
+        commit.author_name()?,
+        commit.author_email()?,
+        commit.author_time()?.format(DATE_FORMAT)
+    ));
+
+    // git indents every line of the message, blank ones included
+    for line in commit.message().map(String::as_str).unwrap_or("").lines() {
+        out.push_str(&format!("    {}\n", line));
+    }
+
+    if let Some(note) = note {
+        out.push_str("\nNotes:\n");
+        for line in note.lines() {
+            out.push_str(&format!("    {}\n", line));
+        }
+    }
+
+    Ok(out)
+}
+
+/// the first paragraph of a commit message, on one line
+fn subject(commit: &Commit) -> String {
+    commit
+        .message()
+        .map(String::as_str)
+        .unwrap_or("")
+        .lines()
+        .map(str::trim)
+        .skip_while(|line| line.is_empty())
+        .take_while(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// the `Tagger:` and `Date:` lines of a tag, from `name <email> <timestamp> <tz>`
+fn show_tagger(tagger: &str) -> anyhow::Result<String> {
+    let (name, email, time) = parse_signature(tagger)?;
+
+    Ok(format!(
+        "Tagger: {} <{}>\nDate:   {}\n",
+        name,
+        email,
+        time.format(DATE_FORMAT)
+    ))
+}
@@ -0,0 +1,337 @@
+//! The state of each path in HEAD, the index and the work tree.
+
+use crate::objects::{Fmt, GitObject};
+use crate::platform::{is_executable, Stat};
+use crate::repository::Repository;
+use crate::utils::{repo_path, LockFile, NULL_SHA};
+use anyhow::Context;
+use indexmap::IndexMap;
+use std::fs::{self, Metadata};
+use std::path::Path;
+
+/// the mode git prints for a side missing the path
+const NULL_MODE: &str = "000000";
+
+/// a tracked path whose HEAD, index or work tree version differ, see [Repository::status]
+#[derive(Debug, PartialEq)]
+pub struct StatusEntry {
+    pub path: String,
+    /// the staged change, `.` when HEAD and the index agree, or one of `A`, `D`, `M`, `T`
+    pub staged: char,
+    /// the unstaged change, `.` when the index and the work tree agree, or one of `D`, `M`, `T`
+    pub unstaged: char,
+    /// (mode, sha) of the path in HEAD
+    pub head: Option<(String, String)>,
+    /// (mode, sha) of the path in the index
+    pub index: Option<(String, String)>,
+    /// the mode of the file in the work tree
+    pub work_tree: Option<String>,
+}
+
+impl StatusEntry {
+    /// the line of git's `--porcelain=v2` format,
+    /// `1 <XY> <sub> <mH> <mI> <mW> <hH> <hI> <path>`
+    pub fn porcelain_v2(&self) -> String {
+        let (mode_head, sha_head) = self
+            .head
+            .as_ref()
+            .map_or((NULL_MODE, NULL_SHA), |(mode, sha)| (mode, sha));
+        let (mode_index, sha_index) = self
+            .index
+            .as_ref()
+            .map_or((NULL_MODE, NULL_SHA), |(mode, sha)| (mode, sha));
+
+        format!(
+            "1 {}{} N... {} {} {} {} {} {}",
+            self.staged,
+            self.unstaged,
+            mode_head,
+            mode_index,
+            self.work_tree.as_deref().unwrap_or(NULL_MODE),
+            sha_head,
+            sha_index,
+            self.path
+        )
+    }
+}
+
+/// how a path changed between two versions, see [Status::staged] and [Status::unstaged]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChangeKind {
+    Added,
+    Modified,
+    Deleted,
+    /// the path changed between a file, a symlink and a git link
+    TypeChanged,
+}
+
+impl ChangeKind {
+    /// the kind of a status code of [StatusEntry], None for `.`
+    fn from_code(code: char) -> Option<Self> {
+        match code {
+            'A' => Some(Self::Added),
+            'M' => Some(Self::Modified),
+            'D' => Some(Self::Deleted),
+            'T' => Some(Self::TypeChanged),
+            _ => None,
+        }
+    }
+}
+
+/// a changed path
+#[derive(Debug, PartialEq)]
+pub struct Change {
+    pub path: String,
+    pub kind: ChangeKind,
+}
+
+/// the state of the repository, see [Repository::status]
+#[derive(Debug, Default, PartialEq)]
+pub struct Status {
+    /// the changed paths, sorted
+    pub entries: Vec<StatusEntry>,
+    /// the untracked paths which are not ignored, sorted
+    pub untracked: Vec<String>,
+}
+
+impl Status {
+    /// the changes from HEAD to the index, sorted by path
+    pub fn staged(&self) -> Vec<Change> {
+        self.changes(|entry| entry.staged)
+    }
+
+    /// the changes from the index to the work tree, sorted by path
+    pub fn unstaged(&self) -> Vec<Change> {
+        self.changes(|entry| entry.unstaged)
+    }
+
+    fn changes(&self, code: impl Fn(&StatusEntry) -> char) -> Vec<Change> {
+        self.entries
+            .iter()
+            .filter_map(|entry| {
+                Some(Change {
+                    path: entry.path.clone(),
+                    kind: ChangeKind::from_code(code(entry))?,
+                })
+            })
+            .collect()
+    }
+}
+
+impl Repository {
+    /// Compare HEAD, the index and the work tree path by path.
+    ///
+    /// Renames are not detected, a renamed file is a deleted and an added path.
+    pub fn status(&self) -> anyhow::Result<Status> {
+        Ok(Status {
+            entries: self.status_entries()?,
+            untracked: self.untracked_files(false)?,
+        })
+    }
+
+    /// The tracked files whose work tree version differs from the index, sorted
+    /// repo-relative paths.
+    ///
+    /// Like git, a deleted file is modified too.
+    pub fn modified_files(&self) -> anyhow::Result<Vec<String>> {
+        self.unstaged_paths(|code| code != '.')
+    }
+
+    /// The tracked files missing from the work tree, sorted repo-relative paths.
+    pub fn deleted_files(&self) -> anyhow::Result<Vec<String>> {
+        self.unstaged_paths(|code| code == 'D')
+    }
+
+    /// the paths of the entries whose unstaged code matches, once per path although an
+    /// unmerged path has an entry per stage
+    fn unstaged_paths(&self, matches: impl Fn(char) -> bool) -> anyhow::Result<Vec<String>> {
+        let mut paths: Vec<_> = self
+            .status_entries()?
+            .into_iter()
+            .filter(|entry| matches(entry.unstaged))
+            .map(|entry| entry.path)
+            .collect();
+        paths.dedup();
+
+        Ok(paths)
+    }
+
+    /// the entries of [Self::status], the changed tracked paths
+    fn status_entries(&self) -> anyhow::Result<Vec<StatusEntry>> {
+        let mut head = match self.find_object("HEAD", true)? {
+            Some(_) => self.read_tree_recursive("HEAD")?,
+            None => IndexMap::new(),
+        };
+        // taken before the index is read, a write in between shows as a different stat
+        let index_stat = file_stat(self.index_path());
+        let mut index = self.read_index()?;
+        let attributes = self.read_attributes()?;
+
+        // a file modified in the same instant the index was written may have kept its
+        // stat data, such racily clean entries have their content compared
+        let index_mtime = index_stat.map(|stat| stat.mtime);
+
+        let mut entries = vec![];
+        let mut refreshed = false;
+
+        for entry in &mut index.entries {
+            let mode = format!("{:06o}", entry.mode());
+            let head = head
+                .shift_remove(&entry.name)
+                .map(|(mode, sha)| (format!("{:0>6}", mode), sha));
+
+            // the first two digits of a mode are the kind of file: 10, 12 or 16
+            let staged = match &head {
+                None => 'A',
+                Some((head_mode, _)) if head_mode[..2] != mode[..2] => 'T',
+                Some((head_mode, sha)) if *head_mode != mode || *sha != entry.sha_hex() => 'M',
+                Some(_) => '.',
+            };
+
+            let path = self.work_tree.join(&entry.name);
+            let (unstaged, work_tree) = match path.symlink_metadata() {
+                Err(_) => ('D', None),
+                Ok(meta) if entry.is_type_changed(&meta) => ('T', Some(work_tree_mode(&meta))),
+                Ok(meta) => {
+                    let racy = index_mtime.is_none_or(|mtime| entry.mtime >= mtime);
+
+                    // a size change is a content change, matching stat data is none,
+                    // only the rest is hashed
+                    let modified = if !entry.same_size(&meta) {
+                        true
+                    } else if entry.is_unchanged(&meta) && !racy {
+                        false
+                    } else {
+                        // the blob of a symlink is its target, not what it points to, the
+                        // blob of a file is its content once cleaned, as add stores it
+                        let data = if meta.file_type().is_symlink() {
+                            fs::read_link(&path)?
+                                .into_os_string()
+                                .into_encoded_bytes()
+                                .into()
+                        } else {
+                            self.run_filter(
+                                &attributes,
+                                &entry.name,
+                                "clean",
+                                fs::read(&path)?.into(),
+                            )?
+                        };
+                        let modified = GitObject::new(Fmt::Blob, data).sha()? != entry.sha_hex();
+
+                        // the next status can trust the stat data again
+                        if !modified && entry.flag_stage == 0 {
+                            entry.refresh(&meta);
+                            refreshed = true;
+                        }
+                        modified
+                    };
+
+                    (
+                        if modified { 'M' } else { '.' },
+                        Some(work_tree_mode(&meta)),
+                    )
+                }
+            };
+
+            if staged != '.' || unstaged != '.' {
+                entries.push(StatusEntry {
+                    path: entry.name.clone(),
+                    staged,
+                    unstaged,
+                    head,
+                    index: Some((mode, entry.sha_hex())),
+                    work_tree,
+                });
+            }
+        }
+
+        // what is left in head was deleted from the index
+        for (path, (mode, sha)) in head {
+            entries.push(StatusEntry {
+                path,
+                staged: 'D',
+                unstaged: '.',
+                head: Some((format!("{:0>6}", mode), sha)),
+                index: None,
+                work_tree: None,
+            });
+        }
+
+        entries.sort_by(|a, b| a.path.cmp(&b.path));
+
+        // like git, the refresh is opportunistic: a read-only index still gets a status,
+        // and the refresh is skipped while another process holds the lock, or when the
+        // index was written since it was read
+        if refreshed {
+            if let Ok(lock) = LockFile::acquire(self.index_path()) {
+                if file_stat(self.index_path()) == index_stat {
+                    lock.commit(&index.serialize()?)?;
+                }
+            }
+        }
+
+        Ok(entries)
+    }
+
+    /// The files of the work tree missing from the index, sorted repo-relative paths.
+    ///
+    /// Ignored files are left out, unless `include_ignored` is set.
+    pub fn untracked_files(&self, include_ignored: bool) -> anyhow::Result<Vec<String>> {
+        let index = self.read_index()?;
+        let ignore = self.read_ignore()?;
+
+        // skip the repository's own metadata directory, and a git directory living
+        // alongside it
+        let metadata_dirs = [self.git_dir.clone(), self.work_tree.join(".git")];
+
+        let walker = walkdir::WalkDir::new(&self.work_tree)
+            .into_iter()
+            .filter_entry(|e| !metadata_dirs.iter().any(|dir| e.path() == dir));
+
+        let mut untracked = vec![];
+
+        for entry in walker {
+            let entry = entry.context("failed to read entry")?;
+
+            if entry.file_type().is_dir() {
+                continue;
+            }
+
+            let path = repo_path(entry.path().strip_prefix(&self.work_tree)?)?;
+
+            if index.contains(&path) || (!include_ignored && ignore.is_ignored(&path)?) {
+                continue;
+            }
+
+            untracked.push(path);
+        }
+
+        untracked.sort();
+
+        Ok(untracked)
+    }
+}
+
+/// the stat data of a file, None when it does not exist
+fn file_stat(path: &Path) -> Option<Stat> {
+    fs::metadata(path)
+        .ok()
+        .map(|metadata| Stat::from_metadata(&metadata))
+}
+
+/// the mode git records for a file of the work tree
+fn work_tree_mode(metadata: &Metadata) -> String {
+    let file_type = metadata.file_type();
+
+    if file_type.is_symlink() {
+        "120000"
+    } else if file_type.is_dir() {
+        "160000"
+    } else if is_executable(metadata) {
+        "100755"
+    } else {
+        "100644"
+    }
+    .to_string()
+}
@@ -0,0 +1,83 @@
+//! Walking the history of a commit.
+
+use crate::objects::commit::Commit;
+use crate::repository::Repository;
+use std::collections::{HashSet, VecDeque};
+
+/// an iterator over the history of a commit, see [Repository::commits_from]
+pub struct Commits<'a> {
+    repo: &'a Repository,
+    queue: VecDeque<String>,
+    /// commits already queued, so merges and cycles do not yield a commit twice
+    seen: HashSet<String>,
+    /// the commits of a shallow repository whose parents are missing
+    shallow: HashSet<String>,
+    first_parent_only: bool,
+}
+
+impl Iterator for Commits<'_> {
+    type Item = anyhow::Result<(String, Commit)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let sha = self.queue.pop_front()?;
+
+        let commit = match self.repo.read_commit(&sha) {
+            Ok(commit) => commit,
+            Err(e) => {
+                // stop the walk, the history past a broken commit is unknown
+                self.queue.clear();
+                return Some(Err(e));
+            }
+        };
+
+        let parents = commit.parents().cloned().unwrap_or_default();
+        let parents = if self.first_parent_only {
+            &parents[..parents.len().min(1)]
+        } else {
+            &parents[..]
+        };
+
+        // the history stops at the shallow boundaries
+        let parents = if self.shallow.contains(&sha) {
+            &[]
+        } else {
+            parents
+        };
+
+        for parent in parents {
+            if self.seen.insert(parent.clone()) {
+                self.queue.push_back(parent.clone());
+            }
+        }
+
+        Some(Ok((sha, commit)))
+    }
+}
+
+impl Repository {
+    /// Walk the history of `start`, breadth first, yielding each commit once with its sha.
+    ///
+    /// `start` may be any name [Self::find_object] resolves, tags are peeled.
+    /// With `first_parent_only`, only the first parent of merges is followed.
+    ///
+    /// Commits are read as the iterator advances, so taking the first n reads only those.
+    /// The order is by distance from `start`, parents in order, which is not topological:
+    /// a commit reached through a short path comes before its descendants on a longer one.
+    pub fn commits_from(
+        &self,
+        start: &str,
+        first_parent_only: bool,
+    ) -> anyhow::Result<Commits<'_>> {
+        let sha = self
+            .find_object(start, true)?
+            .ok_or(anyhow::anyhow!("object not found: {}", start))?;
+
+        Ok(Commits {
+            repo: self,
+            queue: VecDeque::from([sha.clone()]),
+            seen: HashSet::from([sha]),
+            shallow: self.shallow()?,
+            first_parent_only,
+        })
+    }
+}
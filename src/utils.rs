@@ -1,4 +1,13 @@
+use anyhow::Context;
+use chrono::{DateTime, FixedOffset};
 use sha1::Digest;
+use std::cmp::Ordering;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// the sha standing for a missing object, as git prints it
+pub const NULL_SHA: &str = "0000000000000000000000000000000000000000";
 
 pub fn sha(data: &[u8]) -> String {
     let mut hasher = sha1::Sha1::new();
@@ -7,3 +16,274 @@ pub fn sha(data: &[u8]) -> String {
 
     hex::encode(hasher.finalize())
 }
+
+/// Convert a path relative to the work tree into the form used for index entry names
+/// and tree paths: components joined by forward slashes, whatever the OS separator is.
+pub fn repo_path(path: &Path) -> anyhow::Result<String> {
+    let components = path
+        .components()
+        .map(|c| {
+            c.as_os_str()
+                .to_str()
+                .with_context(|| format!("invalid path: {}", path.display()))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    Ok(components.join("/"))
+}
+
+/// Parse a date the way git does for `GIT_AUTHOR_DATE` and `GIT_COMMITTER_DATE`.
+///
+/// Accepts git's internal format `<unix timestamp> <+hhmm>` (optionally prefixed by `@`),
+/// RFC 2822 and ISO 8601.
+pub fn parse_date(date: &str) -> anyhow::Result<DateTime<FixedOffset>> {
+    let date = date.trim();
+
+    let mut parts = date.trim_start_matches('@').split_whitespace();
+
+    if let Some(Ok(timestamp)) = parts.next().map(|t| t.parse::<i64>()) {
+        let offset = match parts.next() {
+            Some(tz) => parse_tz(tz)?,
+            None => FixedOffset::east_opt(0).context("invalid timezone")?,
+        };
+
+        anyhow::ensure!(parts.next().is_none(), "invalid date: {}", date);
+
+        return Ok(DateTime::from_timestamp(timestamp, 0)
+            .context(format!("invalid timestamp: {}", timestamp))?
+            .with_timezone(&offset));
+    }
+
+    DateTime::parse_from_rfc2822(date)
+        .or_else(|_| DateTime::parse_from_rfc3339(date))
+        .or_else(|_| DateTime::parse_from_str(date, "%Y-%m-%d %H:%M:%S %z"))
+        .context(format!("invalid date: {}", date))
+}
+
+/// Clean up a commit message the way `commit.cleanup` describes.
+///
+/// `strip` removes the lines starting with `comment_char`, then behaves like `whitespace`:
+/// trailing whitespace, leading and trailing blank lines are removed, consecutive blank
+/// lines are collapsed, and the message ends with a newline. `verbatim` keeps the message
+/// as is. Messages never come from an editor, so `default` and `scissors` act like
+/// `whitespace`, as they do in git for `-m` messages.
+pub fn cleanup_message(message: &str, mode: &str, comment_char: char) -> anyhow::Result<String> {
+    let strip = match mode {
+        "verbatim" => return Ok(message.to_string()),
+        "strip" => true,
+        "whitespace" | "default" | "scissors" => false,
+        _ => anyhow::bail!("invalid cleanup mode: {}", mode),
+    };
+
+    let mut lines: Vec<&str> = vec![];
+
+    for line in message.lines() {
+        if strip && line.starts_with(comment_char) {
+            continue;
+        }
+
+        let line = line.trim_end();
+        let blank = line.is_empty();
+
+        // no leading blank line, and a single blank line between paragraphs
+        if blank && lines.last().is_none_or(|last| last.is_empty()) {
+            continue;
+        }
+
+        lines.push(line);
+    }
+
+    if lines.last().is_some_and(|last| last.is_empty()) {
+        lines.pop();
+    }
+
+    Ok(lines.iter().map(|line| format!("{}\n", line)).collect())
+}
+
+/// Compare two names the way `--sort=version:refname` does, so `v1.9` comes before `v1.10`.
+///
+/// Runs of digits are compared by their numeric value, the rest byte by byte.
+pub fn version_cmp(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (a.as_bytes(), b.as_bytes());
+
+    loop {
+        match (a.first(), b.first()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let x_len = a.iter().take_while(|c| c.is_ascii_digit()).count();
+                let y_len = b.iter().take_while(|c| c.is_ascii_digit()).count();
+
+                // leading zeros don't change the value, compare the length then the digits
+                let x_digits = trim_zeros(&a[..x_len]);
+                let y_digits = trim_zeros(&b[..y_len]);
+                let ordering = x_digits
+                    .len()
+                    .cmp(&y_digits.len())
+                    .then_with(|| x_digits.cmp(y_digits));
+
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+
+                a = &a[x_len..];
+                b = &b[y_len..];
+            }
+            (Some(x), Some(y)) => {
+                if x != y {
+                    return x.cmp(y);
+                }
+
+                a = &a[1..];
+                b = &b[1..];
+            }
+        }
+    }
+}
+
+fn trim_zeros(digits: &[u8]) -> &[u8] {
+    let zeros = digits.iter().take_while(|c| **c == b'0').count();
+    &digits[zeros..]
+}
+
+/// parse a `+hhmm` timezone offset
+fn parse_tz(tz: &str) -> anyhow::Result<FixedOffset> {
+    anyhow::ensure!(
+        tz.len() == 5 && (tz.starts_with('+') || tz.starts_with('-')),
+        "invalid timezone: {}",
+        tz
+    );
+
+    let hours: i32 = tz[1..3].parse().context("invalid timezone")?;
+    let minutes: i32 = tz[3..5].parse().context("invalid timezone")?;
+
+    let seconds = (hours * 3600 + minutes * 60) * if tz.starts_with('-') { -1 } else { 1 };
+
+    FixedOffset::east_opt(seconds).context(format!("invalid timezone: {}", tz))
+}
+
+/// A `<path>.lock` file, written then renamed over the file it locks, like git's lock
+/// files: the file is never seen half written, and a concurrent writer fails to take
+/// the lock. The lock is removed when dropped without [LockFile::commit].
+pub struct LockFile {
+    path: PathBuf,
+    lock: PathBuf,
+    file: fs::File,
+    committed: bool,
+}
+
+impl LockFile {
+    /// create the lock of path, which must not exist yet
+    pub fn acquire(path: &Path) -> anyhow::Result<Self> {
+        let name = path.file_name().context("invalid path")?.to_string_lossy();
+        let lock = path.with_file_name(format!("{}.lock", name));
+
+        let file = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&lock)
+            .context(format!("unable to create '{}'", lock.display()))?;
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            lock,
+            file,
+            committed: false,
+        })
+    }
+
+    /// write data to the lock, then rename it over the locked file
+    pub fn commit(mut self, data: &[u8]) -> anyhow::Result<()> {
+        self.file
+            .write_all(data)
+            .context(format!("failed to write {}", self.lock.display()))?;
+        fs::rename(&self.lock, &self.path)
+            .context(format!("failed to replace {}", self.path.display()))?;
+
+        self.committed = true;
+
+        Ok(())
+    }
+}
+
+impl Drop for LockFile {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = fs::remove_file(&self.lock);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_date() {
+        let date = parse_date("1700000000 +0800").unwrap();
+        assert_eq!(date.timestamp(), 1700000000);
+        assert_eq!(date.offset().local_minus_utc(), 8 * 3600);
+
+        assert_eq!(
+            parse_date("@1700000000 -0130").unwrap().timestamp(),
+            1700000000
+        );
+        assert_eq!(
+            parse_date("Tue, 14 Nov 2023 22:13:20 +0000")
+                .unwrap()
+                .timestamp(),
+            1700000000
+        );
+        assert_eq!(
+            parse_date("2023-11-14T22:13:20Z").unwrap().timestamp(),
+            1700000000
+        );
+        assert!(parse_date("yesterday").is_err());
+    }
+
+    #[test]
+    fn test_cleanup_message() {
+        let message = "\n\nsubject  \n# a comment\n\n\n\nbody\t\n\n";
+
+        assert_eq!(
+            cleanup_message(message, "strip", '#').unwrap(),
+            "subject\n\nbody\n"
+        );
+        assert_eq!(
+            cleanup_message(message, "whitespace", '#').unwrap(),
+            "subject\n# a comment\n\nbody\n"
+        );
+        assert_eq!(
+            cleanup_message(message, "default", '#').unwrap(),
+            cleanup_message(message, "whitespace", '#').unwrap()
+        );
+        assert_eq!(cleanup_message(message, "verbatim", '#').unwrap(), message);
+
+        // the comment character is configurable
+        assert_eq!(
+            cleanup_message("subject\n; note\n# kept\n", "strip", ';').unwrap(),
+            "subject\n# kept\n"
+        );
+        assert!(cleanup_message(message, "bogus", '#').is_err());
+    }
+
+    #[test]
+    fn test_version_cmp() {
+        let mut names = vec!["v1.10", "v1.9", "v2.0", "v1.9.1", "v1.09", "v10.0", "beta"];
+        names.sort_by(|a, b| version_cmp(a, b));
+
+        assert_eq!(
+            names,
+            vec!["beta", "v1.9", "v1.09", "v1.9.1", "v1.10", "v2.0", "v10.0"]
+        );
+    }
+
+    #[test]
+    fn test_repo_path() {
+        let path = Path::new("sub").join("dir").join("file.txt");
+
+        assert_eq!(repo_path(&path).unwrap(), "sub/dir/file.txt");
+        assert_eq!(repo_path(Path::new("")).unwrap(), "");
+    }
+}